@@ -0,0 +1,12 @@
+CREATE TABLE ferrox_audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    kind TEXT NOT NULL,
+    login_name TEXT,
+    login_id UUID,
+    ip TEXT,
+    user_agent TEXT,
+    detail TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX ferrox_audit_log_created_at ON ferrox_audit_log (created_at);
+CREATE INDEX ferrox_audit_log_login ON ferrox_audit_log (login_name, login_id);
@@ -0,0 +1,7 @@
+CREATE TABLE ferrox_magic_link (
+    id UUID PRIMARY KEY,
+    email TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    used_at TIMESTAMPTZ
+);
+CREATE INDEX ferrox_magic_link_email ON ferrox_magic_link (email, created_at);
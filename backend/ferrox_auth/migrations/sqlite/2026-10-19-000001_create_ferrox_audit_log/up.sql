@@ -0,0 +1,12 @@
+CREATE TABLE ferrox_audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    login_name TEXT,
+    login_id TEXT,
+    ip TEXT,
+    user_agent TEXT,
+    detail TEXT,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX ferrox_audit_log_created_at ON ferrox_audit_log (created_at);
+CREATE INDEX ferrox_audit_log_login ON ferrox_audit_log (login_name, login_id);
@@ -0,0 +1,7 @@
+CREATE TABLE ferrox_magic_link (
+    id TEXT PRIMARY KEY,
+    email TEXT NOT NULL,
+    created_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    used_at TEXT
+);
+CREATE INDEX ferrox_magic_link_email ON ferrox_magic_link (email, created_at);
@@ -0,0 +1,221 @@
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{async_trait, Request};
+use crate::{read_request_token, validate_claim, Authenticated, Login, LoginClaim, Permission};
+
+/// Login type accepted by an [AnyLogin] guard.
+///
+/// Implemented for every [Login] implementing [Clone], which is accepted without further permissions,
+/// and for [Authenticated] to require a [Permission] for this login type only.
+pub trait LoginVariant: Send + 'static {
+    /// Type of the login.
+    type Login: Login + 'static;
+    /// Permission required for this login type.
+    type Permission: Permission;
+
+    /// Wraps the authenticated login.
+    fn from_login(login: Arc<Self::Login>) -> Self;
+}
+
+impl<T: Login + Clone + 'static> LoginVariant for T {
+    type Login = T;
+    type Permission = ();
+
+    fn from_login(login: Arc<Self::Login>) -> Self {
+        Arc::unwrap_or_clone(login)
+    }
+}
+
+impl<T: Login + 'static, P: Permission + Send + 'static> LoginVariant for Authenticated<T, P> {
+    type Login = T;
+    type Permission = P;
+
+    fn from_login(login: Arc<Self::Login>) -> Self {
+        Authenticated(login, PhantomData)
+    }
+}
+
+/// Tuple of [LoginVariant]s accepted by an [AnyLogin] guard.
+#[async_trait]
+pub trait LoginSet {
+    /// Enum with one variant per login type.
+    type Output: Send;
+
+    /// Dispatches the claim to the login type matching [LoginClaim::login_name].
+    async fn authenticate(request: &Request<'_>, claim: LoginClaim) -> Outcome<Self::Output, &'static str>;
+}
+
+macro_rules! login_sets {
+    ( $set:ident $( $variant:ident )+ ) => {
+        /// Login authenticated by an [AnyLogin] guard, one variant per login type of the tuple.
+        pub enum $set<$($variant),+> {
+            $(
+                #[allow(missing_docs)]
+                $variant($variant),
+            )+
+        }
+
+        #[async_trait]
+        impl<$($variant: LoginVariant),+> LoginSet for ($($variant),+) {
+            type Output = $set<$($variant),+>;
+
+            async fn authenticate(request: &Request<'_>, claim: LoginClaim) -> Outcome<Self::Output, &'static str> {
+                $(
+                    if claim.login_name == <$variant::Login as Login>::LOGIN_NAME {
+                        return validate_claim::<$variant::Login, $variant::Permission>(request, claim).await
+                            .map(|login| $set::$variant($variant::from_login(login)));
+                    }
+                )+
+
+                Outcome::Error((Status::Unauthorized, "Invalid login"))
+            }
+        }
+    };
+}
+
+login_sets!(OneOf2 A B);
+login_sets!(OneOf3 A B C);
+login_sets!(OneOf4 A B C D);
+login_sets!(OneOf5 A B C D E);
+
+/// Request guard for endpoints accepting multiple [Login] types.
+///
+/// Dispatches on [Login::LOGIN_NAME] of the token and yields an enum like [OneOf2].
+/// Wrap a login type in [Authenticated] to require a [Permission] for this type only.
+///
+/// ```ignore
+/// #[get("/orders")]
+/// fn orders(login: AnyLogin<(User, Authenticated<Staff, RoleSupport>)>) -> String {
+///     match login.into_inner() {
+///         OneOf2::A(user) => format!("user {}", user.get_id()),
+///         OneOf2::B(staff) => format!("staff {}", staff.get_id()),
+///     }
+/// }
+/// ```
+///
+/// This will automatically respond with [Status::Unauthorized] if conditions are not met.
+pub struct AnyLogin<L: LoginSet>(L::Output);
+
+impl<L: LoginSet> AnyLogin<L> {
+    /// Returns the authenticated login.
+    pub fn into_inner(self) -> L::Output {
+        self.0
+    }
+}
+
+impl<L: LoginSet> Deref for AnyLogin<L> {
+    type Target = L::Output;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<L: LoginSet> DerefMut for AnyLogin<L> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[async_trait]
+impl<'r, L: LoginSet> FromRequest<'r> for AnyLogin<L> {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(token) = read_request_token(request) else {
+            #[cfg(feature = "auth-from-cookie")]
+            return Outcome::Error((Status::Unauthorized, "Cookie not found"));
+            #[cfg(all(feature = "auth-from-header", not(feature = "auth-from-cookie")))]
+            return Outcome::Error((Status::Unauthorized, "Header not found"));
+        };
+
+        match LoginClaim::read_token(&token) {
+            Ok(claim) => L::authenticate(request, claim).await.map(AnyLogin),
+            Err(_) => Outcome::Error((Status::Unauthorized, "Failed to read token")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::{async_test, async_trait, get, routes, Request};
+    use uuid::Uuid;
+    use ferrox_db::PooledConnection;
+    use crate::testing::{AuthenticatedRequest, TestAuth, TestLogin, TestLogins, TestToken};
+    use crate::{define_role, AnyLogin, Authenticated, Login, OneOf2, Roles, RolesMut};
+    use crate as ferrox_auth;
+
+    define_role!(RoleSupport, "ROLE_SUPPORT");
+
+    #[derive(Clone)]
+    struct Staff {
+        id: Uuid,
+        roles: Vec<String>,
+    }
+
+    #[async_trait]
+    impl Login for Staff {
+        const LOGIN_NAME: &'static str = "staff";
+
+        fn get_id(&self) -> Uuid {
+            self.id
+        }
+
+        async fn get_roles(&self, _conn: &mut PooledConnection) -> Roles {
+            Roles(&self.roles)
+        }
+
+        async fn get_roles_mut(&mut self, _conn: &mut PooledConnection) -> RolesMut {
+            RolesMut(&mut self.roles)
+        }
+
+        async fn get_by_id(id: Uuid, _conn: &mut PooledConnection) -> Result<Option<Self>, Box<dyn Error>> {
+            Ok(TestLogins::get::<Self>(id).map(|(login, _)| login))
+        }
+
+        async fn load_authenticated(id: Uuid, _request: &Request<'_>) -> Result<Option<(Self, Vec<String>)>, String> {
+            Ok(TestLogins::get::<Self>(id))
+        }
+    }
+
+    #[get("/orders")]
+    fn orders(login: AnyLogin<(TestLogin, Authenticated<Staff, RoleSupport>)>) -> &'static str {
+        match login.into_inner() {
+            OneOf2::A(_) => "test",
+            OneOf2::B(_) => "staff",
+        }
+    }
+
+    #[async_test]
+    async fn test_any_login() {
+        TestAuth::init();
+        let client = Client::tracked(rocket::build().mount("/", routes![orders])).await.unwrap();
+
+        let user = TestLogin::default().register();
+        let response = client.get("/orders").authenticated(user.token()).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "test");
+
+        let support = Staff { id: Uuid::from_u128(rand::random()), roles: vec!["ROLE_SUPPORT".to_string()] };
+        TestLogins::insert(support.clone(), support.roles.clone());
+        let token = TestToken::new::<Staff>(support.id).with_role::<RoleSupport>();
+        let response = client.get("/orders").authenticated(token).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "staff");
+
+        let staff = Staff { id: Uuid::from_u128(rand::random()), roles: vec![] };
+        TestLogins::insert(staff.clone(), vec![]);
+        let response = client.get("/orders").authenticated(TestToken::new::<Staff>(staff.id)).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let token = user.token().with_login_name("other");
+        let response = client.get("/orders").authenticated(token).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+}
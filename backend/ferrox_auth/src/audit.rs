@@ -0,0 +1,318 @@
+use std::convert::Infallible;
+use std::io::Write;
+use std::path::PathBuf;
+
+use diesel::backend::Backend;
+use diesel::deserialize::FromSql;
+use diesel::prelude::*;
+use diesel::serialize::{Output, ToSql};
+use diesel::sql_types::Text;
+use diesel::{deserialize, serialize, AsExpression, FromSqlRow};
+use diesel_async::RunQueryDsl;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{async_trait, error, tokio, warn, Request};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+use ferrox_db::DbPool;
+use crate::AuthConfig;
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql::{SqlTimestamp, SqlUuid};
+
+    /// Table storing the [AuditEvent]s recorded by [DbAuditSink].
+    ferrox_audit_log (id) {
+        id -> BigInt,
+        kind -> Text,
+        login_name -> Nullable<Text>,
+        login_id -> Nullable<SqlUuid>,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        detail -> Nullable<Text>,
+        created_at -> SqlTimestamp,
+    }
+}
+
+/// Kind of security-relevant event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, AsExpression, FromSqlRow)]
+#[diesel(sql_type = Text)]
+pub enum AuditEventKind {
+    /// A password was verified successfully.
+    LoginSuccess,
+    /// A password verification failed.
+    LoginFailure,
+    /// A login token was created.
+    TokenIssued,
+    /// A login was logged out.
+    Logout,
+    /// A role was added through [crate::RolesMut::add_role].
+    RoleAdded,
+    /// A role was removed through [crate::RolesMut::remove_role].
+    RoleRemoved,
+    /// A password was changed.
+    PasswordChanged,
+    /// A login was denied by the [crate::Permission] of an [crate::Authenticated] guard.
+    PermissionDenied,
+}
+
+impl AuditEventKind {
+    /// Returns the name of this kind as stored in the database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEventKind::LoginSuccess => "login_success",
+            AuditEventKind::LoginFailure => "login_failure",
+            AuditEventKind::TokenIssued => "token_issued",
+            AuditEventKind::Logout => "logout",
+            AuditEventKind::RoleAdded => "role_added",
+            AuditEventKind::RoleRemoved => "role_removed",
+            AuditEventKind::PasswordChanged => "password_changed",
+            AuditEventKind::PermissionDenied => "permission_denied",
+        }
+    }
+
+    fn from_str(value: &str) -> Option<Self> {
+        Some(match value {
+            "login_success" => AuditEventKind::LoginSuccess,
+            "login_failure" => AuditEventKind::LoginFailure,
+            "token_issued" => AuditEventKind::TokenIssued,
+            "logout" => AuditEventKind::Logout,
+            "role_added" => AuditEventKind::RoleAdded,
+            "role_removed" => AuditEventKind::RoleRemoved,
+            "password_changed" => AuditEventKind::PasswordChanged,
+            "permission_denied" => AuditEventKind::PermissionDenied,
+            _ => return None,
+        })
+    }
+}
+
+impl<B: Backend> ToSql<Text, B> for AuditEventKind where str: ToSql<Text, B> {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, B>) -> serialize::Result {
+        self.as_str().to_sql(out)
+    }
+}
+
+impl<B: Backend> FromSql<Text, B> for AuditEventKind where String: FromSql<Text, B> {
+    fn from_sql(bytes: B::RawValue<'_>) -> deserialize::Result<Self> {
+        let value = <String as FromSql<Text, B>>::from_sql(bytes)?;
+        AuditEventKind::from_str(&value).ok_or_else(|| format!("Unknown audit event kind {}", value).into())
+    }
+}
+
+/// A security-relevant event.
+///
+/// Events are recorded through [AuditLog::record].
+#[derive(Clone, Debug, Serialize, Deserialize, Queryable, Selectable, Insertable)]
+#[diesel(table_name = ferrox_audit_log)]
+pub struct AuditEvent {
+    /// Kind of this event.
+    pub kind: AuditEventKind,
+    /// [crate::Login::LOGIN_NAME] of the login this event refers to.
+    pub login_name: Option<String>,
+    /// Id of the login this event refers to.
+    #[cfg_attr(not(feature = "postgres"), diesel(serialize_as = crate::sql::TextUuid, deserialize_as = crate::sql::TextUuid))]
+    pub login_id: Option<Uuid>,
+    /// IP address of the client.
+    pub ip: Option<String>,
+    /// User agent of the client.
+    pub user_agent: Option<String>,
+    /// Additional information, e.g. the name of a role.
+    pub detail: Option<String>,
+    /// Time of this event.
+    pub created_at: OffsetDateTime,
+}
+
+impl AuditEvent {
+    /// Creates a new event of `kind` happening now.
+    pub fn new(kind: AuditEventKind) -> Self {
+        AuditEvent {
+            kind,
+            login_name: None,
+            login_id: None,
+            ip: None,
+            user_agent: None,
+            detail: None,
+            created_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    /// Sets the login this event refers to.
+    pub fn with_login(mut self, login_name: &str, login_id: Uuid) -> Self {
+        self.login_name = Some(login_name.to_string());
+        self.login_id = Some(login_id);
+        self
+    }
+
+    /// Sets the client information from an [AuditContext].
+    pub fn with_context(mut self, context: &AuditContext) -> Self {
+        self.ip = context.ip.clone();
+        self.user_agent = context.user_agent.clone();
+        self
+    }
+
+    /// Sets additional information.
+    pub fn with_detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+}
+
+/// Request guard providing client information for [AuditEvent]s.
+///
+/// This guard never fails.
+#[derive(Clone, Debug, Default)]
+pub struct AuditContext {
+    /// IP address of the client.
+    pub ip: Option<String>,
+    /// User agent of the client.
+    pub user_agent: Option<String>,
+}
+
+impl AuditContext {
+    /// Reads the client information from a request.
+    pub fn read(request: &Request<'_>) -> Self {
+        AuditContext {
+            ip: request.client_ip().map(|ip| ip.to_string()),
+            user_agent: request.headers().get_one("User-Agent").map(str::to_string),
+        }
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for AuditContext {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(AuditContext::read(request))
+    }
+}
+
+/// Destination of recorded [AuditEvent]s.
+///
+/// Implement this to forward events e.g. to sentry.
+/// Sinks are added through [crate::AuthFairing::with_audit_sink].
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    /// Called once at ignition, e.g. to create storage.
+    async fn init(&self) {}
+
+    /// Records a single event.
+    async fn record(&self, event: &AuditEvent);
+}
+
+/// [AuditSink] storing events in the `ferrox_audit_log` table through [DbPool].
+///
+/// The table is created by [crate::AUTH_MIGRATIONS].
+pub struct DbAuditSink;
+
+#[async_trait]
+impl AuditSink for DbAuditSink {
+    async fn record(&self, event: &AuditEvent) {
+        let result = match DbPool::get_conn().await {
+            Ok(mut conn) => diesel::insert_into(ferrox_audit_log::table)
+                .values(event.clone())
+                .execute(&mut conn)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+
+        if let Err(e) = result {
+            error!("Failed to store audit event: {}", e);
+        }
+    }
+}
+
+/// [AuditSink] appending events as JSON lines to a file.
+pub struct FileAuditSink(pub PathBuf);
+
+#[async_trait]
+impl AuditSink for FileAuditSink {
+    async fn record(&self, event: &AuditEvent) {
+        let path = self.0.clone();
+        let mut line = serde_json::to_vec(event).unwrap();
+        line.push(b'\n');
+
+        let result = tokio::task::spawn_blocking(move || {
+            std::fs::OpenOptions::new().create(true).append(true).open(path)?.write_all(&line)
+        }).await;
+
+        if let Ok(Err(e)) = result {
+            error!("Failed to write audit event: {}", e);
+        }
+    }
+}
+
+/// Provides functions to record and prune [AuditEvent]s.
+pub struct AuditLog;
+
+impl AuditLog {
+    /// Records an event in all configured [AuditSink]s.
+    ///
+    /// Recording happens in the background, so this never blocks the caller.
+    pub fn record(event: AuditEvent) {
+        let sinks = &AuthConfig::get().audit_sinks;
+        if sinks.is_empty() {
+            return;
+        }
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    for sink in sinks {
+                        sink.record(&event).await;
+                    }
+                });
+            }
+            Err(_) => warn!("Dropped audit event {} outside of runtime", event.kind.as_str()),
+        }
+    }
+
+    /// Deletes all events from the `ferrox_audit_log` table older than `retention`.
+    ///
+    /// Returns the number of deleted events.
+    pub async fn prune(retention: Duration) -> Result<usize, String> {
+        let mut conn = DbPool::get_conn().await.map_err(|e| e.to_string())?;
+        let threshold = OffsetDateTime::now_utc() - retention;
+
+        diesel::delete(ferrox_audit_log::table.filter(ferrox_audit_log::created_at.lt(threshold)))
+            .execute(&mut conn)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+    use diesel_async::RunQueryDsl;
+    use rocket::async_test;
+    use time::Duration;
+    use uuid::Uuid;
+    use ferrox_db::DbPool;
+    use ferrox_env::EnvLoader;
+    use crate::sql::uuid_value;
+    use crate::{ferrox_audit_log, AuditEvent, AuditEventKind, AuditLog, AuditSink, DbAuditSink, AUTH_MIGRATIONS};
+
+    #[async_test]
+    async fn test_db_audit_sink() {
+        EnvLoader::load_test();
+        DbPool::run_migrations(vec![AUTH_MIGRATIONS]).await.unwrap();
+
+        let login_id = Uuid::from_u128(rand::random());
+        let mut event = AuditEvent::new(AuditEventKind::LoginFailure).with_login("test", login_id);
+        DbAuditSink.record(&event).await;
+        event.created_at -= Duration::days(10);
+        DbAuditSink.record(&event).await;
+
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        let query = ferrox_audit_log::table.filter(ferrox_audit_log::login_id.eq(uuid_value(login_id)));
+        let events = query.select(AuditEvent::as_select()).load(&mut conn).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, AuditEventKind::LoginFailure);
+
+        AuditLog::prune(Duration::days(5)).await.unwrap();
+        let events = query.select(AuditEvent::as_select()).load(&mut conn).await.unwrap();
+        assert_eq!(events.len(), 1);
+    }
+}
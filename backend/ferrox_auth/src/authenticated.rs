@@ -1,21 +1,24 @@
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome};
 use rocket::{async_trait, Request};
 use time::OffsetDateTime;
-use ferrox_db::DbPool;
-use crate::{Login, LoginClaim, Permission};
+use crate::{AuditContext, AuditEvent, AuditEventKind, AuditLog, AuthConfig, Login, LoginCache, LoginClaim, Permission, Roles, Tenant};
 
 /// Request guard for authenticated endpoints.
 ///
 /// Provides the [Login] instance and checks provided [Permission].
 ///
-/// This will automatically respond with [Status::Unauthorized] if conditions are not met.
-pub struct Authenticated<T: Login, P: Permission = ()>(T, PhantomData<P>);
+/// This will automatically respond with [Status::Unauthorized] if conditions are not met,
+/// and with [Status::Forbidden] like the [Tenant] guard if the token belongs to another tenant than the requested one.
+///
+/// All guards of a request share the same login, changing it through [DerefMut] creates a copy.
+pub struct Authenticated<T: Login, P: Permission = ()>(pub(crate) Arc<T>, pub(crate) PhantomData<fn() -> P>);
 
-impl<T: Login> Deref for Authenticated<T> {
+impl<T: Login, P: Permission> Deref for Authenticated<T, P> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -23,69 +26,148 @@ impl<T: Login> Deref for Authenticated<T> {
     }
 }
 
-impl<T: Login> DerefMut for Authenticated<T> {
+impl<T: Login + Clone, P: Permission> DerefMut for Authenticated<T, P> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        Arc::make_mut(&mut self.0)
     }
 }
 
 /// Contains the name of the authentication cookie.
 #[cfg(feature = "auth-from-cookie")]
-pub const AUTH_COOKIE_NAME: &'static str = "Authentication";
+pub const AUTH_COOKIE_NAME: &str = "Authentication";
 
 /// Contains the name of the authentication header.
 #[cfg(feature = "auth-from-header")]
-pub const AUTH_HEADER_NAME: &'static str = "Authentication";
+pub const AUTH_HEADER_NAME: &str = "Authentication";
+
+/// Reads the raw login token from the request.
+#[cfg(feature = "auth-from-cookie")]
+pub(crate) fn read_request_token(request: &Request<'_>) -> Option<String> {
+    request.cookies().get_private(AUTH_COOKIE_NAME).map(|cookie| cookie.value().to_string())
+}
+
+/// Reads the raw login token from the request.
+#[cfg(all(feature = "auth-from-header", not(feature = "auth-from-cookie")))]
+pub(crate) fn read_request_token(request: &Request<'_>) -> Option<String> {
+    request.headers().get_one(AUTH_HEADER_NAME).map(str::to_string)
+}
+
+/// Defines how the [Authenticated] guard handles tokens whose roles differ from the current roles of the login.
+///
+/// Configured through [crate::AuthFairing::with_role_change_policy].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RoleChangePolicy {
+    /// Rejects the token, which logs the login out on every device.
+    #[default]
+    Reject,
+    /// Accepts the token and re-issues it with the current roles on the response.
+    ///
+    /// The new token keeps the expiry of the old one.
+    /// With `auth-from-cookie` the cookie is replaced, with `auth-from-header` the token is sent in the response header.
+    /// Both are added by [crate::AuthFairing], so error responses receive the new token as well.
+    Reissue,
+    /// Accepts the token if roles were only added, rejects it if roles were removed.
+    TolerateAdditions,
+}
+
+/// Token re-issued by the [Authenticated] guard, sent by [crate::AuthFairing] on the response.
+pub(crate) struct ReissuedToken(pub(crate) Option<String>);
+
+fn reissue_token(request: &Request<'_>, claim: LoginClaim, roles: &[String]) {
+    let claim = LoginClaim {
+        roles: roles.to_vec(),
+        ..claim
+    };
+
+    AuditLog::record(AuditEvent::new(AuditEventKind::TokenIssued)
+        .with_login(&claim.login_name, claim.id)
+        .with_context(&AuditContext::read(request))
+        .with_detail("role change"));
+
+    let token = claim.sign();
+    request.local_cache(|| ReissuedToken(Some(token)));
+}
 
 #[async_trait]
-impl<'r, T: Login, P: Permission> FromRequest<'r> for Authenticated<T, P> {
+impl<'r, T: Login + 'static, P: Permission> FromRequest<'r> for Authenticated<T, P> {
     type Error = &'static str;
 
     #[cfg(feature = "auth-from-cookie")]
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let cookie = request.cookies().get_private(AUTH_COOKIE_NAME);
-        if cookie.is_none() {
+        let Some(token) = read_request_token(request) else {
             return Outcome::Error((Status::Unauthorized, "Cookie not found"));
-        }
+        };
 
-        handle_parse_token(cookie.unwrap().value()).await
+        handle_parse_token(request, &token).await
     }
     
     #[cfg(all(feature = "auth-from-header", not(feature = "auth-from-cookie")))]
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let headers = request.headers().get(AUTH_HEADER_NAME).collect::<Vec<&str>>();
-        if headers.is_empty() {
+        let Some(token) = read_request_token(request) else {
             return Outcome::Error((Status::Unauthorized, "Header not found"));
-        }
+        };
 
-        handle_parse_token(headers.first().unwrap()).await
+        handle_parse_token(request, &token).await
     }
 }
 
-async fn handle_parse_token<T: Login, P: Permission>(input: &str) -> rocket::outcome::Outcome<Authenticated<T, P>, (Status, &'static str), Status> {
+async fn handle_parse_token<T: Login + 'static, P: Permission>(request: &Request<'_>, input: &str) -> rocket::outcome::Outcome<Authenticated<T, P>, (Status, &'static str), Status> {
     match LoginClaim::read_token(input) {
         Ok(claim) => {
             if claim.login_name != T::LOGIN_NAME {
                 return Outcome::Error((Status::Unauthorized, "Invalid login"))
             }
 
-            if claim.valid_to > OffsetDateTime::now_utc() {
-                let mut conn = DbPool::get_conn().await.unwrap();
-                let user = T::get_by_id(claim.id, &mut conn).await.unwrap();
-                if let Some(user) = user {
-                    let roles = user.get_roles(&mut conn).await;
-                    if **roles != claim.roles || !P::is_granted(&roles) {
-                        return Outcome::Error((Status::Unauthorized, "Outdated login"))
-                    }
-
-                    Outcome::Success(Authenticated(user, PhantomData))
-                } else {
-                    Outcome::Error((Status::Unauthorized, "User not found"))
-                }
-            } else {
-                Outcome::Error((Status::Unauthorized, "Login expired"))
-            }
+            validate_claim::<T, P>(request, claim).await.map(|user| Authenticated(user, PhantomData))
         },
         Err(_) => Outcome::Error((Status::Unauthorized, "Failed to read token")),
     }
 }
+
+/// Validates a [LoginClaim] of the login `T` and loads the login if it is granted `P`.
+pub(crate) async fn validate_claim<T: Login + 'static, P: Permission>(request: &Request<'_>, claim: LoginClaim) -> rocket::outcome::Outcome<Arc<T>, (Status, &'static str), Status> {
+    if let (Some(requested), Some(claimed)) = (Tenant::requested(request), &claim.tenant) {
+        if requested != *claimed {
+            return Outcome::Error((Status::Forbidden, "Invalid tenant"))
+        }
+    }
+
+    if claim.valid_to > OffsetDateTime::now_utc() {
+        let Ok(user) = LoginCache::load::<T>(request, claim.id).await else {
+            return Outcome::Error((Status::InternalServerError, "Failed to load login"))
+        };
+
+        if let Some((user, roles)) = user {
+            let roles = Roles(&roles);
+            let outdated = **roles != claim.roles;
+            if outdated {
+                let rejected = match AuthConfig::get().role_change_policy {
+                    RoleChangePolicy::Reject => true,
+                    RoleChangePolicy::Reissue => false,
+                    RoleChangePolicy::TolerateAdditions => !claim.roles.iter().all(|role| roles.contains(role)),
+                };
+                if rejected {
+                    return Outcome::Error((Status::Unauthorized, "Outdated login"))
+                }
+            }
+
+            if !P::is_granted(&roles) {
+                AuditLog::record(AuditEvent::new(AuditEventKind::PermissionDenied)
+                    .with_login(T::LOGIN_NAME, claim.id)
+                    .with_context(&AuditContext::read(request))
+                    .with_detail(request.uri().path().as_str()));
+                return Outcome::Error((Status::Unauthorized, "Permission denied"))
+            }
+
+            if outdated && AuthConfig::get().role_change_policy == RoleChangePolicy::Reissue {
+                reissue_token(request, claim, &roles);
+            }
+
+            Outcome::Success(user)
+        } else {
+            Outcome::Error((Status::Unauthorized, "User not found"))
+        }
+    } else {
+        Outcome::Error((Status::Unauthorized, "Login expired"))
+    }
+}
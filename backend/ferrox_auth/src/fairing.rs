@@ -0,0 +1,233 @@
+use std::sync::{Mutex, OnceLock};
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{async_trait, error, info, tokio, warn, Build, Orbit, Request, Response, Rocket};
+use time::Duration;
+use crate::{AuditLog, AuditSink, MagicLinkConfig, ReissuedToken, RoleChangePolicy, TenantSource};
+
+static AUTH_CONFIG: OnceLock<AuthConfig> = OnceLock::new();
+
+/// Configuration of the authentication system provided by [AuthFairing].
+#[derive(Default)]
+pub(crate) struct AuthConfig {
+    pub(crate) tenant_sources: Vec<TenantSource>,
+    pub(crate) audit_sinks: Vec<Box<dyn AuditSink>>,
+    pub(crate) audit_retention: Option<Duration>,
+    pub(crate) magic_link: Option<MagicLinkConfig>,
+    pub(crate) role_change_policy: RoleChangePolicy,
+    pub(crate) login_cache_ttl: Option<std::time::Duration>,
+    pub(crate) login_cache_capacity: Option<usize>,
+}
+
+impl AuthConfig {
+    /// Retrieves the current [AuthConfig].
+    ///
+    /// Falls back to the default configuration if no [AuthFairing] is attached.
+    pub(crate) fn get() -> &'static Self {
+        AUTH_CONFIG.get_or_init(AuthConfig::default)
+    }
+}
+
+/// Fairing configuring the authentication system.
+///
+/// Attaching this fairing is optional, without it the defaults are used.
+#[derive(Default)]
+pub struct AuthFairing {
+    config: Mutex<Option<AuthConfig>>,
+}
+
+impl AuthFairing {
+    fn config(&mut self) -> &mut AuthConfig {
+        self.config.get_mut().unwrap().get_or_insert_with(AuthConfig::default)
+    }
+
+    /// Adds a [TenantSource] used to resolve the tenant of a request.
+    ///
+    /// Sources are checked in the order they were added.
+    pub fn with_tenant_source(mut self, source: TenantSource) -> Self {
+        self.config().tenant_sources.push(source);
+        self
+    }
+
+    /// Adds an [AuditSink] receiving all recorded [crate::AuditEvent]s.
+    pub fn with_audit_sink(mut self, sink: impl AuditSink + 'static) -> Self {
+        self.config().audit_sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Prunes stored [crate::AuditEvent]s older than `retention` once every hour.
+    ///
+    /// See [AuditLog::prune].
+    pub fn with_audit_retention(mut self, retention: Duration) -> Self {
+        self.config().audit_retention = Some(retention);
+        self
+    }
+
+    /// Enables logins through [crate::MagicLink].
+    ///
+    /// Requires the [ferrox_db::DbPool] with [crate::AUTH_MIGRATIONS] and the [ferrox_mailer::Mailer] to be configured.
+    pub fn with_magic_links(mut self, config: MagicLinkConfig) -> Self {
+        self.config().magic_link = Some(config);
+        self
+    }
+
+    /// Caches logins and their roles across requests for `ttl`, see [crate::LoginCache].
+    ///
+    /// Keep `ttl` short if roles can be changed by other instances, as their changes only take effect after it expires.
+    pub fn with_login_cache(mut self, ttl: std::time::Duration) -> Self {
+        self.config().login_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Sets the maximum number of logins held by the [crate::LoginCache].
+    ///
+    /// Defaults to [crate::DEFAULT_LOGIN_CACHE_CAPACITY].
+    pub fn with_login_cache_capacity(mut self, capacity: usize) -> Self {
+        self.config().login_cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Sets how tokens with outdated roles are handled, see [RoleChangePolicy].
+    ///
+    /// Defaults to [RoleChangePolicy::Reject].
+    pub fn with_role_change_policy(mut self, policy: RoleChangePolicy) -> Self {
+        self.config().role_change_policy = policy;
+        self
+    }
+}
+
+#[async_trait]
+impl Fairing for AuthFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "ferrox-auth",
+            kind: Kind::Ignite | Kind::Liftoff | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let config = std::mem::take(&mut *self.config.lock().unwrap()).unwrap_or_default();
+        if AUTH_CONFIG.set(config).is_err() {
+            warn!("Authentication was already configured");
+        }
+
+        for sink in &AuthConfig::get().audit_sinks {
+            sink.init().await;
+        }
+
+        Ok(rocket)
+    }
+
+    async fn on_liftoff(&self, _rocket: &Rocket<Orbit>) {
+        if let Some(retention) = AuthConfig::get().audit_retention {
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+                loop {
+                    interval.tick().await;
+                    match AuditLog::prune(retention).await {
+                        Ok(count) => info!("Pruned {} audit events", count),
+                        Err(e) => error!("Failed to prune audit events: {}", e),
+                    }
+                }
+            });
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let ReissuedToken(Some(token)) = request.local_cache(|| ReissuedToken(None)) else {
+            return;
+        };
+
+        #[cfg(feature = "auth-from-cookie")]
+        reissue_cookie(request, response, token.clone());
+        #[cfg(all(feature = "auth-from-header", not(feature = "auth-from-cookie")))]
+        response.set_raw_header(crate::AUTH_HEADER_NAME, token.clone());
+    }
+}
+
+/// Adds the re-issued token as private cookie to `response`.
+///
+/// The [rocket::http::CookieJar] of the request is already emitted when response fairings run,
+/// so the cookie is encrypted with the secret key of the [rocket::Config] like [rocket::http::CookieJar::add_private] does.
+/// Responses already setting the authentication cookie, e.g. on logout, are left untouched.
+#[cfg(feature = "auth-from-cookie")]
+fn reissue_cookie(request: &Request<'_>, response: &mut Response<'_>, token: String) {
+    use rocket::figment::Figment;
+    use rocket::http::private::cookie::{CookieJar, Key};
+    use rocket::http::SameSite;
+
+    let prefix = format!("{}=", crate::AUTH_COOKIE_NAME);
+    if response.headers().get("Set-Cookie").any(|cookie| cookie.starts_with(&prefix)) {
+        return;
+    }
+
+    let config = request.rocket().config();
+    let master = match Figment::from(config).extract_inner::<Vec<u8>>(rocket::Config::SECRET_KEY) {
+        Ok(master) if master.len() >= 64 => master,
+        _ => {
+            error!("Failed to re-issue authentication cookie: no secret key available");
+            return;
+        }
+    };
+
+    let mut cookie = crate::auth_cookie(token);
+    cookie.set_path("/");
+    if cookie.same_site().is_none() {
+        cookie.set_same_site(SameSite::Strict);
+    }
+    cookie.set_http_only(true);
+    cookie.set_expires(time::OffsetDateTime::now_utc() + Duration::weeks(1));
+    if config.tls_enabled() {
+        cookie.set_secure(true);
+    }
+
+    let mut jar = CookieJar::new();
+    jar.private_mut(&Key::from(&master)).add(cookie);
+    if let Some(cookie) = jar.delta().next() {
+        response.adjoin_header(cookie.clone());
+    }
+}
+
+#[cfg(all(test, feature = "auth-from-cookie"))]
+mod tests {
+    use rocket::http::{CookieJar, Status};
+    use rocket::local::asynchronous::Client;
+    use rocket::request::{FromRequest, Outcome};
+    use rocket::{async_test, async_trait, get, routes, Request};
+    use crate::{AuthFairing, ReissuedToken, AUTH_COOKIE_NAME};
+
+    struct Reissue;
+
+    #[async_trait]
+    impl<'r> FromRequest<'r> for Reissue {
+        type Error = ();
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            request.local_cache(|| ReissuedToken(Some("reissued".to_string())));
+            Outcome::Success(Reissue)
+        }
+    }
+
+    #[get("/reissue")]
+    fn reissue(_reissue: Reissue) -> Status {
+        Status::NotFound
+    }
+
+    #[get("/token")]
+    fn token(cookies: &CookieJar<'_>) -> Option<String> {
+        cookies.get_private(AUTH_COOKIE_NAME).map(|cookie| cookie.value().to_string())
+    }
+
+    #[async_test]
+    async fn test_reissue_cookie() {
+        let rocket = rocket::build().attach(AuthFairing::default()).mount("/", routes![reissue, token]);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let response = client.get("/reissue").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        assert!(response.cookies().get_private(AUTH_COOKIE_NAME).is_some());
+
+        let response = client.get("/token").dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "reissued");
+    }
+}
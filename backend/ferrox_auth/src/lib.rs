@@ -1,13 +1,48 @@
 //! Contains various modules implementing an authentication system.
 //!
 //! Core of this system are [Login], [Authenticated] and [Permission].
+//!
+//! Tables used by this crate are created by [AUTH_MIGRATIONS].
+//!
+//! The token is read from a cookie with the `auth-from-cookie` feature (default) or from a header with `auth-from-header`.
+
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+
+#[cfg(not(any(feature = "auth-from-cookie", feature = "auth-from-header")))]
+compile_error!("Either the auth-from-cookie or the auth-from-header feature of ferrox_auth has to be enabled");
 
 mod login;
 mod authenticated;
+mod any_login;
 mod roles;
 mod permissions;
+mod tenant;
+mod fairing;
+mod audit;
+mod magic_link;
+mod login_cache;
+mod sql;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 pub use authenticated::*;
+pub use any_login::*;
 pub use login::*;
 pub use permissions::*;
 pub use roles::*;
+pub use tenant::*;
+pub use fairing::*;
+pub use audit::*;
+pub use magic_link::*;
+pub use login_cache::*;
+
+/// Migrations creating the tables of [DbAuditSink] and [MagicLink].
+///
+/// Add them through `DatabaseFairing::with_migrations` next to the migrations of the application.
+#[cfg(feature = "postgres")]
+pub const AUTH_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/postgres");
+/// Migrations creating the tables of [DbAuditSink] and [MagicLink].
+///
+/// Add them through `DatabaseFairing::with_migrations` next to the migrations of the application.
+#[cfg(not(feature = "postgres"))]
+pub const AUTH_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/sqlite");
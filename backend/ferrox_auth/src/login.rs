@@ -5,17 +5,19 @@ use hmac::{Hmac, Mac};
 use jwt::{SignWithKey, VerifyWithKey};
 use rand::distributions::Alphanumeric;
 use rand::Rng;
+#[cfg(feature = "auth-from-cookie")]
 use rocket::http::{Cookie, SameSite};
 use rocket::serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use std::error::Error;
 use std::fs;
 use std::sync::OnceLock;
-use rocket::async_trait;
+use rocket::request::Outcome;
+use rocket::{async_trait, Request};
 use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
-use ferrox_db::PooledConnection;
-use crate::{Roles, RolesMut};
+use ferrox_db::{DbConn, DbPool, PooledConnection};
+use crate::{AuditContext, AuditEvent, AuditEventKind, AuditLog, Roles, RolesMut};
 
 static HMAC_SECRET: OnceLock<String> = OnceLock::new();
 
@@ -34,9 +36,32 @@ fn init_secret() -> String {
     }
 }
 
+/// Sets the secret used by [signing_key] if it was not initialized yet.
+#[cfg(any(test, feature = "testing"))]
+pub(crate) fn set_secret(secret: &str) -> bool {
+    HMAC_SECRET.set(secret.to_string()).is_ok() || HMAC_SECRET.get().unwrap() == secret
+}
+
+/// Returns the key used to sign all JWTs of this crate.
+pub(crate) fn signing_key() -> Hmac<Sha256> {
+    Hmac::new_from_slice(HMAC_SECRET.get_or_init(init_secret).as_bytes()).unwrap()
+}
+
+/// Constructs the authentication cookie containing `token`.
+#[cfg(feature = "auth-from-cookie")]
+pub(crate) fn auth_cookie(token: String) -> Cookie<'static> {
+    let mut cookie = Cookie::new(crate::AUTH_COOKIE_NAME, token);
+    #[cfg(debug_assertions)]
+    cookie.set_same_site(SameSite::None);
+    cookie
+}
+
 /// Trait defining a way of logging in.
 ///
 /// This is required for the [crate::Authenticated] guard to work.
+///
+/// Logins are loaded once per request and shared between the [crate::Authenticated] guards, see [crate::LoginCache].
+/// Changing a login through [crate::Authenticated] requires the login to implement [Clone].
 #[async_trait]
 pub trait Login: Send + Sync {
     /// Name of this type of login. (e.g. user)
@@ -47,27 +72,46 @@ pub trait Login: Send + Sync {
     /// Usually refers to the [Uuid] of the corresponding entity.
     fn get_id(&self) -> Uuid;
 
+    /// Returns the tenant this login belongs to.
+    ///
+    /// The tenant is stored in the [LoginClaim] and checked against the tenant of each request.
+    /// Logins without a tenant are not bound to any tenant.
+    fn get_tenant(&self) -> Option<String> {
+        None
+    }
+
     /// Creates the JWT token for this login.
+    ///
+    /// Prefer [Self::create_token_with_context] in handlers, so the client is recorded in the [AuditLog].
     async fn create_token(&self, conn: &mut PooledConnection) -> String {
-        let key: Hmac<Sha256> = Hmac::new_from_slice(HMAC_SECRET.get_or_init(init_secret).as_bytes()).unwrap();
+        self.create_token_with_context(conn, &AuditContext::default()).await
+    }
 
+    /// Creates the JWT token for this login and records the client of `context` in the [AuditLog].
+    async fn create_token_with_context(&self, conn: &mut PooledConnection, context: &AuditContext) -> String {
         let claim = LoginClaim {
             id: self.get_id(),
             login_name: Self::LOGIN_NAME.to_string(),
             valid_to: OffsetDateTime::now_utc().checked_add(Duration::days(30)).unwrap(),
             roles: self.get_roles(conn).await.0.clone(),
+            tenant: self.get_tenant(),
         };
 
-        claim.sign_with_key(&key).unwrap()
+        AuditLog::record(AuditEvent::new(AuditEventKind::TokenIssued).with_login(Self::LOGIN_NAME, self.get_id()).with_context(context));
+
+        claim.sign()
     }
 
     /// Creates a cookie based on the JWT provided by [Self::create_token].
     #[cfg(feature = "auth-from-cookie")]
     async fn create_cookie(&self, conn: &mut PooledConnection) -> Cookie<'static> {
-        let mut cookie = Cookie::new(crate::AUTH_COOKIE_NAME, self.create_token(conn).await);
-        #[cfg(debug_assertions)]
-        cookie.set_same_site(SameSite::None);
-        cookie
+        auth_cookie(self.create_token(conn).await)
+    }
+
+    /// Creates a cookie based on the JWT provided by [Self::create_token_with_context].
+    #[cfg(feature = "auth-from-cookie")]
+    async fn create_cookie_with_context(&self, conn: &mut PooledConnection, context: &AuditContext) -> Cookie<'static> {
+        auth_cookie(self.create_token_with_context(conn, context).await)
     }
 
     /// Constructs the logout cookie.
@@ -76,6 +120,13 @@ pub trait Login: Send + Sync {
         Cookie::build(crate::AUTH_COOKIE_NAME).same_site(SameSite::None).build()
     }
 
+    /// Records the logout of this login and constructs the logout cookie.
+    #[cfg(feature = "auth-from-cookie")]
+    fn logout(&self, context: &AuditContext) -> Cookie<'static> {
+        AuditLog::record(AuditEvent::new(AuditEventKind::Logout).with_login(Self::LOGIN_NAME, self.get_id()).with_context(context));
+        Self::logout_cookie()
+    }
+
     /// Retrieves a [Roles] struct from self for checking permissions.
     async fn get_roles(&self, conn: &mut PooledConnection) -> Roles;
 
@@ -87,6 +138,35 @@ pub trait Login: Send + Sync {
     /// Usually happens through a query from the database.
     async fn get_by_id(id: Uuid, conn: &mut PooledConnection) -> Result<Option<Self>, Box<dyn Error>> where Self: Sized;
 
+    /// Loads the login and its roles for the [crate::Authenticated] guard.
+    ///
+    /// Results are cached per request and, if enabled, in the [crate::LoginCache].
+    /// Defaults to [Self::get_by_id] and [Self::get_roles] with the [DbConn] of the request.
+    /// If a guard declared earlier in the handler holds the [DbConn], a separate connection from the [DbPool] is used.
+    async fn load_authenticated(id: Uuid, request: &Request<'_>) -> Result<Option<(Self, Vec<String>)>, String> where Self: Sized + 'static {
+        let mut request_conn;
+        let mut pool_conn;
+        let conn: &mut PooledConnection = match request.guard::<DbConn>().await {
+            Outcome::Success(conn) => {
+                request_conn = conn;
+                &mut request_conn
+            }
+            _ => {
+                pool_conn = DbPool::get_conn().await.map_err(|e| e.to_string())?;
+                &mut pool_conn
+            }
+        };
+
+        let login = Self::get_by_id(id, conn).await.map_err(|e| e.to_string())?;
+        match login {
+            Some(login) => {
+                let roles = login.get_roles(conn).await.0.clone();
+                Ok(Some((login, roles)))
+            }
+            None => Ok(None),
+        }
+    }
+
     /// Hashes a new password using this login.
     fn hash_pw(raw_pw: &[u8]) -> Result<String, argon2::password_hash::Error> {
         let salt = SaltString::generate(&mut OsRng);
@@ -100,6 +180,23 @@ pub trait Login: Send + Sync {
         let hash = PasswordHash::new(pw_hash)?;
         argon.verify_password(raw_pw, &hash)
     }
+
+    /// Verifies the password of this login and records the result in the [AuditLog].
+    fn check_password(&self, raw_pw: &[u8], pw_hash: &str, context: &AuditContext) -> Result<(), argon2::password_hash::Error> {
+        let result = Self::verify_password(raw_pw, pw_hash);
+        let kind = if result.is_ok() { AuditEventKind::LoginSuccess } else { AuditEventKind::LoginFailure };
+        AuditLog::record(AuditEvent::new(kind).with_login(Self::LOGIN_NAME, self.get_id()).with_context(context));
+
+        result
+    }
+
+    /// Hashes a new password for this login and records the change in the [AuditLog].
+    fn change_password(&self, raw_pw: &[u8], context: &AuditContext) -> Result<String, argon2::password_hash::Error> {
+        let hash = Self::hash_pw(raw_pw)?;
+        AuditLog::record(AuditEvent::new(AuditEventKind::PasswordChanged).with_login(Self::LOGIN_NAME, self.get_id()).with_context(context));
+
+        Ok(hash)
+    }
 }
 
 /// Claim of the JWT token of a login.
@@ -113,17 +210,21 @@ pub struct LoginClaim {
     pub valid_to: OffsetDateTime,
     /// Roles of the login.
     ///
-    /// This causes a logout when roles are changing.
+    /// Changed roles are handled according to the [crate::RoleChangePolicy].
     pub roles: Vec<String>,
+    /// Tenant of the login. See [Login::get_tenant].
+    #[serde(default)]
+    pub tenant: Option<String>,
 }
 
 impl LoginClaim {
     /// Reads the [LoginClaim] from the JWT string.
     pub(crate) fn read_token(token: &str) -> Result<LoginClaim, String> {
-        let key: Hmac<Sha256> = Hmac::new_from_slice(HMAC_SECRET.get_or_init(init_secret).as_bytes()).unwrap();
-
-        let claims: LoginClaim = token.verify_with_key(&key).unwrap();
+        token.verify_with_key(&signing_key()).map_err(|e| e.to_string())
+    }
 
-        Ok(claims)
+    /// Signs this claim into a JWT string.
+    pub(crate) fn sign(&self) -> String {
+        self.sign_with_key(&signing_key()).unwrap()
     }
 }
\ No newline at end of file
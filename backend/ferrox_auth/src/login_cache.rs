@@ -0,0 +1,212 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use rocket::Request;
+use uuid::Uuid;
+use crate::{AuthConfig, Login};
+
+type LoginKey = (&'static str, Uuid);
+
+/// Default of [crate::AuthFairing::with_login_cache_capacity].
+pub const DEFAULT_LOGIN_CACHE_CAPACITY: usize = 10_000;
+
+static LOGIN_CACHE: OnceLock<Mutex<CachedLogins>> = OnceLock::new();
+
+/// Logins cached across requests, evicting expired entries and the oldest entry once `capacity` is reached.
+#[derive(Default)]
+struct CachedLogins(HashMap<LoginKey, (Instant, Box<dyn Any + Send + Sync>)>);
+
+impl CachedLogins {
+    fn get<T: Clone + 'static>(&mut self, key: &LoginKey, ttl: Duration) -> Option<T> {
+        match self.0.get(key) {
+            Some((loaded_at, _)) if loaded_at.elapsed() >= ttl => {
+                self.0.remove(key);
+                None
+            }
+            Some((_, login)) => login.downcast_ref::<T>().cloned(),
+            None => None,
+        }
+    }
+
+    fn insert<T: Send + Sync + 'static>(&mut self, key: LoginKey, login: T, ttl: Duration, capacity: usize) {
+        if self.0.len() >= capacity && !self.0.contains_key(&key) {
+            self.0.retain(|_, (loaded_at, _)| loaded_at.elapsed() < ttl);
+        }
+
+        if self.0.len() >= capacity && !self.0.contains_key(&key) {
+            let oldest = self.0.iter().min_by_key(|(_, (loaded_at, _))| *loaded_at).map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.0.remove(&oldest);
+            }
+        }
+
+        if capacity > 0 {
+            self.0.insert(key, (Instant::now(), Box::new(login)));
+        }
+    }
+}
+
+/// Logins resolved during the current request.
+#[derive(Default)]
+struct RequestLogins(Mutex<HashMap<LoginKey, Box<dyn Any + Send + Sync>>>);
+
+/// Cross-request cache of logins and their roles used by [crate::Authenticated] and [crate::AnyLogin].
+///
+/// Enabled through [crate::AuthFairing::with_login_cache].
+/// Holds at most [DEFAULT_LOGIN_CACHE_CAPACITY] logins unless configured through [crate::AuthFairing::with_login_cache_capacity].
+/// Entries are invalidated when role changes made through [crate::AuditedRolesMut] are [crate::RoleChanges::persisted].
+/// Call [Self::invalidate] when logins or roles are changed in any other way.
+pub struct LoginCache;
+
+impl LoginCache {
+    fn logins() -> &'static Mutex<CachedLogins> {
+        LOGIN_CACHE.get_or_init(Default::default)
+    }
+
+    /// Removes the login `T` with `id` from the cache.
+    pub fn invalidate<T: Login>(id: Uuid) {
+        Self::invalidate_login(T::LOGIN_NAME, id);
+    }
+
+    /// Removes all logins from the cache.
+    pub fn invalidate_all() {
+        Self::logins().lock().unwrap().0.clear();
+    }
+
+    pub(crate) fn invalidate_login(login_name: &'static str, id: Uuid) {
+        if let Some(logins) = LOGIN_CACHE.get() {
+            logins.lock().unwrap().0.remove(&(login_name, id));
+        }
+    }
+
+    /// Loads the login `T` with its roles, cached per request and, if enabled, across requests.
+    ///
+    /// The login is shared through an [Arc], so logins do not need to implement [Clone].
+    pub(crate) async fn load<T: Login + 'static>(request: &Request<'_>, id: Uuid) -> Result<Option<(Arc<T>, Vec<String>)>, String> {
+        let key = (T::LOGIN_NAME, id);
+        let local = request.local_cache(RequestLogins::default);
+        if let Some(login) = local.0.lock().unwrap().get(&key).and_then(|login| login.downcast_ref::<(Arc<T>, Vec<String>)>()) {
+            return Ok(Some(login.clone()));
+        }
+
+        let config = AuthConfig::get();
+        let ttl = config.login_cache_ttl;
+        let cached = ttl.and_then(|ttl| Self::logins().lock().unwrap().get::<(Arc<T>, Vec<String>)>(&key, ttl));
+
+        let login = match cached {
+            Some(login) => Some(login),
+            None => {
+                let login = T::load_authenticated(id, request).await?.map(|(login, roles)| (Arc::new(login), roles));
+                if let (Some(login), Some(ttl)) = (&login, ttl) {
+                    let capacity = config.login_cache_capacity.unwrap_or(DEFAULT_LOGIN_CACHE_CAPACITY);
+                    Self::logins().lock().unwrap().insert(key, login.clone(), ttl, capacity);
+                }
+                login
+            }
+        };
+
+        if let Some(login) = &login {
+            local.0.lock().unwrap().insert(key, Box::new(login.clone()));
+        }
+
+        Ok(login)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::{async_test, async_trait, get, routes, Request};
+    use uuid::Uuid;
+    use ferrox_db::PooledConnection;
+    use crate::login_cache::{CachedLogins, LoginCache};
+    use crate::testing::{AuthenticatedRequest, TestAuth, TestToken};
+    use crate::{AuditContext, AuditedRolesMut, Authenticated, Login, Roles, RolesMut, RoleUser};
+
+    static LOADS: AtomicUsize = AtomicUsize::new(0);
+
+    struct CountedLogin(Uuid, Vec<String>);
+
+    #[async_trait]
+    impl Login for CountedLogin {
+        const LOGIN_NAME: &'static str = "counted";
+
+        fn get_id(&self) -> Uuid {
+            self.0
+        }
+
+        async fn get_roles(&self, _conn: &mut PooledConnection) -> Roles {
+            Roles(&self.1)
+        }
+
+        async fn get_roles_mut(&mut self, _conn: &mut PooledConnection) -> RolesMut {
+            RolesMut(&mut self.1)
+        }
+
+        async fn get_by_id(id: Uuid, _conn: &mut PooledConnection) -> Result<Option<Self>, Box<dyn Error>> {
+            Ok(Some(CountedLogin(id, vec![])))
+        }
+
+        async fn load_authenticated(id: Uuid, _request: &Request<'_>) -> Result<Option<(Self, Vec<String>)>, String> {
+            LOADS.fetch_add(1, Ordering::SeqCst);
+            Ok(Some((CountedLogin(id, vec![]), vec!["ROLE_USER".to_string()])))
+        }
+    }
+
+    #[get("/")]
+    fn index(_login: Authenticated<CountedLogin>, _user: Authenticated<CountedLogin, RoleUser>) {}
+
+    #[async_test]
+    async fn test_request_cache() {
+        TestAuth::init();
+        let client = Client::tracked(rocket::build().mount("/", routes![index])).await.unwrap();
+
+        let token = TestToken::new::<CountedLogin>(Uuid::from_u128(rand::random())).with_role::<RoleUser>();
+        let response = client.get("/").authenticated(token).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(LOADS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let (a, b, c) = (("test", Uuid::from_u128(1)), ("test", Uuid::from_u128(2)), ("test", Uuid::from_u128(3)));
+        let mut logins = CachedLogins::default();
+        logins.insert(a, 1, Duration::from_secs(60), 2);
+        logins.insert(b, 2, Duration::from_secs(60), 2);
+        logins.insert(c, 3, Duration::from_secs(60), 2);
+        assert_eq!(logins.get::<i32>(&a, Duration::from_secs(60)), None);
+        assert_eq!(logins.get::<i32>(&b, Duration::from_secs(60)), Some(2));
+        assert_eq!(logins.get::<i32>(&c, Duration::from_secs(60)), Some(3));
+
+        std::thread::sleep(Duration::from_millis(20));
+        logins.insert(a, 1, Duration::from_millis(10), 2);
+        assert_eq!(logins.0.len(), 1);
+        assert_eq!(logins.get::<i32>(&a, Duration::from_millis(10)), Some(1));
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(logins.get::<i32>(&a, Duration::from_millis(10)), None);
+    }
+
+    #[test]
+    fn test_cache_invalidation() {
+        let id = Uuid::from_u128(rand::random());
+        let key = (CountedLogin::LOGIN_NAME, id);
+        LoginCache::logins().lock().unwrap().insert(key, (Arc::new(CountedLogin(id, vec![])), Vec::<String>::new()), Duration::from_secs(60), 10);
+
+        let mut login = CountedLogin(id, vec![]);
+        let mut roles = AuditedRolesMut::new(RolesMut(&mut login.1), CountedLogin::LOGIN_NAME, id, &AuditContext::default());
+        roles.add_role::<RoleUser>();
+        let changes = roles.finish();
+        assert!(LoginCache::logins().lock().unwrap().0.contains_key(&key));
+
+        changes.persisted();
+        assert!(!LoginCache::logins().lock().unwrap().0.contains_key(&key));
+    }
+}
@@ -0,0 +1,448 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+use diesel::dsl::count_star;
+use diesel::prelude::*;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use ferrox_mailer::lettre::message::header::ContentType;
+use ferrox_mailer::lettre::{Message, Transport};
+use ferrox_mailer::Mailer;
+use jwt::{SignWithKey, VerifyWithKey};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::http::{Cookie, CookieJar};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{async_trait, tokio};
+use sha2::{Digest, Sha256};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+use ferrox_db::PooledConnection;
+use crate::sql::uuid_value;
+use crate::{signing_key, AuditContext, AuditEvent, AuditEventKind, AuditLog, AuthConfig, Login};
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use crate::sql::{SqlTimestamp, SqlUuid};
+
+    /// Table tracking sent magic links for replay protection and rate limits.
+    ferrox_magic_link (id) {
+        id -> SqlUuid,
+        email -> Text,
+        created_at -> SqlTimestamp,
+        used_at -> Nullable<SqlTimestamp>,
+    }
+}
+
+/// Name of the private cookie binding a magic link to the requesting device.
+pub const MAGIC_LINK_DEVICE_COOKIE_NAME: &str = "MagicLinkDevice";
+
+/// [Login] which can log in through a [MagicLink].
+#[async_trait]
+pub trait MagicLinkLogin: Login {
+    /// Provide a way to retrieve the login by its email address.
+    ///
+    /// Usually happens through a query from the database.
+    async fn get_by_email(email: &str, conn: &mut PooledConnection) -> Result<Option<Self>, Box<dyn Error>> where Self: Sized;
+}
+
+/// Configuration of magic links.
+///
+/// Enable magic links through [crate::AuthFairing::with_magic_links].
+/// The `ferrox_magic_link` table is created by [crate::AUTH_MIGRATIONS].
+pub struct MagicLinkConfig {
+    sender: String,
+    subject: String,
+    body: Box<dyn Fn(&str) -> String + Send + Sync>,
+    ttl: Duration,
+    max_per_window: i64,
+    window: Duration,
+    same_device: bool,
+}
+
+impl MagicLinkConfig {
+    /// Creates a new configuration sending mails from `sender`.
+    ///
+    /// By default links are valid for 15 minutes and at most 5 links per hour are sent to an address.
+    pub fn new(sender: &str) -> Self {
+        MagicLinkConfig {
+            sender: sender.to_string(),
+            subject: "Your login link".to_string(),
+            body: Box::new(|link| format!("Use the following link to log in:\n\n{}\n\nThe link can only be used once.", link)),
+            ttl: Duration::minutes(15),
+            max_per_window: 5,
+            window: Duration::hours(1),
+            same_device: false,
+        }
+    }
+
+    /// Sets the subject of the mail.
+    pub fn with_subject(mut self, subject: &str) -> Self {
+        self.subject = subject.to_string();
+        self
+    }
+
+    /// Sets the plain text body of the mail, built from the link.
+    pub fn with_body(mut self, body: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.body = Box::new(body);
+        self
+    }
+
+    /// Sets how long a link is valid.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Limits the amount of links sent to one address within `window`.
+    pub fn with_rate_limit(mut self, max: i64, window: Duration) -> Self {
+        self.max_per_window = max;
+        self.window = window;
+        self
+    }
+
+    /// Requires links to be followed on the device which requested them.
+    ///
+    /// The device is identified through the private [MAGIC_LINK_DEVICE_COOKIE_NAME] cookie.
+    pub fn with_same_device(mut self) -> Self {
+        self.same_device = true;
+        self
+    }
+}
+
+/// Errors while sending or verifying a [MagicLink].
+#[derive(Debug)]
+pub enum MagicLinkError {
+    /// Magic links are not configured.
+    NotConfigured,
+    /// The email address is invalid.
+    InvalidEmail,
+    /// Too many links were sent to this address.
+    RateLimited,
+    /// The token is invalid or was tampered with.
+    InvalidToken,
+    /// The link is expired.
+    Expired,
+    /// The link was already used.
+    AlreadyUsed,
+    /// The link was opened on another device.
+    DeviceMismatch,
+    /// The login of the link does not exist anymore.
+    LoginNotFound,
+    /// Sending the mail failed.
+    Mail(String),
+    /// A database operation failed.
+    Database(String),
+}
+
+impl Display for MagicLinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MagicLinkError::NotConfigured => write!(f, "Magic links are not configured"),
+            MagicLinkError::InvalidEmail => write!(f, "Invalid email address"),
+            MagicLinkError::RateLimited => write!(f, "Too many login links requested"),
+            MagicLinkError::InvalidToken => write!(f, "Invalid login link"),
+            MagicLinkError::Expired => write!(f, "Login link expired"),
+            MagicLinkError::AlreadyUsed => write!(f, "Login link already used"),
+            MagicLinkError::DeviceMismatch => write!(f, "Login link was requested on another device"),
+            MagicLinkError::LoginNotFound => write!(f, "User not found"),
+            MagicLinkError::Mail(e) => write!(f, "Failed to send login link: {}", e),
+            MagicLinkError::Database(e) => write!(f, "Database error: {}", e),
+        }
+    }
+}
+
+impl Error for MagicLinkError {}
+
+impl From<diesel::result::Error> for MagicLinkError {
+    fn from(e: diesel::result::Error) -> Self {
+        MagicLinkError::Database(e.to_string())
+    }
+}
+
+/// Claim of the JWT token of a magic link.
+#[derive(Serialize, Deserialize)]
+struct MagicLinkClaim {
+    nonce: Uuid,
+    id: Uuid,
+    login_name: String,
+    email: String,
+    valid_to: OffsetDateTime,
+    device: Option<String>,
+}
+
+/// Provides passwordless logins through single-use links sent by [Mailer].
+///
+/// A successful [MagicLink::verify] returns the login, which then issues the usual
+/// token through [Login::create_token] or cookie through [Login::create_cookie].
+pub struct MagicLink;
+
+impl MagicLink {
+    /// Sends a magic link to `email` if a login with this address exists.
+    ///
+    /// `link` builds the URL of the mail from the token, e.g. `|token| format!("https://example.com/login?token={token}")`.
+    ///
+    /// Unknown addresses are not reported, so this can not be used to enumerate logins.
+    /// The address is trimmed and lowercased before it is rate limited or passed to [MagicLinkLogin::get_by_email].
+    pub async fn send<T: MagicLinkLogin>(
+        email: &str,
+        link: impl Fn(&str) -> String + Send,
+        cookies: &CookieJar<'_>,
+        conn: &mut PooledConnection,
+    ) -> Result<(), MagicLinkError> {
+        let config = AuthConfig::get().magic_link.as_ref().ok_or(MagicLinkError::NotConfigured)?;
+        Self::send_with_config::<T>(config, email, link, cookies, conn).await
+    }
+
+    async fn send_with_config<T: MagicLinkLogin>(
+        config: &MagicLinkConfig,
+        email: &str,
+        link: impl Fn(&str) -> String + Send,
+        cookies: &CookieJar<'_>,
+        conn: &mut PooledConnection,
+    ) -> Result<(), MagicLinkError> {
+        let email = normalize_email(email);
+        let email = email.as_str();
+        let now = OffsetDateTime::now_utc();
+        let nonce = reserve_link(config, email, now, conn).await?;
+
+        let login = T::get_by_email(email, conn).await.map_err(|e| MagicLinkError::Database(e.to_string()))?;
+        let Some(login) = login else {
+            return Ok(());
+        };
+
+        let device = if config.same_device {
+            let secret = rand::thread_rng()
+                .sample_iter(Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect::<String>();
+            let hash = hash_device(&secret);
+            cookies.add_private(Cookie::build((MAGIC_LINK_DEVICE_COOKIE_NAME, secret)).max_age(config.ttl));
+            Some(hash)
+        } else {
+            None
+        };
+
+        let claim = MagicLinkClaim {
+            nonce,
+            id: login.get_id(),
+            login_name: T::LOGIN_NAME.to_string(),
+            email: email.to_string(),
+            valid_to: now + config.ttl,
+            device,
+        };
+        let token = claim.sign_with_key(&signing_key()).unwrap();
+
+        let message = Message::builder()
+            .from(config.sender.parse().map_err(|_| MagicLinkError::Mail("Invalid sender".to_string()))?)
+            .to(email.parse().map_err(|_| MagicLinkError::InvalidEmail)?)
+            .subject(&config.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body((config.body)(&link(&token)))
+            .map_err(|e| MagicLinkError::Mail(e.to_string()))?;
+
+        tokio::task::spawn_blocking(move || Mailer::get().send(&message))
+            .await
+            .map_err(|e| MagicLinkError::Mail(e.to_string()))?
+            .map_err(|e| MagicLinkError::Mail(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Verifies a magic link token and returns the corresponding login.
+    ///
+    /// Each token can only be verified once.
+    /// `context` is the client following the link and is recorded in the [AuditLog].
+    pub async fn verify<T: MagicLinkLogin>(
+        token: &str,
+        cookies: &CookieJar<'_>,
+        context: &AuditContext,
+        conn: &mut PooledConnection,
+    ) -> Result<T, MagicLinkError> {
+        let result = Self::verify_claim::<T>(token, cookies, conn).await;
+
+        match &result {
+            Ok(login) => {
+                cookies.remove_private(MAGIC_LINK_DEVICE_COOKIE_NAME);
+                AuditLog::record(AuditEvent::new(AuditEventKind::LoginSuccess)
+                    .with_login(T::LOGIN_NAME, login.get_id())
+                    .with_context(context)
+                    .with_detail("magic-link"));
+            }
+            Err(e) => {
+                AuditLog::record(AuditEvent::new(AuditEventKind::LoginFailure)
+                    .with_context(context)
+                    .with_detail(&format!("magic-link: {}", e)));
+            }
+        }
+
+        result
+    }
+
+    async fn verify_claim<T: MagicLinkLogin>(
+        token: &str,
+        cookies: &CookieJar<'_>,
+        conn: &mut PooledConnection,
+    ) -> Result<T, MagicLinkError> {
+        let claim: MagicLinkClaim = token.verify_with_key(&signing_key()).map_err(|_| MagicLinkError::InvalidToken)?;
+        if claim.login_name != T::LOGIN_NAME {
+            return Err(MagicLinkError::InvalidToken);
+        }
+
+        if claim.valid_to < OffsetDateTime::now_utc() {
+            return Err(MagicLinkError::Expired);
+        }
+
+        if let Some(device) = &claim.device {
+            let secret = cookies.get_private(MAGIC_LINK_DEVICE_COOKIE_NAME);
+            if secret.map(|cookie| hash_device(cookie.value())).as_ref() != Some(device) {
+                return Err(MagicLinkError::DeviceMismatch);
+            }
+        }
+
+        let updated = diesel::update(ferrox_magic_link::table.find(uuid_value(claim.nonce)).filter(ferrox_magic_link::used_at.is_null()))
+            .set(ferrox_magic_link::used_at.eq(OffsetDateTime::now_utc()))
+            .execute(conn)
+            .await?;
+        if updated == 0 {
+            return Err(MagicLinkError::AlreadyUsed);
+        }
+
+        T::get_by_id(claim.id, conn).await
+            .map_err(|e| MagicLinkError::Database(e.to_string()))?
+            .ok_or(MagicLinkError::LoginNotFound)
+    }
+}
+
+/// Stores a new link for `email` unless the rate limit of `config` is exceeded and returns its nonce.
+///
+/// Counting and inserting happens in one transaction, serialized per address, so concurrent requests
+/// can not exceed the limit.
+async fn reserve_link(config: &MagicLinkConfig, email: &str, now: OffsetDateTime, conn: &mut PooledConnection) -> Result<Uuid, MagicLinkError> {
+    conn.transaction::<_, MagicLinkError, _>(|conn| Box::pin(async move {
+        #[cfg(feature = "postgres")]
+        diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext('ferrox_magic_link:' || $1))")
+            .bind::<diesel::sql_types::Text, _>(email)
+            .execute(conn)
+            .await?;
+
+        // On sqlite this write already serializes the transaction
+        diesel::delete(ferrox_magic_link::table.filter(ferrox_magic_link::created_at.lt(now - config.ttl.max(config.window))))
+            .execute(conn)
+            .await?;
+
+        let sent: i64 = ferrox_magic_link::table
+            .filter(ferrox_magic_link::email.eq(email))
+            .filter(ferrox_magic_link::created_at.gt(now - config.window))
+            .select(count_star())
+            .first(conn)
+            .await?;
+        if sent >= config.max_per_window {
+            return Err(MagicLinkError::RateLimited);
+        }
+
+        let nonce = Uuid::from_u128(rand::random());
+        diesel::insert_into(ferrox_magic_link::table)
+            .values((ferrox_magic_link::id.eq(uuid_value(nonce)), ferrox_magic_link::email.eq(email), ferrox_magic_link::created_at.eq(now)))
+            .execute(conn)
+            .await?;
+
+        Ok(nonce)
+    })).await
+}
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn hash_device(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::{Arc, Mutex};
+
+    use rocket::http::{CookieJar, Status};
+    use rocket::local::asynchronous::Client;
+    use rocket::{async_test, async_trait, get, routes};
+    use time::Duration;
+    use uuid::Uuid;
+    use ferrox_db::{DbPool, PooledConnection};
+    use ferrox_env::EnvLoader;
+    use ferrox_mailer::Mailer;
+    use crate::testing::{TestAuth, TestLogin, TestLogins};
+    use crate::{AuditContext, Login, MagicLink, MagicLinkConfig, MagicLinkError, MagicLinkLogin, AUTH_MIGRATIONS, MAGIC_LINK_DEVICE_COOKIE_NAME};
+
+    #[async_trait]
+    impl MagicLinkLogin for TestLogin {
+        async fn get_by_email(email: &str, _conn: &mut PooledConnection) -> Result<Option<Self>, Box<dyn Error>> {
+            let id = email.trim_end_matches("@test.de").parse::<Uuid>()?;
+            Ok(TestLogins::get::<Self>(id).map(|(login, _)| login))
+        }
+    }
+
+    static TOKEN: Mutex<Option<String>> = Mutex::new(None);
+
+    #[get("/send?<email>")]
+    async fn send(email: &str, cookies: &CookieJar<'_>) -> String {
+        let config = MagicLinkConfig::new("ferrox@test.de")
+            .with_rate_limit(2, Duration::hours(1))
+            .with_same_device();
+        let token = Arc::new(Mutex::new(None));
+        let link_token = token.clone();
+        let link = move |value: &str| {
+            *link_token.lock().unwrap() = Some(value.to_string());
+            value.to_string()
+        };
+
+        let mut conn = DbPool::get_conn().await.unwrap();
+        match MagicLink::send_with_config::<TestLogin>(&config, email, link, cookies, &mut conn).await {
+            Ok(()) => {
+                *TOKEN.lock().unwrap() = token.lock().unwrap().take();
+                "sent".to_string()
+            }
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[get("/verify?<token>")]
+    async fn verify(token: &str, cookies: &CookieJar<'_>, context: AuditContext) -> String {
+        let mut conn = DbPool::get_conn().await.unwrap();
+        match MagicLink::verify::<TestLogin>(token, cookies, &context, &mut conn).await {
+            Ok(login) => login.get_id().to_string(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[async_test]
+    async fn test_magic_link() {
+        EnvLoader::load_test();
+        TestAuth::init();
+        DbPool::run_migrations(vec![AUTH_MIGRATIONS]).await.unwrap();
+        Mailer::get_or_init();
+        let client = Client::untracked(rocket::build().mount("/", routes![send, verify])).await.unwrap();
+
+        let login = TestLogin::default().register();
+        let email = format!("{}@test.de", login.id);
+        let response = client.get(format!("/send?email={}", email)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let device = response.cookies().get_private(MAGIC_LINK_DEVICE_COOKIE_NAME).unwrap();
+        assert_eq!(response.into_string().await.unwrap(), "sent");
+        let token = TOKEN.lock().unwrap().take().unwrap();
+
+        let verify = format!("/verify?token={}", token);
+        let response = client.get(&verify).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), MagicLinkError::DeviceMismatch.to_string());
+        let response = client.get(&verify).private_cookie(device.clone()).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), login.id.to_string());
+        let response = client.get(&verify).private_cookie(device).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), MagicLinkError::AlreadyUsed.to_string());
+
+        let response = client.get(format!("/send?email={}", email)).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "sent");
+        let response = client.get(format!("/send?email=%20{}", email.to_uppercase())).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), MagicLinkError::RateLimited.to_string());
+    }
+}
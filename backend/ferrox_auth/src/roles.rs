@@ -1,4 +1,6 @@
 use std::ops::{Deref, DerefMut};
+use uuid::Uuid;
+use crate::{AuditContext, AuditEvent, AuditEventKind, AuditLog, LoginCache};
 
 /// Provides a convenient api for checking permissions.
 pub struct Roles<'a>(pub &'a Vec<String>);
@@ -12,6 +14,8 @@ impl<'a> Deref for Roles<'a> {
 }
 
 /// Provides a convenient api for modifying permissions.
+///
+/// Every change is recorded in the [AuditLog], use [AuditedRolesMut] to include the login and client.
 pub struct RolesMut<'a>(pub &'a mut Vec<String>);
 
 impl<'a> Deref for RolesMut<'a> {
@@ -44,22 +48,128 @@ impl<'a> Roles<'a> {
 impl<'a> RolesMut<'a> {
     common_functions!();
 
-    /// Adds a [Role].
+    /// Adds a [Role] and records [AuditEventKind::RoleAdded].
     ///
     /// This will not add duplicates and will ignore such instructions.
     pub fn add_role<T: Role>(&mut self) {
-        let str = T::ROLE_NAME.to_string();
-        if !self.0.contains(&str) {
-            self.0.push(str);
+        if self.insert::<T>() {
+            AuditLog::record(AuditEvent::new(AuditEventKind::RoleAdded).with_detail(T::ROLE_NAME));
         }
     }
 
-    /// Removes a [Role].
+    /// Removes a [Role] and records [AuditEventKind::RoleRemoved].
     ///
     /// If this role is not granted, nothing will happen.
     pub fn remove_role<T: Role>(&mut self) {
-        if let Some(index) = self.0.iter().position(|v| v == T::ROLE_NAME) {
-            self.0.remove(index);
+        if self.delete::<T>() {
+            AuditLog::record(AuditEvent::new(AuditEventKind::RoleRemoved).with_detail(T::ROLE_NAME));
+        }
+    }
+
+    fn insert<T: Role>(&mut self) -> bool {
+        let str = T::ROLE_NAME.to_string();
+        if self.0.contains(&str) {
+            return false;
+        }
+
+        self.0.push(str);
+        true
+    }
+
+    fn delete<T: Role>(&mut self) -> bool {
+        match self.0.iter().position(|v| v == T::ROLE_NAME) {
+            Some(index) => {
+                self.0.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Wraps [RolesMut] of a login, so the recorded [AuditEvent]s include the login and the client.
+///
+/// ```rust,ignore
+/// let id = user.get_id();
+/// let mut roles = AuditedRolesMut::new(user.get_roles_mut(&mut conn).await, User::LOGIN_NAME, id, &context);
+/// roles.add_role::<RoleAdmin>();
+/// let changes = roles.finish();
+/// user.save(&mut conn).await?;
+/// changes.persisted();
+/// ```
+pub struct AuditedRolesMut<'a> {
+    roles: RolesMut<'a>,
+    changes: RoleChanges,
+}
+
+impl<'a> Deref for AuditedRolesMut<'a> {
+    type Target = RolesMut<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.roles
+    }
+}
+
+impl<'a> AuditedRolesMut<'a> {
+    /// Wraps `roles` of the login `id`, usually called with [crate::Login::LOGIN_NAME] and [crate::Login::get_id].
+    ///
+    /// `context` is the client changing the roles.
+    pub fn new(roles: RolesMut<'a>, login_name: &'static str, id: Uuid, context: &AuditContext) -> Self {
+        AuditedRolesMut {
+            roles,
+            changes: RoleChanges {
+                login_name,
+                id,
+                context: context.clone(),
+                events: Vec::new(),
+            },
+        }
+    }
+
+    /// Adds a [Role], see [RolesMut::add_role].
+    pub fn add_role<T: Role>(&mut self) {
+        if self.roles.insert::<T>() {
+            self.changes.record(AuditEventKind::RoleAdded, T::ROLE_NAME);
+        }
+    }
+
+    /// Removes a [Role], see [RolesMut::remove_role].
+    pub fn remove_role<T: Role>(&mut self) {
+        if self.roles.delete::<T>() {
+            self.changes.record(AuditEventKind::RoleRemoved, T::ROLE_NAME);
+        }
+    }
+
+    /// Releases the roles, so the login can be persisted.
+    pub fn finish(self) -> RoleChanges {
+        self.changes
+    }
+}
+
+/// Role changes made through [AuditedRolesMut].
+#[must_use = "the login cache is only invalidated through RoleChanges::persisted"]
+pub struct RoleChanges {
+    login_name: &'static str,
+    id: Uuid,
+    context: AuditContext,
+    events: Vec<(AuditEventKind, &'static str)>,
+}
+
+impl RoleChanges {
+    fn record(&mut self, kind: AuditEventKind, role: &'static str) {
+        AuditLog::record(AuditEvent::new(kind)
+            .with_login(self.login_name, self.id)
+            .with_context(&self.context)
+            .with_detail(role));
+        self.events.push((kind, role));
+    }
+
+    /// Invalidates the [LoginCache] of the login if its roles were changed.
+    ///
+    /// Call this after the changed roles were persisted, otherwise a concurrent request could cache the old roles again.
+    pub fn persisted(self) {
+        if !self.events.is_empty() {
+            LoginCache::invalidate_login(self.login_name, self.id);
         }
     }
 }
@@ -92,4 +202,46 @@ macro_rules! define_role {
 }
 
 use crate as ferrox_auth;
-define_role!(RoleUser, "ROLE_USER");
\ No newline at end of file
+define_role!(RoleUser, "ROLE_USER");
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use crate::{AuditContext, AuditEventKind, AuditedRolesMut, Role, RolesMut, RoleUser};
+    use crate as ferrox_auth;
+
+    define_role!(RoleAdmin, "ROLE_ADMIN");
+
+    #[test]
+    fn test_roles_mut() {
+        let mut roles = vec![RoleUser::ROLE_NAME.to_string()];
+        let mut roles_mut = RolesMut(&mut roles);
+        roles_mut.add_role::<RoleAdmin>();
+        roles_mut.add_role::<RoleAdmin>();
+        roles_mut.remove_role::<RoleUser>();
+        roles_mut.remove_role::<RoleUser>();
+        assert!(roles_mut.is_granted::<RoleAdmin>());
+        assert!(!roles_mut.is_granted::<RoleUser>());
+        assert_eq!(roles, vec![RoleAdmin::ROLE_NAME.to_string()]);
+    }
+
+    #[test]
+    fn test_audited_roles() {
+        let mut roles = vec![RoleUser::ROLE_NAME.to_string()];
+        let context = AuditContext {
+            ip: Some("127.0.0.1".to_string()),
+            user_agent: None,
+        };
+
+        let mut audited = AuditedRolesMut::new(RolesMut(&mut roles), "test", Uuid::nil(), &context);
+        audited.add_role::<RoleAdmin>();
+        audited.add_role::<RoleAdmin>();
+        audited.remove_role::<RoleUser>();
+        audited.remove_role::<RoleUser>();
+        assert!(audited.is_granted::<RoleAdmin>());
+
+        let changes = audited.finish();
+        assert_eq!(roles, vec![RoleAdmin::ROLE_NAME.to_string()]);
+        assert_eq!(changes.context.ip.as_deref(), Some("127.0.0.1"));
+        assert_eq!(changes.events, vec![(AuditEventKind::RoleAdded, "ROLE_ADMIN"), (AuditEventKind::RoleRemoved, "ROLE_USER")]);
+    }
+}
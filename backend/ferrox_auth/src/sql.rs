@@ -0,0 +1,95 @@
+//! Contains the sql types of the tables of this crate for the backend selected in [ferrox_db].
+//!
+//! SQLite has no uuid type in diesel, so uuids are stored as text there.
+
+#[cfg(not(any(feature = "postgres", feature = "sqlite")))]
+compile_error!("Either the postgres or the sqlite feature of ferrox_auth has to be enabled");
+
+#[cfg(feature = "postgres")]
+pub(crate) use postgres::*;
+#[cfg(not(feature = "postgres"))]
+pub(crate) use sqlite::*;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use uuid::Uuid;
+
+    pub(crate) type SqlUuid = diesel::sql_types::Uuid;
+    pub(crate) type SqlTimestamp = diesel::sql_types::Timestamptz;
+
+    /// Value of a uuid in queries.
+    pub(crate) type UuidValue = Uuid;
+
+    pub(crate) fn uuid_value(id: Uuid) -> UuidValue {
+        id
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+mod sqlite {
+    use diesel::deserialize::{self, FromSql};
+    use diesel::serialize::{self, IsNull, Output, ToSql};
+    use diesel::sql_types::{Nullable, Text};
+    use diesel::sqlite::{Sqlite, SqliteValue};
+    use diesel::{AsExpression, FromSqlRow};
+    use uuid::Uuid;
+
+    pub(crate) type SqlUuid = Text;
+    pub(crate) type SqlTimestamp = diesel::sql_types::TimestamptzSqlite;
+
+    /// Value of a uuid in queries.
+    pub(crate) type UuidValue = TextUuid;
+
+    pub(crate) fn uuid_value(id: Uuid) -> UuidValue {
+        TextUuid(Some(id))
+    }
+
+    /// Uuid stored as text, used through `serialize_as` and `deserialize_as` of nullable uuid fields.
+    #[derive(Clone, Copy, Debug, AsExpression, FromSqlRow)]
+    #[diesel(sql_type = Text)]
+    pub struct TextUuid(pub Option<Uuid>);
+
+    impl From<Option<Uuid>> for TextUuid {
+        fn from(id: Option<Uuid>) -> Self {
+            TextUuid(id)
+        }
+    }
+
+    impl From<TextUuid> for Option<Uuid> {
+        fn from(id: TextUuid) -> Self {
+            id.0
+        }
+    }
+
+    impl ToSql<Text, Sqlite> for TextUuid {
+        fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+            match self.0 {
+                Some(id) => {
+                    out.set_value(id.to_string());
+                    Ok(IsNull::No)
+                }
+                None => Ok(IsNull::Yes),
+            }
+        }
+    }
+
+    impl FromSql<Text, Sqlite> for TextUuid {
+        fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+            let value = <String as FromSql<Text, Sqlite>>::from_sql(bytes)?;
+            Ok(TextUuid(Some(value.parse()?)))
+        }
+    }
+
+    impl FromSql<Nullable<Text>, Sqlite> for TextUuid {
+        fn from_sql(bytes: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+            <Self as FromSql<Text, Sqlite>>::from_sql(bytes)
+        }
+
+        fn from_nullable_sql(bytes: Option<SqliteValue<'_, '_, '_>>) -> deserialize::Result<Self> {
+            match bytes {
+                Some(bytes) => <Self as FromSql<Text, Sqlite>>::from_sql(bytes),
+                None => Ok(TextUuid(None)),
+            }
+        }
+    }
+}
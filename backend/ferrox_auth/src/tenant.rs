@@ -0,0 +1,96 @@
+use std::ops::Deref;
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{async_trait, Request};
+#[cfg(feature = "postgres")]
+use ferrox_db::{DbPool, DbPoolError, PooledConnection};
+use crate::{read_request_token, AuthConfig, LoginClaim};
+
+/// Defines where the tenant of a request is resolved from.
+///
+/// Sources are configured through [crate::AuthFairing::with_tenant_source].
+#[derive(Clone, Debug)]
+pub enum TenantSource {
+    /// Subdomain below the given base domain.
+    ///
+    /// With `Subdomain("example.com")` the host `acme.example.com` resolves to `acme`.
+    Subdomain(&'static str),
+    /// Value of the given header.
+    Header(&'static str),
+    /// Tenant of the [LoginClaim] of the current login.
+    Claim,
+}
+
+impl TenantSource {
+    fn resolve(&self, request: &Request<'_>) -> Option<String> {
+        match self {
+            TenantSource::Subdomain(base) => {
+                let host = request.host()?;
+                let subdomain = host.domain().as_str().strip_suffix(base)?.strip_suffix('.')?;
+                (!subdomain.is_empty()).then(|| subdomain.to_string())
+            }
+            TenantSource::Header(name) => request.headers().get_one(name).map(str::to_string),
+            TenantSource::Claim => LoginClaim::read_token(&read_request_token(request)?).ok()?.tenant,
+        }
+    }
+}
+
+/// Request guard providing the tenant of the current request.
+///
+/// The tenant is resolved by the configured [TenantSource]s, the first match wins.
+///
+/// This will respond with [Status::BadRequest] if no tenant was found
+/// and with [Status::Forbidden] if the tenant of the login differs from the requested one.
+/// Logins without a tenant (see [crate::Login::get_tenant]) are not bound to any tenant.
+pub struct Tenant(String);
+
+impl Tenant {
+    /// Returns the identifier of this tenant.
+    pub fn id(&self) -> &str {
+        &self.0
+    }
+
+    /// Retrieves a [PooledConnection] isolated to this tenant.
+    ///
+    /// See [DbPool::get_tenant_conn]. Requires Postgres.
+    #[cfg(feature = "postgres")]
+    pub async fn get_conn(&self) -> Result<PooledConnection, DbPoolError> {
+        DbPool::get_tenant_conn(&self.0).await
+    }
+
+    /// Resolves the tenant requested through subdomain or header, ignoring the login.
+    pub(crate) fn requested(request: &Request<'_>) -> Option<String> {
+        AuthConfig::get().tenant_sources.iter()
+            .filter(|source| !matches!(source, TenantSource::Claim))
+            .find_map(|source| source.resolve(request))
+    }
+}
+
+impl Deref for Tenant {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for Tenant {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let tenant = AuthConfig::get().tenant_sources.iter().find_map(|source| source.resolve(request));
+        let Some(tenant) = tenant else {
+            return Outcome::Error((Status::BadRequest, "Tenant not found"));
+        };
+
+        if let Some(claimed) = TenantSource::Claim.resolve(request) {
+            if claimed != tenant {
+                return Outcome::Error((Status::Forbidden, "Invalid tenant"));
+            }
+        }
+
+        Outcome::Success(Tenant(tenant))
+    }
+}
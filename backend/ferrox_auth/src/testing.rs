@@ -0,0 +1,288 @@
+//! Contains utilities for testing [crate::Authenticated] routes without a database.
+//!
+//! Enabled through the `testing` feature.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Mutex, OnceLock};
+
+use jwt::SignWithKey;
+use hmac::{Hmac, Mac};
+use rocket::{async_trait, Request};
+use rocket::local::asynchronous::LocalRequest;
+use sha2::Sha256;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+use ferrox_db::PooledConnection;
+use crate::{set_secret, Login, LoginClaim, Role, Roles, RolesMut};
+
+/// Deterministic secret used to sign tokens in tests.
+pub const TEST_SECRET: &str = "ferrox-test-secret-do-not-use-in-production";
+
+/// Provides the setup for tests.
+pub struct TestAuth;
+
+impl TestAuth {
+    /// Signs and verifies all tokens with [TEST_SECRET] instead of `secret.local`.
+    ///
+    /// # Panics
+    /// Panics if a different secret was already in use.
+    pub fn init() {
+        assert!(set_secret(TEST_SECRET), "Login secret was already initialized");
+    }
+}
+
+type LoginFactory<T> = Box<dyn Fn() -> (T, Vec<String>) + Send + Sync>;
+
+type LoginRegistry = Mutex<HashMap<(&'static str, Uuid), Box<dyn Any + Send + Sync>>>;
+
+static TEST_LOGINS: OnceLock<LoginRegistry> = OnceLock::new();
+
+/// In-memory registry of logins used by [TestLogin] instead of the database.
+///
+/// Other fixtures can use it by returning [Self::get] from [Login::get_by_id] and [Login::load_authenticated].
+pub struct TestLogins;
+
+impl TestLogins {
+    fn logins() -> &'static LoginRegistry {
+        TEST_LOGINS.get_or_init(Default::default)
+    }
+
+    /// Registers a login with its roles, [Self::get] returns copies of it.
+    pub fn insert<T: Login + Clone + 'static>(login: T, roles: Vec<String>) {
+        let key = (T::LOGIN_NAME, login.get_id());
+        let factory: LoginFactory<T> = Box::new(move || (login.clone(), roles.clone()));
+        Self::logins().lock().unwrap().insert(key, Box::new(factory));
+    }
+
+    /// Removes a registered login.
+    pub fn remove<T: Login>(id: Uuid) {
+        Self::logins().lock().unwrap().remove(&(T::LOGIN_NAME, id));
+    }
+
+    /// Retrieves a registered login with its roles.
+    pub fn get<T: Login + 'static>(id: Uuid) -> Option<(T, Vec<String>)> {
+        let logins = Self::logins().lock().unwrap();
+        let factory = logins.get(&(T::LOGIN_NAME, id))?.downcast_ref::<LoginFactory<T>>()?;
+        Some(factory())
+    }
+}
+
+/// In-memory [Login] fixture.
+///
+/// Call [Self::register] to make it available to [crate::Authenticated].
+#[derive(Clone, Debug)]
+pub struct TestLogin {
+    /// Id of this login.
+    pub id: Uuid,
+    /// Roles of this login.
+    pub roles: Vec<String>,
+    /// Tenant of this login.
+    pub tenant: Option<String>,
+}
+
+impl Default for TestLogin {
+    fn default() -> Self {
+        TestLogin {
+            id: Uuid::from_u128(rand::random()),
+            roles: vec![],
+            tenant: None,
+        }
+    }
+}
+
+impl TestLogin {
+    /// Adds a [Role] to this login.
+    pub fn with_role<R: Role>(mut self) -> Self {
+        RolesMut(&mut self.roles).add_role::<R>();
+        self
+    }
+
+    /// Sets the tenant of this login.
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    /// Registers this login in [TestLogins].
+    pub fn register(self) -> Self {
+        TestLogins::insert(self.clone(), self.roles.clone());
+        self
+    }
+
+    /// Creates a valid [TestToken] for this login.
+    pub fn token(&self) -> TestToken {
+        let mut token = TestToken::new::<Self>(self.id).with_roles(self.roles.clone());
+        token.claim.tenant = self.tenant.clone();
+        token
+    }
+}
+
+#[async_trait]
+impl Login for TestLogin {
+    const LOGIN_NAME: &'static str = "test";
+
+    fn get_id(&self) -> Uuid {
+        self.id
+    }
+
+    fn get_tenant(&self) -> Option<String> {
+        self.tenant.clone()
+    }
+
+    async fn get_roles(&self, _conn: &mut PooledConnection) -> Roles {
+        Roles(&self.roles)
+    }
+
+    async fn get_roles_mut(&mut self, _conn: &mut PooledConnection) -> RolesMut {
+        RolesMut(&mut self.roles)
+    }
+
+    async fn get_by_id(id: Uuid, _conn: &mut PooledConnection) -> Result<Option<Self>, Box<dyn Error>> {
+        Ok(TestLogins::get::<Self>(id).map(|(login, _)| login))
+    }
+
+    async fn load_authenticated(id: Uuid, _request: &Request<'_>) -> Result<Option<(Self, Vec<String>)>, String> {
+        Ok(TestLogins::get::<Self>(id))
+    }
+}
+
+/// Builder for login tokens, including invalid ones to test rejections.
+pub struct TestToken {
+    claim: LoginClaim,
+    secret: Option<String>,
+}
+
+impl TestToken {
+    /// Creates a token for the login `T` with `id`, valid for one day and without roles.
+    pub fn new<T: Login>(id: Uuid) -> Self {
+        TestToken {
+            claim: LoginClaim {
+                id,
+                login_name: T::LOGIN_NAME.to_string(),
+                valid_to: OffsetDateTime::now_utc() + Duration::days(1),
+                roles: vec![],
+                tenant: None,
+            },
+            secret: None,
+        }
+    }
+
+    /// Sets the roles of the token.
+    pub fn with_roles(mut self, roles: Vec<String>) -> Self {
+        self.claim.roles = roles;
+        self
+    }
+
+    /// Adds a [Role] to the token.
+    pub fn with_role<R: Role>(mut self) -> Self {
+        RolesMut(&mut self.claim.roles).add_role::<R>();
+        self
+    }
+
+    /// Sets the tenant of the token.
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.claim.tenant = Some(tenant.to_string());
+        self
+    }
+
+    /// Sets another [Login::LOGIN_NAME].
+    pub fn with_login_name(mut self, login_name: &str) -> Self {
+        self.claim.login_name = login_name.to_string();
+        self
+    }
+
+    /// Makes the token expired.
+    pub fn expired(mut self) -> Self {
+        self.claim.valid_to = OffsetDateTime::now_utc() - Duration::minutes(1);
+        self
+    }
+
+    /// Signs the token with another secret than [TEST_SECRET].
+    pub fn signed_with(mut self, secret: &str) -> Self {
+        self.secret = Some(secret.to_string());
+        self
+    }
+
+    /// Signs the token.
+    pub fn build(self) -> String {
+        let secret = self.secret.unwrap_or_else(|| TEST_SECRET.to_string());
+        let key: Hmac<Sha256> = Hmac::new_from_slice(secret.as_bytes()).unwrap();
+        self.claim.sign_with_key(&key).unwrap()
+    }
+
+    /// Changes the signature of a token, so it no longer verifies.
+    pub fn tamper(token: &str) -> String {
+        let (content, signature) = token.rsplit_once('.').unwrap();
+        let replacement = if signature.starts_with('A') { "B" } else { "A" };
+        format!("{}.{}{}", content, replacement, &signature[1..])
+    }
+}
+
+/// Attaches login tokens to requests of a [rocket::local::asynchronous::Client].
+pub trait AuthenticatedRequest {
+    /// Attaches a raw token as cookie or header, depending on the enabled feature.
+    fn with_token(self, token: &str) -> Self;
+
+    /// Attaches the token built from a [TestToken].
+    fn authenticated(self, token: TestToken) -> Self where Self: Sized {
+        self.with_token(&token.build())
+    }
+}
+
+impl AuthenticatedRequest for LocalRequest<'_> {
+    #[cfg(feature = "auth-from-cookie")]
+    fn with_token(self, token: &str) -> Self {
+        self.private_cookie(rocket::http::Cookie::new(crate::AUTH_COOKIE_NAME, token.to_string()))
+    }
+
+    #[cfg(all(feature = "auth-from-header", not(feature = "auth-from-cookie")))]
+    fn with_token(self, token: &str) -> Self {
+        self.header(rocket::http::Header::new(crate::AUTH_HEADER_NAME, token.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::{async_test, get, routes};
+    use crate::testing::{AuthenticatedRequest, TestAuth, TestLogin, TestToken};
+    use crate::{define_role, Authenticated, Login, RoleUser};
+    use crate as ferrox_auth;
+
+    define_role!(RoleAdmin, "ROLE_ADMIN");
+
+    #[get("/admin")]
+    fn admin(login: Authenticated<TestLogin, RoleAdmin>) -> String {
+        login.get_id().to_string()
+    }
+
+    #[async_test]
+    async fn test_authenticated() {
+        TestAuth::init();
+        let client = Client::tracked(rocket::build().mount("/", routes![admin])).await.unwrap();
+
+        let admin = TestLogin::default().with_role::<RoleAdmin>().register();
+        let response = client.get("/admin").authenticated(admin.token()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), admin.id.to_string());
+
+        let user = TestLogin::default().with_role::<RoleUser>().register();
+        let response = client.get("/admin").authenticated(user.token()).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let rejected = [
+            admin.token().expired().build(),
+            admin.token().signed_with("other").build(),
+            admin.token().with_login_name("other").build(),
+            admin.token().with_role::<RoleUser>().build(),
+            TestToken::tamper(&admin.token().build()),
+        ];
+        for token in rejected {
+            let response = client.get("/admin").with_token(&token).dispatch().await;
+            assert_eq!(response.status(), Status::Unauthorized);
+        }
+    }
+}
@@ -0,0 +1 @@
+DROP TABLE ferrox_scheduled_task;
@@ -0,0 +1,8 @@
+CREATE TABLE ferrox_scheduled_task (
+    name TEXT PRIMARY KEY,
+    last_tick TIMESTAMPTZ NOT NULL,
+    last_run_at TIMESTAMPTZ,
+    last_duration_ms BIGINT,
+    last_success BOOLEAN,
+    last_error TEXT
+);
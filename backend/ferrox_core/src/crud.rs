@@ -0,0 +1,660 @@
+//! Contains [CrudResource], which provides the list, get, create, update and delete routes of a table.
+//!
+//! The resource declares its types and the [Permission] of every action, [crate::crud_store] implements
+//! the queries for its table:
+//! ```rust,ignore
+//! ferrox_core::listable!(users (id) {
+//!     sort: [id, name, created_at],
+//!     filter: [role],
+//! });
+//!
+//! pub struct UserResource;
+//!
+//! impl CrudResource for UserResource {
+//!     type Login = User;
+//!     type Model = UserDto;
+//!     type Id = Uuid;
+//!     type Create = CreateUser;
+//!     type Update = UpdateUser;
+//!     type ListPermission = RoleUser;
+//!     type GetPermission = RoleUser;
+//!     type CreatePermission = RoleAdmin;
+//!     type UpdatePermission = RoleAdmin;
+//!     type DeletePermission = RoleAdmin;
+//! }
+//!
+//! ferrox_core::crud_store!(UserResource, users);
+//!
+//! rocket::build().mount("/users", UserResource::routes())
+//! ```
+//!
+//! This mounts the following routes, all responding with [StdResponse]:
+//! - `GET /` lists the table following the [Pagination] conventions
+//! - `GET /<id>` returns one row
+//! - `POST /` inserts the JSON body, responding with [Status::Created]
+//! - `PATCH /<id>` updates the columns set in the JSON body
+//! - `DELETE /<id>` deletes one row
+//!
+//! Tables with soft delete are supported through `crud_store!(UserResource, users, soft_delete)`.
+//!
+//! Writes run inside a [Tx], so they are rolled back if a hook fails.
+//!
+//! Enabled through the `crud` feature.
+
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
+
+use rocket::data::FromData;
+use rocket::form::{FromForm, Options};
+use rocket::http::uri::Origin;
+use rocket::http::{Method, Status};
+use rocket::request::{FromParam, FromRequest, Outcome};
+use rocket::response::status::Custom;
+use rocket::response::Responder;
+use rocket::route::{self, Handler};
+use rocket::serde::json::Json;
+use rocket::{async_trait, Data, Request, Route};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use crate::auth::{Authenticated, Login, Permission};
+use crate::db::{DbConn, PooledConnection, Tx};
+use crate::db_error::DbError;
+use crate::pagination::{Page, Pagination, PaginationError};
+use crate::std_response::StdResponse;
+
+/// Validation of the input of a [CrudResource].
+pub trait Validate {
+    /// Returns the message describing why the input is invalid.
+    fn validate(&self) -> Result<(), String>;
+}
+
+/// Errors of the routes of a [CrudResource], which can also be returned by its hooks.
+#[derive(Debug)]
+pub enum CrudError {
+    /// A request guard failed with the status, which is forwarded to the catcher.
+    Guard(Status),
+    /// The row does not exist.
+    NotFound,
+    /// The input is invalid.
+    Invalid(String),
+    /// Responds with the status and message.
+    Custom(Status, String),
+    /// Listing the table failed.
+    Pagination(PaginationError),
+    /// A query failed, responds like [DbError].
+    Query(diesel::result::Error),
+}
+
+impl Display for CrudError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CrudError::Guard(status) => write!(f, "Request guard failed: {}", status),
+            CrudError::NotFound => write!(f, "Not found"),
+            CrudError::Invalid(msg) => write!(f, "Invalid input: {}", msg),
+            CrudError::Custom(_, msg) => write!(f, "{}", msg),
+            CrudError::Pagination(e) => write!(f, "{}", e),
+            CrudError::Query(e) => write!(f, "Query failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CrudError {}
+
+impl From<diesel::result::Error> for CrudError {
+    fn from(e: diesel::result::Error) -> Self {
+        CrudError::Query(e)
+    }
+}
+
+impl From<PaginationError> for CrudError {
+    fn from(e: PaginationError) -> Self {
+        CrudError::Pagination(e)
+    }
+}
+
+impl<'r> Responder<'r, 'r> for CrudError {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'r> {
+        let msg = self.to_string();
+        let status = match self {
+            CrudError::Guard(status) => return Err(status),
+            CrudError::Pagination(e) => return e.respond_to(request),
+            CrudError::Query(e) => return DbError::from(e).respond_to(request),
+            CrudError::NotFound => Status::NotFound,
+            CrudError::Invalid(_) => Status::UnprocessableEntity,
+            CrudError::Custom(status, _) => status,
+        };
+
+        Custom(status, StdResponse::<()>::failure(&msg)).respond_to(request)
+    }
+}
+
+/// Resource providing the routes to list, get, create, update and delete the rows of a table.
+///
+/// The queries are implemented through [crate::crud_store], the hooks can be overridden for custom logic.
+/// See the [module](self) documentation.
+#[async_trait]
+pub trait CrudResource: Send + Sync + 'static {
+    /// [Login] required for all routes.
+    type Login: Login + 'static;
+    /// Row of the table as returned by all routes.
+    type Model: Serialize + Send;
+    /// Primary key of the table.
+    type Id: for<'a> FromParam<'a> + Clone + Send + Sync;
+    /// Input of `POST /`.
+    type Create: DeserializeOwned + Validate + Send;
+    /// Input of `PATCH /<id>`, usually with optional fields to only update the columns which are set.
+    type Update: DeserializeOwned + Validate + Send;
+
+    /// [Permission] required for `GET /`.
+    type ListPermission: Permission;
+    /// [Permission] required for `GET /<id>`.
+    type GetPermission: Permission;
+    /// [Permission] required for `POST /`.
+    type CreatePermission: Permission;
+    /// [Permission] required for `PATCH /<id>`.
+    type UpdatePermission: Permission;
+    /// [Permission] required for `DELETE /<id>`.
+    type DeletePermission: Permission;
+
+    /// Called with the validated input before it is inserted, e.g. to set the owner or to reject it.
+    async fn before_create(_login: &Self::Login, _input: &mut Self::Create, _conn: &mut PooledConnection) -> Result<(), CrudError> {
+        Ok(())
+    }
+
+    /// Called with the inserted row.
+    async fn after_create(_login: &Self::Login, _model: &Self::Model, _conn: &mut PooledConnection) -> Result<(), CrudError> {
+        Ok(())
+    }
+
+    /// Called with the validated input before the row with `id` is updated.
+    async fn before_update(_login: &Self::Login, _id: &Self::Id, _input: &mut Self::Update, _conn: &mut PooledConnection) -> Result<(), CrudError> {
+        Ok(())
+    }
+
+    /// Called with the updated row.
+    async fn after_update(_login: &Self::Login, _model: &Self::Model, _conn: &mut PooledConnection) -> Result<(), CrudError> {
+        Ok(())
+    }
+
+    /// Called before the row with `id` is deleted.
+    async fn before_delete(_login: &Self::Login, _id: &Self::Id, _conn: &mut PooledConnection) -> Result<(), CrudError> {
+        Ok(())
+    }
+
+    /// Called after the row with `id` was deleted.
+    async fn after_delete(_login: &Self::Login, _id: &Self::Id, _conn: &mut PooledConnection) -> Result<(), CrudError> {
+        Ok(())
+    }
+
+    /// Returns the routes of this resource to be mounted.
+    fn routes() -> Vec<Route> where Self: CrudStore + Sized {
+        vec![
+            Route::new(Method::Get, "/", CrudHandler::<Self>::new(CrudAction::List)),
+            Route::new(Method::Get, "/<id>", CrudHandler::<Self>::new(CrudAction::Get)),
+            Route::new(Method::Post, "/", CrudHandler::<Self>::new(CrudAction::Create)),
+            Route::new(Method::Patch, "/<id>", CrudHandler::<Self>::new(CrudAction::Update)),
+            Route::new(Method::Delete, "/<id>", CrudHandler::<Self>::new(CrudAction::Delete)),
+        ]
+    }
+}
+
+/// Queries of a [CrudResource] on its table.
+///
+/// Implement this through [crate::crud_store].
+#[async_trait]
+pub trait CrudStore: CrudResource {
+    /// Loads the requested page of the table.
+    async fn list(pagination: &Pagination, uri: &Origin<'_>, conn: &mut PooledConnection) -> Result<Page<Self::Model>, PaginationError>;
+
+    /// Loads the row with `id`.
+    async fn get(id: &Self::Id, conn: &mut PooledConnection) -> diesel::QueryResult<Option<Self::Model>>;
+
+    /// Inserts `input` and returns the inserted row.
+    async fn insert(input: Self::Create, conn: &mut PooledConnection) -> diesel::QueryResult<Self::Model>;
+
+    /// Updates the row with `id` and returns it, `None` if it does not exist.
+    async fn update(id: &Self::Id, input: Self::Update, conn: &mut PooledConnection) -> diesel::QueryResult<Option<Self::Model>>;
+
+    /// Deletes the row with `id` and returns whether it existed.
+    async fn delete(id: &Self::Id, conn: &mut PooledConnection) -> diesel::QueryResult<bool>;
+}
+
+/// Implements [CrudStore] for a [CrudResource] on a table declared with [diesel::table].
+///
+/// Takes the resource and the table, which has to be [crate::pagination::Listable].
+/// The model has to be [diesel::Queryable] from all columns, the create input [diesel::Insertable]
+/// and the update input [diesel::AsChangeset].
+///
+/// For a [crate::db::SoftDelete] table, pass `soft_delete` after the table: deleted rows are then
+/// left out of all routes and `DELETE /<id>` marks the row as deleted instead of removing it.
+/// ```rust,ignore
+/// ferrox_core::crud_store!(UserResource, users, soft_delete);
+/// ```
+#[macro_export]
+macro_rules! crud_store {
+    ($resource:ty, $table:ident) => {
+        $crate::crud_store!(@store $resource, $table, ::std::convert::identity, ::diesel::delete);
+    };
+    ($resource:ty, $table:ident, soft_delete) => {
+        $crate::crud_store!(@store $resource, $table, $crate::db::SoftDeleteDsl::active, $crate::db::SoftDeleteDsl::soft_delete);
+    };
+    (@store $resource:ty, $table:ident, $scope:path, $delete:path) => {
+        #[::rocket::async_trait]
+        impl $crate::crud::CrudStore for $resource {
+            async fn list(
+                pagination: &$crate::pagination::Pagination,
+                uri: &::rocket::http::uri::Origin<'_>,
+                conn: &mut $crate::db::PooledConnection,
+            ) -> Result<$crate::pagination::Page<Self::Model>, $crate::pagination::PaginationError> {
+                pagination.load(|| ::diesel::QueryDsl::into_boxed($scope($table::table)), uri, conn).await
+            }
+
+            async fn get(id: &Self::Id, conn: &mut $crate::db::PooledConnection) -> ::diesel::QueryResult<Option<Self::Model>> {
+                let query = $scope(::diesel::QueryDsl::find($table::table, id.clone()));
+                ::diesel::OptionalExtension::optional(::diesel_async::RunQueryDsl::get_result(query, conn).await)
+            }
+
+            async fn insert(input: Self::Create, conn: &mut $crate::db::PooledConnection) -> ::diesel::QueryResult<Self::Model> {
+                let query = ::diesel::insert_into($table::table).values(input);
+                ::diesel_async::RunQueryDsl::get_result(query, conn).await
+            }
+
+            async fn update(id: &Self::Id, input: Self::Update, conn: &mut $crate::db::PooledConnection) -> ::diesel::QueryResult<Option<Self::Model>> {
+                let query = ::diesel::update($scope(::diesel::QueryDsl::find($table::table, id.clone()))).set(input);
+                match ::diesel_async::RunQueryDsl::get_result(query, conn).await {
+                    // Nothing to update, so the row is returned as is
+                    Err(::diesel::result::Error::QueryBuilderError(e)) if e.is::<::diesel::result::EmptyChangeset>() => {
+                        <Self as $crate::crud::CrudStore>::get(id, conn).await
+                    }
+                    result => ::diesel::OptionalExtension::optional(result),
+                }
+            }
+
+            async fn delete(id: &Self::Id, conn: &mut $crate::db::PooledConnection) -> ::diesel::QueryResult<bool> {
+                let query = $delete(::diesel::QueryDsl::find($table::table, id.clone()));
+                ::diesel_async::RunQueryDsl::execute(query, conn).await.map(|count| count > 0)
+            }
+        }
+    };
+}
+
+#[derive(Clone, Copy)]
+enum CrudAction {
+    List,
+    Get,
+    Create,
+    Update,
+    Delete,
+}
+
+struct CrudHandler<R> {
+    action: CrudAction,
+    resource: PhantomData<fn() -> R>,
+}
+
+impl<R> CrudHandler<R> {
+    fn new(action: CrudAction) -> Self {
+        CrudHandler {
+            action,
+            resource: PhantomData,
+        }
+    }
+}
+
+impl<R> Clone for CrudHandler<R> {
+    fn clone(&self) -> Self {
+        CrudHandler::new(self.action)
+    }
+}
+
+#[async_trait]
+impl<R: CrudStore> Handler for CrudHandler<R> {
+    async fn handle<'r>(&self, request: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        match self.action {
+            CrudAction::List => route::Outcome::from(request, list::<R>(request).await),
+            CrudAction::Get => route::Outcome::from(request, get::<R>(request).await),
+            CrudAction::Create => route::Outcome::from(request, create::<R>(request, data).await),
+            CrudAction::Update => route::Outcome::from(request, update::<R>(request, data).await),
+            CrudAction::Delete => route::Outcome::from(request, delete::<R>(request).await),
+        }
+    }
+}
+
+async fn list<R: CrudStore>(request: &Request<'_>) -> Result<Page<R::Model>, CrudError> {
+    authenticated::<R::Login, R::ListPermission>(request).await?;
+    let mut context = <Pagination as FromForm>::init(Options::Lenient);
+    for field in request.query_fields() {
+        <Pagination as FromForm>::push_value(&mut context, field);
+    }
+    let pagination = <Pagination as FromForm>::finalize(context).map_err(|e| CrudError::Invalid(e.to_string()))?;
+
+    let mut conn = guard::<DbConn>(request).await?;
+    Ok(R::list(&pagination, request.uri(), &mut conn).await?)
+}
+
+async fn get<R: CrudStore>(request: &Request<'_>) -> Result<StdResponse<R::Model>, CrudError> {
+    authenticated::<R::Login, R::GetPermission>(request).await?;
+    let id = param::<R>(request)?;
+
+    let mut conn = guard::<DbConn>(request).await?;
+    let model = R::get(&id, &mut conn).await?.ok_or(CrudError::NotFound)?;
+    Ok(StdResponse::success(model))
+}
+
+async fn create<'r, R: CrudStore>(request: &'r Request<'_>, data: Data<'r>) -> Result<Custom<StdResponse<R::Model>>, CrudError> {
+    let login = authenticated::<R::Login, R::CreatePermission>(request).await?;
+    let mut input = body::<R::Create>(request, data).await?;
+    input.validate().map_err(CrudError::Invalid)?;
+
+    let mut conn = guard::<Tx>(request).await?;
+    R::before_create(&login, &mut input, &mut conn).await?;
+    let model = R::insert(input, &mut conn).await?;
+    R::after_create(&login, &model, &mut conn).await?;
+    Ok(Custom(Status::Created, StdResponse::success(model)))
+}
+
+async fn update<'r, R: CrudStore>(request: &'r Request<'_>, data: Data<'r>) -> Result<StdResponse<R::Model>, CrudError> {
+    let login = authenticated::<R::Login, R::UpdatePermission>(request).await?;
+    let id = param::<R>(request)?;
+    let mut input = body::<R::Update>(request, data).await?;
+    input.validate().map_err(CrudError::Invalid)?;
+
+    let mut conn = guard::<Tx>(request).await?;
+    R::before_update(&login, &id, &mut input, &mut conn).await?;
+    let model = R::update(&id, input, &mut conn).await?.ok_or(CrudError::NotFound)?;
+    R::after_update(&login, &model, &mut conn).await?;
+    Ok(StdResponse::success(model))
+}
+
+async fn delete<R: CrudStore>(request: &Request<'_>) -> Result<StdResponse<()>, CrudError> {
+    let login = authenticated::<R::Login, R::DeletePermission>(request).await?;
+    let id = param::<R>(request)?;
+
+    let mut conn = guard::<Tx>(request).await?;
+    R::before_delete(&login, &id, &mut conn).await?;
+    if !R::delete(&id, &mut conn).await? {
+        return Err(CrudError::NotFound);
+    }
+    R::after_delete(&login, &id, &mut conn).await?;
+    Ok(StdResponse::success(()))
+}
+
+async fn guard<'r, T: FromRequest<'r>>(request: &'r Request<'_>) -> Result<T, CrudError> {
+    match request.guard::<T>().await {
+        Outcome::Success(guard) => Ok(guard),
+        Outcome::Error((status, _)) | Outcome::Forward(status) => Err(CrudError::Guard(status)),
+    }
+}
+
+/// Returns the login of the request if it is granted `P`.
+async fn authenticated<T: Login + 'static, P: Permission>(request: &Request<'_>) -> Result<Authenticated<T, P>, CrudError> {
+    guard::<Authenticated<T, P>>(request).await
+}
+
+fn param<R: CrudResource>(request: &Request<'_>) -> Result<R::Id, CrudError> {
+    match request.param::<R::Id>(0) {
+        Some(Ok(id)) => Ok(id),
+        _ => Err(CrudError::NotFound),
+    }
+}
+
+async fn body<'r, T: DeserializeOwned>(request: &'r Request<'_>, data: Data<'r>) -> Result<T, CrudError> {
+    match Json::<T>::from_data(request, data).await {
+        rocket::data::Outcome::Success(Json(input)) => Ok(input),
+        rocket::data::Outcome::Error((status, e)) => Err(CrudError::Custom(status, e.to_string())),
+        rocket::data::Outcome::Forward((_, status)) => Err(CrudError::Guard(status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel_async::SimpleAsyncConnection;
+    use rocket::http::Status;
+    use rocket::local::asynchronous::{Client, LocalResponse};
+    use rocket::async_test;
+    use serde_json::{json, Value};
+    use ferrox_auth::testing::{AuthenticatedRequest, TestAuth, TestLogin};
+    use ferrox_env::EnvLoader;
+    use crate::auth::{define_role, RoleUser};
+    use crate::crud::{CrudError, CrudResource, Validate};
+    use crate::db::{DatabaseFairing, DbPool, PooledConnection, SoftDelete, SoftDeleteDsl};
+
+    define_role!(RoleAdmin, "ROLE_ADMIN");
+
+    diesel::table! {
+        ferrox_crud_test (id) {
+            id -> Integer,
+            name -> Text,
+            locked -> Bool,
+        }
+    }
+
+    crate::listable!(ferrox_crud_test (id) {
+        sort: [id, name],
+        filter: [locked],
+    });
+
+    #[derive(diesel::Queryable, serde::Serialize)]
+    struct Item {
+        id: i32,
+        name: String,
+        locked: bool,
+    }
+
+    #[derive(diesel::Insertable, serde::Deserialize)]
+    #[diesel(table_name = ferrox_crud_test)]
+    struct CreateItem {
+        name: String,
+        locked: bool,
+    }
+
+    impl Validate for CreateItem {
+        fn validate(&self) -> Result<(), String> {
+            if self.name.is_empty() {
+                return Err("name must not be empty".to_string());
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(diesel::AsChangeset, serde::Deserialize)]
+    #[diesel(table_name = ferrox_crud_test)]
+    struct UpdateItem {
+        name: Option<String>,
+    }
+
+    impl Validate for UpdateItem {
+        fn validate(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    struct ItemResource;
+
+    #[rocket::async_trait]
+    impl CrudResource for ItemResource {
+        type Login = TestLogin;
+        type Model = Item;
+        type Id = i32;
+        type Create = CreateItem;
+        type Update = UpdateItem;
+        type ListPermission = RoleUser;
+        type GetPermission = RoleUser;
+        type CreatePermission = RoleAdmin;
+        type UpdatePermission = RoleAdmin;
+        type DeletePermission = RoleAdmin;
+
+        async fn before_delete(_login: &TestLogin, id: &i32, conn: &mut PooledConnection) -> Result<(), CrudError> {
+            use diesel::{ExpressionMethods, QueryDsl};
+            use diesel_async::RunQueryDsl;
+
+            // Rolled back with the request if the item is locked
+            diesel::update(ferrox_crud_test::table.find(id)).set(ferrox_crud_test::name.eq("deleting")).execute(conn).await?;
+
+            let locked = ferrox_crud_test::table.find(id).select(ferrox_crud_test::locked).get_result::<bool>(conn).await?;
+            if locked {
+                return Err(CrudError::Custom(Status::Conflict, "Item is locked".to_string()));
+            }
+
+            Ok(())
+        }
+    }
+
+    crate::crud_store!(ItemResource, ferrox_crud_test);
+
+    diesel::table! {
+        ferrox_crud_soft_test (id) {
+            id -> Integer,
+            name -> Text,
+            deleted_at -> Nullable<Timestamptz>,
+        }
+    }
+
+    crate::listable!(ferrox_crud_soft_test (id) {
+        sort: [id],
+        filter: [],
+    });
+
+    impl SoftDelete for ferrox_crud_soft_test::table {
+        type DeletedAt = ferrox_crud_soft_test::deleted_at;
+    }
+
+    #[derive(diesel::Queryable, serde::Serialize)]
+    struct SoftItem {
+        id: i32,
+        name: String,
+        deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    }
+
+    #[derive(diesel::Insertable, serde::Deserialize)]
+    #[diesel(table_name = ferrox_crud_soft_test)]
+    struct CreateSoftItem {
+        name: String,
+    }
+
+    impl Validate for CreateSoftItem {
+        fn validate(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    #[derive(diesel::AsChangeset, serde::Deserialize)]
+    #[diesel(table_name = ferrox_crud_soft_test)]
+    struct UpdateSoftItem {
+        name: Option<String>,
+    }
+
+    impl Validate for UpdateSoftItem {
+        fn validate(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    struct SoftItemResource;
+
+    impl CrudResource for SoftItemResource {
+        type Login = TestLogin;
+        type Model = SoftItem;
+        type Id = i32;
+        type Create = CreateSoftItem;
+        type Update = UpdateSoftItem;
+        type ListPermission = RoleUser;
+        type GetPermission = RoleUser;
+        type CreatePermission = RoleUser;
+        type UpdatePermission = RoleUser;
+        type DeletePermission = RoleUser;
+    }
+
+    crate::crud_store!(SoftItemResource, ferrox_crud_soft_test, soft_delete);
+
+    async fn json(response: LocalResponse<'_>) -> Value {
+        serde_json::from_str(&response.into_string().await.unwrap()).unwrap()
+    }
+
+    #[async_test]
+    async fn test_crud() {
+        EnvLoader::load_test();
+        TestAuth::init();
+        std::env::set_var("BASE_URL", "http://localhost");
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        conn.batch_execute("
+            CREATE TABLE IF NOT EXISTS ferrox_crud_test (id SERIAL PRIMARY KEY, name TEXT NOT NULL, locked BOOL NOT NULL);
+            DELETE FROM ferrox_crud_test;
+        ").await.unwrap();
+
+        let client = Client::tracked(rocket::build().attach(DatabaseFairing::default()).mount("/items", ItemResource::routes())).await.unwrap();
+        let user = TestLogin::default().with_role::<RoleUser>().register();
+        let admin = TestLogin::default().with_role::<RoleAdmin>().register();
+
+        let response = client.post("/items").json(&json!({ "name": "a", "locked": false })).authenticated(user.token()).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client.post("/items").json(&json!({ "name": "", "locked": false })).authenticated(admin.token()).dispatch().await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        let response = client.post("/items").json(&json!({ "name": "a", "locked": false })).authenticated(admin.token()).dispatch().await;
+        assert_eq!(response.status(), Status::Created);
+        let id = json(response).await["data"]["id"].as_i64().unwrap();
+        let response = client.post("/items").json(&json!({ "name": "b", "locked": true })).authenticated(admin.token()).dispatch().await;
+        let locked_id = json(response).await["data"]["id"].as_i64().unwrap();
+
+        let response = client.patch(format!("/items/{}", id)).json(&json!({ "name": "c" })).authenticated(admin.token()).dispatch().await;
+        assert_eq!(json(response).await["data"]["name"], "c");
+        let response = client.patch(format!("/items/{}", id)).json(&json!({})).authenticated(admin.token()).dispatch().await;
+        assert_eq!(json(response).await["data"]["name"], "c");
+
+        let response = client.get("/items?sort=-name&filter.locked=false").authenticated(user.token()).dispatch().await;
+        let page = json(response).await;
+        assert_eq!(page["data"]["total"], 1);
+        assert_eq!(page["data"]["items"][0]["id"], id);
+
+        let response = client.delete(format!("/items/{}", locked_id)).authenticated(admin.token()).dispatch().await;
+        assert_eq!(response.status(), Status::Conflict);
+        let response = client.delete(format!("/items/{}", id)).authenticated(admin.token()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client.get(format!("/items/{}", id)).authenticated(user.token()).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get(format!("/items/{}", locked_id)).authenticated(user.token()).dispatch().await;
+        assert_eq!(json(response).await["data"]["name"], "b");
+    }
+
+    #[async_test]
+    async fn test_crud_soft_delete() {
+        EnvLoader::load_test();
+        TestAuth::init();
+        std::env::set_var("BASE_URL", "http://localhost");
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        conn.batch_execute("
+            CREATE TABLE IF NOT EXISTS ferrox_crud_soft_test (id SERIAL PRIMARY KEY, name TEXT NOT NULL, deleted_at TIMESTAMPTZ);
+            DELETE FROM ferrox_crud_soft_test;
+        ").await.unwrap();
+
+        let client = Client::tracked(rocket::build().attach(DatabaseFairing::default()).mount("/items", SoftItemResource::routes())).await.unwrap();
+        let user = TestLogin::default().with_role::<RoleUser>().register();
+
+        let response = client.post("/items").json(&json!({ "name": "a" })).authenticated(user.token()).dispatch().await;
+        let id = json(response).await["data"]["id"].as_i64().unwrap();
+        let response = client.post("/items").json(&json!({ "name": "b" })).authenticated(user.token()).dispatch().await;
+        let kept_id = json(response).await["data"]["id"].as_i64().unwrap();
+
+        let response = client.delete(format!("/items/{}", id)).authenticated(user.token()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.delete(format!("/items/{}", id)).authenticated(user.token()).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client.get(format!("/items/{}", id)).authenticated(user.token()).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.patch(format!("/items/{}", id)).json(&json!({ "name": "c" })).authenticated(user.token()).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let page = json(client.get("/items").authenticated(user.token()).dispatch().await).await;
+        assert_eq!(page["data"]["total"], 1);
+        assert_eq!(page["data"]["items"][0]["id"], kept_id);
+
+        // The row is kept and can be restored
+        let query = diesel::QueryDsl::find(ferrox_crud_soft_test::table, id as i32);
+        let restored = diesel_async::RunQueryDsl::execute(query.restore(), &mut conn).await.unwrap();
+        assert_eq!(restored, 1);
+    }
+}
@@ -0,0 +1,210 @@
+//! Contains the [DbError] of handlers using the database, responding with the matching status.
+//!
+//! ```rust,ignore
+//! #[get("/users/<id>")]
+//! async fn get_user(id: i32) -> Result<StdResponse<User>, DbError> {
+//!     let mut conn = DbPool::get_conn().await?;
+//!     Ok(StdResponse::success(users::table.find(id).first(&mut conn).await?))
+//! }
+//! ```
+//!
+//! With the `sentry` feature, errors responding with a 5xx status are reported to sentry.
+//!
+//! Enabled through the `db` feature.
+
+use std::fmt::{Display, Formatter};
+
+use diesel::result::{DatabaseErrorKind, Error};
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::response::Responder;
+use rocket::{error, Request};
+use crate::db::DbPoolError;
+#[cfg(feature = "postgres")]
+use crate::db::VersionError;
+use crate::std_response::StdResponse;
+
+/// Error of a query or of getting a connection from the pool.
+///
+/// | Error                      | Status                        |
+/// |----------------------------|-------------------------------|
+/// | Row not found              | [Status::NotFound]            |
+/// | Unique violation           | [Status::Conflict]            |
+/// | Version conflict           | [Status::Conflict]            |
+/// | Foreign key violation      | [Status::UnprocessableEntity] |
+/// | Pool timeout               | [Status::ServiceUnavailable]  |
+/// | Everything else            | [Status::InternalServerError] |
+#[derive(Debug)]
+pub enum DbError {
+    /// The query failed.
+    Query(Error),
+    /// No connection could be taken from the pool.
+    Pool(DbPoolError),
+    /// The row was changed since its version was read, see [crate::db::VersionedDsl].
+    VersionConflict,
+}
+
+impl DbError {
+    /// Returns the status this error responds with.
+    pub fn status(&self) -> Status {
+        match self {
+            DbError::Query(Error::NotFound) => Status::NotFound,
+            DbError::Query(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Status::Conflict,
+            DbError::VersionConflict => Status::Conflict,
+            DbError::Query(Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _)) => Status::UnprocessableEntity,
+            DbError::Pool(DbPoolError::Timeout(_)) => Status::ServiceUnavailable,
+            _ => Status::InternalServerError,
+        }
+    }
+
+    /// Returns the message sent to the client, which does not leak details of internal errors.
+    fn message(&self) -> String {
+        match self {
+            DbError::Query(Error::NotFound) => "Not found".to_string(),
+            DbError::Query(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info)) => match info.constraint_name() {
+                Some(constraint) => format!("Already exists: {}", constraint),
+                None => "Already exists".to_string(),
+            },
+            DbError::Query(Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info)) => match info.constraint_name() {
+                Some(constraint) => format!("Invalid reference: {}", constraint),
+                None => "Invalid reference".to_string(),
+            },
+            DbError::Pool(DbPoolError::Timeout(_)) => "Database unavailable".to_string(),
+            DbError::VersionConflict => "The row was changed by another request".to_string(),
+            _ => "Internal server error".to_string(),
+        }
+    }
+}
+
+impl Display for DbError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbError::Query(e) => write!(f, "Query failed: {}", e),
+            DbError::Pool(e) => write!(f, "Failed to get connection: {}", e),
+            DbError::VersionConflict => write!(f, "The row was changed by another request"),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<Error> for DbError {
+    fn from(e: Error) -> Self {
+        DbError::Query(e)
+    }
+}
+
+impl From<DbPoolError> for DbError {
+    fn from(e: DbPoolError) -> Self {
+        DbError::Pool(e)
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<VersionError> for DbError {
+    fn from(e: VersionError) -> Self {
+        match e {
+            VersionError::Conflict => DbError::VersionConflict,
+            VersionError::Query(e) => DbError::Query(e),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'r> for DbError {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'r> {
+        let status = self.status();
+        if status.class().is_server_error() {
+            error!("Database error in {}: {}", request.uri(), self);
+            #[cfg(feature = "sentry")]
+            crate::sentry::sentry::capture_message(
+                &format!("Database error in {}: {}", request.uri(), self),
+                crate::sentry::sentry::Level::Error,
+            );
+        }
+
+        Custom(status, StdResponse::<()>::failure(&self.message())).respond_to(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deadpool::managed::TimeoutType;
+    use diesel::result::{DatabaseErrorInformation, DatabaseErrorKind, Error};
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::{async_test, get, routes};
+    use serde_json::Value;
+    use crate::db::DbPoolError;
+    use crate::db_error::DbError;
+
+    struct Constraint(&'static str);
+
+    impl DatabaseErrorInformation for Constraint {
+        fn message(&self) -> &str {
+            "violation"
+        }
+
+        fn details(&self) -> Option<&str> {
+            None
+        }
+
+        fn hint(&self) -> Option<&str> {
+            None
+        }
+
+        fn table_name(&self) -> Option<&str> {
+            None
+        }
+
+        fn column_name(&self) -> Option<&str> {
+            None
+        }
+
+        fn constraint_name(&self) -> Option<&str> {
+            Some(self.0)
+        }
+
+        fn statement_position(&self) -> Option<i32> {
+            None
+        }
+    }
+
+    #[get("/unique")]
+    fn unique() -> Result<(), DbError> {
+        Err(Error::DatabaseError(DatabaseErrorKind::UniqueViolation, Box::new(Constraint("users_email_key"))).into())
+    }
+
+    #[get("/broken")]
+    fn broken() -> Result<(), DbError> {
+        Err(Error::BrokenTransactionManager.into())
+    }
+
+    #[async_test]
+    async fn test_db_error() {
+        let foreign_key = Error::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, Box::new(Constraint("posts_user_id_fkey")));
+        assert_eq!(DbError::from(Error::NotFound).status(), Status::NotFound);
+        assert_eq!(DbError::from(foreign_key).status(), Status::UnprocessableEntity);
+        assert_eq!(DbError::from(DbPoolError::Timeout(TimeoutType::Wait)).status(), Status::ServiceUnavailable);
+        assert_eq!(DbError::from(DbPoolError::Closed).status(), Status::InternalServerError);
+
+        let client = Client::tracked(rocket::build().mount("/", routes![unique, broken])).await.unwrap();
+        let response = client.get("/unique").dispatch().await;
+        assert_eq!(response.status(), Status::Conflict);
+        let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(body["msg"], "Already exists: users_email_key");
+
+        let response = client.get("/broken").dispatch().await;
+        assert_eq!(response.status(), Status::InternalServerError);
+        let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(body["msg"], "Internal server error");
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn test_version_conflict() {
+        let conflict = DbError::from(crate::db::VersionError::Conflict);
+        assert_eq!(conflict.status(), Status::Conflict);
+        assert_eq!(conflict.message(), "The row was changed by another request");
+        assert_eq!(DbError::from(crate::db::VersionError::Query(Error::NotFound)).status(), Status::NotFound);
+    }
+}
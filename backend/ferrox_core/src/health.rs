@@ -0,0 +1,229 @@
+//! Contains the [HealthFairing] mounting `/health/live` and `/health/ready` for load balancers.
+//!
+//! `/health/live` always responds with 200 while the server is running.
+//! `/health/ready` runs all [HealthCheck]s and responds with 200 if all are healthy and 503 otherwise.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::futures::future::join_all;
+use rocket::http::Status;
+use rocket::response::content::RawJson;
+use rocket::response::status::Custom;
+use rocket::{async_trait, get, routes, Build, Rocket, State};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Result of a single [HealthCheck].
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckReport {
+    /// Whether the subsystem is healthy.
+    pub healthy: bool,
+    /// Error of an unhealthy subsystem.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Additional information, e.g. pool statistics.
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub details: Value,
+}
+
+impl CheckReport {
+    /// Creates a healthy report with `details`.
+    pub fn healthy(details: Value) -> Self {
+        CheckReport {
+            healthy: true,
+            error: None,
+            details,
+        }
+    }
+
+    /// Creates an unhealthy report with `error`.
+    pub fn unhealthy(error: &str) -> Self {
+        CheckReport {
+            healthy: false,
+            error: Some(error.to_string()),
+            details: Value::Null,
+        }
+    }
+}
+
+/// Check of a subsystem, aggregated by `/health/ready`.
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    /// Name of the subsystem in the report.
+    fn name(&self) -> &'static str;
+
+    /// Checks the subsystem.
+    async fn check(&self) -> CheckReport;
+}
+
+/// Report of `/health/ready`.
+#[derive(Serialize)]
+pub struct HealthReport {
+    /// Whether all checks are healthy.
+    pub healthy: bool,
+    /// Reports of all checks by name.
+    pub checks: BTreeMap<&'static str, CheckReport>,
+}
+
+/// [HealthCheck] of the [crate::db::DbPool], reporting pool statistics and the round-trip latency.
+#[cfg(feature = "db")]
+pub struct DbHealthCheck;
+
+#[cfg(feature = "db")]
+#[async_trait]
+impl HealthCheck for DbHealthCheck {
+    fn name(&self) -> &'static str {
+        "db"
+    }
+
+    async fn check(&self) -> CheckReport {
+        let health = crate::db::DbPool::health().await;
+        let details = serde_json::json!({
+            "max_size": health.max_size,
+            "size": health.size,
+            "available": health.available,
+            "waiting": health.waiting,
+            "latency_ms": health.latency.map(|latency| latency.as_secs_f64() * 1000.0),
+        });
+
+        CheckReport {
+            healthy: health.is_healthy(),
+            error: health.error,
+            details,
+        }
+    }
+}
+
+/// Time [MailerHealthCheck] waits for the smtp server before reporting it as unhealthy.
+#[cfg(feature = "mailer")]
+pub const MAILER_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// [HealthCheck] of the [crate::mailer::Mailer], testing the smtp connection.
+///
+/// Reports unhealthy if `MAILER_DSN` is not set or the server does not answer within [MAILER_CHECK_TIMEOUT].
+#[cfg(feature = "mailer")]
+pub struct MailerHealthCheck;
+
+#[cfg(feature = "mailer")]
+#[async_trait]
+impl HealthCheck for MailerHealthCheck {
+    fn name(&self) -> &'static str {
+        "mailer"
+    }
+
+    async fn check(&self) -> CheckReport {
+        if std::env::var("MAILER_DSN").is_err() {
+            return CheckReport::unhealthy("Mailer is not configured");
+        }
+
+        let test = rocket::tokio::task::spawn_blocking(|| crate::mailer::Mailer::get_or_init().test_connection());
+        let Ok(result) = rocket::tokio::time::timeout(MAILER_CHECK_TIMEOUT, test).await else {
+            return CheckReport::unhealthy("Mailer did not respond in time");
+        };
+
+        match result {
+            Ok(Ok(true)) => CheckReport::healthy(Value::Null),
+            Ok(Ok(false)) => CheckReport::unhealthy("Mailer noop failed"),
+            Ok(Err(e)) => CheckReport::unhealthy(&e.to_string()),
+            Err(e) => CheckReport::unhealthy(&e.to_string()),
+        }
+    }
+}
+
+struct HealthChecks(Vec<Box<dyn HealthCheck>>);
+
+/// Fairing mounting the health routes at `/health`.
+#[derive(Default)]
+pub struct HealthFairing {
+    checks: Mutex<Vec<Box<dyn HealthCheck>>>,
+}
+
+impl HealthFairing {
+    /// Adds a [HealthCheck] to `/health/ready`.
+    pub fn with_check(self, check: impl HealthCheck + 'static) -> Self {
+        self.checks.lock().unwrap().push(Box::new(check));
+        self
+    }
+}
+
+#[async_trait]
+impl Fairing for HealthFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "ferrox-health",
+            kind: Kind::Ignite,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let checks = std::mem::take(&mut *self.checks.lock().unwrap());
+
+        Ok(rocket
+            .manage(HealthChecks(checks))
+            .mount("/health", routes![live, ready]))
+    }
+}
+
+#[get("/live")]
+fn live() -> RawJson<&'static str> {
+    RawJson(r#"{"healthy":true}"#)
+}
+
+#[get("/ready")]
+async fn ready(checks: &State<HealthChecks>) -> Custom<RawJson<String>> {
+    let reports = join_all(checks.0.iter().map(|check| async move { (check.name(), check.check().await) })).await;
+    let report = HealthReport {
+        healthy: reports.iter().all(|(_, report)| report.healthy),
+        checks: reports.into_iter().collect(),
+    };
+
+    let status = if report.healthy { Status::Ok } else { Status::ServiceUnavailable };
+    Custom(status, RawJson(serde_json::to_string(&report).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::{async_test, async_trait};
+    use serde_json::Value;
+    use crate::health::{CheckReport, HealthCheck, HealthFairing};
+
+    struct Check(bool);
+
+    #[async_trait]
+    impl HealthCheck for Check {
+        fn name(&self) -> &'static str {
+            if self.0 { "up" } else { "down" }
+        }
+
+        async fn check(&self) -> CheckReport {
+            if self.0 { CheckReport::healthy(Value::Null) } else { CheckReport::unhealthy("down") }
+        }
+    }
+
+    #[async_test]
+    async fn test_health() {
+        let client = Client::tracked(rocket::build().attach(HealthFairing::default().with_check(Check(true)))).await.unwrap();
+        assert_eq!(client.get("/health/live").dispatch().await.status(), Status::Ok);
+        assert_eq!(client.get("/health/ready").dispatch().await.status(), Status::Ok);
+
+        let fairing = HealthFairing::default().with_check(Check(true)).with_check(Check(false));
+        let client = Client::tracked(rocket::build().attach(fairing)).await.unwrap();
+        let response = client.get("/health/ready").dispatch().await;
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        let report: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(report["checks"]["down"]["error"], "down");
+        assert_eq!(report["checks"]["up"]["healthy"], true);
+    }
+
+    #[cfg(feature = "mailer")]
+    #[async_test]
+    async fn test_mailer_health() {
+        ferrox_env::EnvLoader::load_test();
+        let report = crate::health::MailerHealthCheck.check().await;
+        assert!(report.healthy, "{:?}", report.error);
+    }
+}
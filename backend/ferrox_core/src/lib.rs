@@ -1,10 +1,21 @@
 //! Crate containing tons of common backend functionality for [rocket].
 //!
 //! This crate reexports [lettre].
+//!
+//! Database features use the `postgres` backend and `auth` reads the token from a cookie by default.
+//! To use SQLite or only read the token from a header, disable the default features and select the backend and token source explicitly.
+//! `jobs`, `pagination`, `crud` and `scheduler` require `postgres`, `auth` requires `auth-from-cookie` or `auth-from-header`.
 
 #![deny(missing_docs)]
 #![forbid(unsafe_code)]
 
+#[cfg(all(feature = "db", not(any(feature = "postgres", feature = "sqlite"))))]
+compile_error!("The db feature of ferrox_core requires either the postgres or the sqlite feature");
+#[cfg(all(feature = "pagination", not(feature = "postgres")))]
+compile_error!("The pagination feature of ferrox_core requires the postgres feature");
+#[cfg(all(feature = "scheduler", not(feature = "postgres")))]
+compile_error!("The scheduler feature of ferrox_core requires the postgres feature");
+
 #[cfg(feature = "sentry")]
 pub extern crate ferrox_sentry as sentry;
 #[cfg(feature = "env")]
@@ -17,6 +28,8 @@ pub extern crate ferrox_auth as auth;
 pub extern crate ferrox_db as db;
 #[cfg(feature = "db_types")]
 pub extern crate ferrox_db_types as db_types;
+#[cfg(feature = "jobs")]
+pub extern crate ferrox_jobs as jobs;
 
 pub mod prelude {
     //! Contains reexports of all modules for easy importing.
@@ -29,14 +42,27 @@ pub mod prelude {
     #[cfg(feature = "auth")]
     pub use crate::auth::*;
     pub use crate::cors::*;
+    #[cfg(feature = "crud")]
+    pub use crate::crud::*;
     #[cfg(feature = "db")]
     pub use crate::db::*;
+    #[cfg(feature = "db")]
+    pub use crate::db_error::*;
     #[cfg(feature = "db_types")]
     pub use crate::db_types::*;
     #[cfg(feature = "env")]
     pub use crate::env::*;
+    pub use crate::health::*;
+    #[cfg(feature = "jobs")]
+    pub use crate::jobs::*;
     #[cfg(feature = "mailer")]
     pub use crate::mailer::*;
+    #[cfg(feature = "metrics")]
+    pub use crate::metrics::*;
+    #[cfg(feature = "pagination")]
+    pub use crate::pagination::*;
+    #[cfg(feature = "scheduler")]
+    pub use crate::scheduler::*;
     #[cfg(feature = "sentry")]
     pub use crate::sentry::*;
     pub use crate::std_response::*;
@@ -46,3 +72,14 @@ pub mod prelude {
 pub mod std_response;
 pub mod url_generator;
 pub mod cors;
+pub mod health;
+#[cfg(feature = "crud")]
+pub mod crud;
+#[cfg(feature = "db")]
+pub mod db_error;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "pagination")]
+pub mod pagination;
+#[cfg(feature = "scheduler")]
+pub mod scheduler;
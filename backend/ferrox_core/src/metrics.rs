@@ -0,0 +1,111 @@
+//! Contains the [MetricsFairing] collecting HTTP request metrics and mounting `/metrics`.
+//!
+//! `/metrics` exports all metrics of the [prometheus::default_registry] in Prometheus text format.
+//! With the `db` feature, this includes the pool and query metrics of [crate::db::DbPool].
+//!
+//! Enabled through the `metrics` feature.
+
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use prometheus::{register, Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, TextEncoder};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::ContentType;
+use rocket::{async_trait, get, routes, Build, Data, Request, Response, Rocket};
+
+struct HttpMetrics {
+    requests: IntCounterVec,
+    duration: HistogramVec,
+}
+
+static HTTP_METRICS: OnceLock<HttpMetrics> = OnceLock::new();
+
+fn metrics() -> &'static HttpMetrics {
+    HTTP_METRICS.get_or_init(|| {
+        let labels = ["method", "route", "status"];
+        let metrics = HttpMetrics {
+            requests: IntCounterVec::new(Opts::new(
+                "http_requests_total",
+                "Number of handled requests",
+            ), &labels).unwrap(),
+            duration: HistogramVec::new(HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to handle a request",
+            ), &labels).unwrap(),
+        };
+
+        register(Box::new(metrics.requests.clone())).unwrap();
+        register(Box::new(metrics.duration.clone())).unwrap();
+
+        metrics
+    })
+}
+
+struct RequestStart(Instant);
+
+/// Fairing recording the count and duration of requests by method, route and status.
+///
+/// Also mounts the `/metrics` route.
+#[derive(Default)]
+pub struct MetricsFairing;
+
+#[async_trait]
+impl Fairing for MetricsFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "ferrox-metrics",
+            kind: Kind::Ignite | Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        metrics();
+        Ok(rocket.mount("/", routes![export_metrics]))
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _data: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let started = request.local_cache(|| RequestStart(Instant::now())).0;
+        // Use the route template instead of the path to keep the label cardinality low
+        let route = request.route().map(|route| route.uri.to_string()).unwrap_or_else(|| "unmatched".to_string());
+        let status = response.status().code.to_string();
+        let labels = [request.method().as_str(), route.as_str(), status.as_str()];
+
+        let metrics = metrics();
+        metrics.requests.with_label_values(&labels).inc();
+        metrics.duration.with_label_values(&labels).observe(started.elapsed().as_secs_f64());
+    }
+}
+
+#[get("/metrics")]
+fn export_metrics() -> (ContentType, Vec<u8>) {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer).unwrap();
+    (ContentType::Plain, buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::{async_test, get, routes};
+    use crate::metrics::MetricsFairing;
+
+    #[get("/item/<_id>")]
+    fn item(_id: u32) {}
+
+    #[async_test]
+    async fn test_metrics() {
+        let client = Client::tracked(rocket::build().attach(MetricsFairing).mount("/", routes![item])).await.unwrap();
+        assert_eq!(client.get("/item/1").dispatch().await.status(), Status::Ok);
+        assert_eq!(client.get("/item/2").dispatch().await.status(), Status::Ok);
+
+        let response = client.get("/metrics").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.into_string().await.unwrap();
+        assert!(body.contains(r#"http_requests_total{method="GET",route="/item/<_id>",status="200"} 2"#));
+    }
+}
@@ -0,0 +1,658 @@
+//! Contains the [Pagination] query parameters of list endpoints and the [Page] they respond with.
+//!
+//! Tables are made listable through [crate::listable], which whitelists the columns clients may sort and filter by:
+//! ```rust,ignore
+//! ferrox_core::listable!(users (id) {
+//!     sort: [id, name, created_at],
+//!     filter: [name, role],
+//! });
+//!
+//! #[get("/users?<pagination..>")]
+//! async fn list_users(uri: &Origin<'_>, pagination: Pagination, mut conn: DbConn) -> Result<Page<User>, PaginationError> {
+//!     pagination.load(|| users::table.into_boxed(), uri, &mut conn).await
+//! }
+//! ```
+//!
+//! Requests with `page` are paginated by offset, all others by keyset through the `cursor` of the previous page, e.g.
+//! `/users?sort=-created_at&filter.role=admin&cursor=...`.
+//!
+//! Enabled through the `pagination` feature.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+
+use diesel::dsl::{sql, CountStar};
+use diesel::expression::SqlLiteral;
+use diesel::query_dsl::methods::{FilterDsl, LimitDsl, OffsetDsl, SelectDsl, ThenOrderDsl};
+use diesel::sql_types::{Bool, Nullable, Text};
+use diesel::{BoolExpressionMethods, BoxableExpression, Table};
+use diesel_async::methods::LoadQuery;
+use diesel_async::RunQueryDsl;
+use rocket::form::{DataField, FromForm, FromFormField, Options, ValueField};
+use rocket::http::uri::Origin;
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::response::Responder;
+use rocket::{async_trait, Request};
+use serde::Serialize;
+use crate::db::{DbBackend, DbConnection, PooledConnection};
+use crate::db_error::DbError;
+use crate::std_response::StdResponse;
+use crate::url_generator::UrlGenerator;
+
+/// Default value of `per_page`.
+pub const DEFAULT_PER_PAGE: i64 = 20;
+/// Maximum value of `per_page`.
+pub const MAX_PER_PAGE: i64 = 100;
+
+type Condition<T> = Box<dyn BoxableExpression<T, DbBackend, SqlType = Bool>>;
+
+/// Column of a [Listable] table.
+#[derive(Clone, Copy, Debug)]
+pub struct ListColumn {
+    /// Name of the column.
+    pub name: &'static str,
+    /// Postgres type values of the column are cast to, see [SqlCast].
+    pub cast: &'static str,
+    /// Validates a value of the column, see [SqlCast::parse].
+    pub parse: fn(&str) -> Option<String>,
+}
+
+/// Table which can be listed through [Pagination::load].
+///
+/// Implement this through [crate::listable].
+pub trait Listable: Table + Send + 'static {
+    /// Name of the table in SQL.
+    const NAME: &'static str;
+    /// Primary key, used as tiebreaker for sorting and in the cursor.
+    const PRIMARY_KEY: ListColumn;
+    /// Columns clients may sort by.
+    const SORTABLE: &'static [ListColumn];
+    /// Columns clients may filter by.
+    const FILTERABLE: &'static [ListColumn];
+
+    /// Returns the quoted and qualified name of `column`.
+    fn column_sql(column: &ListColumn) -> String {
+        format!("\"{}\".\"{}\"", Self::NAME, column.name)
+    }
+}
+
+/// Implements [Listable] for a table declared with [diesel::table].
+///
+/// Takes the name of the table, its primary key and the columns clients may sort and filter by.
+/// The name of the table has to match its name in SQL.
+#[macro_export]
+macro_rules! listable {
+    ($table:ident ($primary_key:ident) { sort: [$($sort:ident),* $(,)?], filter: [$($filter:ident),* $(,)?] $(,)? }) => {
+        impl $crate::pagination::Listable for $table::table {
+            const NAME: &'static str = stringify!($table);
+            const PRIMARY_KEY: $crate::pagination::ListColumn = $crate::list_column!($table, $primary_key);
+            const SORTABLE: &'static [$crate::pagination::ListColumn] = &[$($crate::list_column!($table, $sort)),*];
+            const FILTERABLE: &'static [$crate::pagination::ListColumn] = &[$($crate::list_column!($table, $filter)),*];
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! list_column {
+    ($table:ident, $column:ident) => {
+        $crate::pagination::ListColumn {
+            name: <$table::$column as ::diesel::Column>::NAME,
+            cast: <<$table::$column as ::diesel::Expression>::SqlType as $crate::pagination::SqlCast>::CAST,
+            parse: <<$table::$column as ::diesel::Expression>::SqlType as $crate::pagination::SqlCast>::parse,
+        }
+    };
+}
+
+/// SQL type which values from query parameters can be cast to.
+pub trait SqlCast {
+    /// Name of the type in Postgres.
+    const CAST: &'static str;
+
+    /// Parses `value` and returns it in a form Postgres accepts for [Self::CAST], or `None` if it is invalid.
+    fn parse(value: &str) -> Option<String>;
+}
+
+macro_rules! sql_cast {
+    ($($sql_type:ty => $cast:literal, $parse:expr);* $(;)?) => {
+        $(impl SqlCast for $sql_type {
+            const CAST: &'static str = $cast;
+
+            fn parse(value: &str) -> Option<String> {
+                let parse: fn(&str) -> Option<String> = $parse;
+                parse(value)
+            }
+        })*
+    };
+}
+
+sql_cast! {
+    diesel::sql_types::SmallInt => "int2", |value| value.parse::<i16>().ok().map(|value| value.to_string());
+    diesel::sql_types::Integer => "int4", |value| value.parse::<i32>().ok().map(|value| value.to_string());
+    diesel::sql_types::BigInt => "int8", |value| value.parse::<i64>().ok().map(|value| value.to_string());
+    diesel::sql_types::Float => "float4", |value| value.parse::<f32>().ok().map(|value| value.to_string());
+    diesel::sql_types::Double => "float8", |value| value.parse::<f64>().ok().map(|value| value.to_string());
+    diesel::sql_types::Numeric => "numeric", parse_numeric;
+    diesel::sql_types::Bool => "bool", |value| match value {
+        "true" | "t" | "1" => Some("true".to_string()),
+        "false" | "f" | "0" => Some("false".to_string()),
+        _ => None,
+    };
+    diesel::sql_types::Text => "text", |value| Some(value.to_string());
+    diesel::sql_types::Date => "date", |value| chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().map(|date| date.to_string());
+    diesel::sql_types::Timestamp => "timestamp", |value| {
+        chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+            .or_else(|_| chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f"))
+            .ok()
+            .map(|timestamp| timestamp.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+    };
+    // Postgres formats timestamps with a space and an offset without minutes when casting them to text
+    diesel::sql_types::Timestamptz => "timestamptz", |value| {
+        chrono::DateTime::parse_from_rfc3339(value)
+            .or_else(|_| chrono::DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f%#z"))
+            .ok()
+            .map(|timestamp| timestamp.to_rfc3339())
+    };
+    diesel::sql_types::Uuid => "uuid", |value| uuid::Uuid::parse_str(value).ok().map(|uuid| uuid.to_string());
+}
+
+impl<T: SqlCast + diesel::sql_types::SqlType> SqlCast for diesel::sql_types::Nullable<T> {
+    const CAST: &'static str = T::CAST;
+
+    fn parse(value: &str) -> Option<String> {
+        T::parse(value)
+    }
+}
+
+/// Accepts decimal numbers with an optional sign, fraction and exponent, e.g. `-1.5e3`.
+fn parse_numeric(value: &str) -> Option<String> {
+    let digits = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit());
+    let (mantissa, exponent) = match value.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (value, None),
+    };
+    let mantissa = mantissa.strip_prefix(['+', '-']).unwrap_or(mantissa);
+    let valid_mantissa = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (digits(integer) || integer.is_empty()) && (digits(fraction) || (fraction.is_empty() && digits(integer))),
+        None => digits(mantissa),
+    };
+    let valid_exponent = exponent.is_none_or(|exponent| digits(exponent.strip_prefix(['+', '-']).unwrap_or(exponent)));
+
+    (valid_mantissa && valid_exponent).then(|| value.to_string())
+}
+
+/// Errors of [Pagination::load].
+#[derive(Debug)]
+pub enum PaginationError {
+    /// The column is not sortable.
+    UnknownSortColumn(String),
+    /// The column is not filterable.
+    UnknownFilterColumn(String),
+    /// The value of the filter of the column cannot be parsed as the type of the column.
+    InvalidFilterValue(String),
+    /// The cursor was not returned for the requested sort.
+    InvalidCursor,
+    /// The page is not positive or its offset is out of range.
+    InvalidPage(i64),
+    /// Loading the page failed.
+    Query(DbError),
+}
+
+impl Display for PaginationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaginationError::UnknownSortColumn(column) => write!(f, "Cannot sort by {}", column),
+            PaginationError::UnknownFilterColumn(column) => write!(f, "Cannot filter by {}", column),
+            PaginationError::InvalidFilterValue(column) => write!(f, "Invalid value for filter {}", column),
+            PaginationError::InvalidCursor => write!(f, "Invalid cursor"),
+            PaginationError::InvalidPage(page) => write!(f, "Invalid page {}", page),
+            PaginationError::Query(e) => write!(f, "Failed to load page: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PaginationError {}
+
+impl From<diesel::result::Error> for PaginationError {
+    fn from(e: diesel::result::Error) -> Self {
+        PaginationError::Query(e.into())
+    }
+}
+
+impl<'r> Responder<'r, 'r> for PaginationError {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'r> {
+        match self {
+            PaginationError::Query(e) => e.respond_to(request),
+            _ => Custom(Status::BadRequest, StdResponse::<()>::failure(&self.to_string())).respond_to(request),
+        }
+    }
+}
+
+/// Query parameters of list endpoints, see the [module](self) documentation.
+#[derive(FromForm, Default, Debug)]
+pub struct Pagination {
+    /// Page starting at 1, selects offset pagination.
+    pub page: Option<i64>,
+    /// Items per page, defaults to [DEFAULT_PER_PAGE] and is limited to [MAX_PER_PAGE].
+    pub per_page: Option<i64>,
+    /// Cursor of the previous page for keyset pagination, see [Page::next_cursor].
+    pub cursor: Option<String>,
+    /// Columns to sort by.
+    pub sort: Sort,
+    /// Values to filter by.
+    pub filter: Filter,
+}
+
+/// Query parameter `sort`, a comma separated list of columns prefixed with `-` for descending order, e.g. `-created_at,name`.
+#[derive(Default, Debug)]
+pub struct Sort(pub String);
+
+#[async_trait]
+impl<'r> FromFormField<'r> for Sort {
+    fn from_value(field: ValueField<'r>) -> rocket::form::Result<'r, Self> {
+        Ok(Sort(field.value.to_string()))
+    }
+
+    fn default() -> Option<Self> {
+        Some(Sort(String::new()))
+    }
+}
+
+/// Query parameters `filter.<column>`, filtering by equality.
+#[derive(Default, Debug)]
+pub struct Filter(pub HashMap<String, String>);
+
+#[async_trait]
+impl<'r> FromForm<'r> for Filter {
+    type Context = <HashMap<String, String> as FromForm<'r>>::Context;
+
+    fn init(opts: Options) -> Self::Context {
+        <HashMap<String, String> as FromForm<'r>>::init(opts)
+    }
+
+    fn push_value(ctxt: &mut Self::Context, field: ValueField<'r>) {
+        <HashMap<String, String> as FromForm<'r>>::push_value(ctxt, field)
+    }
+
+    async fn push_data(ctxt: &mut Self::Context, field: DataField<'r, '_>) {
+        <HashMap<String, String> as FromForm<'r>>::push_data(ctxt, field).await
+    }
+
+    fn finalize(ctxt: Self::Context) -> rocket::form::Result<'r, Self> {
+        <HashMap<String, String> as FromForm<'r>>::finalize(ctxt).map(Filter)
+    }
+}
+
+/// Page of a list endpoint.
+///
+/// Responds as [StdResponse].
+#[derive(Serialize, Debug)]
+pub struct Page<T: Serialize> {
+    /// Items of the page.
+    pub items: Vec<T>,
+    /// Total amount of items matching the filters.
+    pub total: i64,
+    /// Cursor for the next page, `None` on the last page.
+    ///
+    /// Contains the sort values of the last item, so it is only valid for the same sort.
+    pub next_cursor: Option<String>,
+    /// Links to this and the adjacent pages.
+    pub links: PageLinks,
+}
+
+/// Absolute links to a [Page] and the adjacent pages.
+#[derive(Serialize, Debug)]
+pub struct PageLinks {
+    /// Link to the current page.
+    #[serde(rename = "self")]
+    pub current: String,
+    /// Link to the next page, `None` on the last page.
+    pub next: Option<String>,
+    /// Link to the previous page, only available with offset pagination.
+    pub prev: Option<String>,
+}
+
+impl<'r, T: Serialize> Responder<'r, 'r> for Page<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'r> {
+        StdResponse::success(self).respond_to(request)
+    }
+}
+
+impl Pagination {
+    /// Returns the validated items per page.
+    pub fn per_page(&self) -> i64 {
+        self.per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE)
+    }
+
+    /// Loads the page of the items from `query`, filtered and sorted as requested.
+    ///
+    /// `query` is called for the items and the total and usually returns the boxed table, further conditions can be added to it.
+    /// `uri` is the uri of the request, used to generate the links through [UrlGenerator].
+    ///
+    /// Keyset pagination requires sortable columns to be non-null, as rows with null values are skipped.
+    /// Filter values and the cursor are parsed as the types of their columns first, so invalid values respond with [Status::BadRequest].
+    pub fn load<'c, T, U, Q, F>(&self, query: F, uri: &Origin<'_>, conn: &'c mut PooledConnection) -> impl Future<Output = Result<Page<U>, PaginationError>> + Send + 'c
+    where
+        T: Listable,
+        F: Fn() -> Q,
+        Q: FilterDsl<Condition<T>, Output = Q> + ThenOrderDsl<SqlLiteral<Text>, Output = Q>
+            + LimitDsl<Output = Q> + OffsetDsl<Output = Q>
+            + SelectDsl<CountStar> + SelectDsl<(T::AllColumns, SqlLiteral<Text>)>,
+        <Q as SelectDsl<CountStar>>::Output: LoadQuery<'static, DbConnection, i64> + Send + 'static,
+        <Q as SelectDsl<(T::AllColumns, SqlLiteral<Text>)>>::Output: LoadQuery<'static, DbConnection, (U, String)> + Send + 'static,
+        U: Serialize + Send + 'c,
+    {
+        // The queries and links are built before the future, so it does not borrow the request
+        let queries = self.queries::<T, Q, F>(query);
+        let links = PageLinkBuilder::new(uri);
+        let page = self.page;
+        let per_page = self.per_page();
+        let cursor = self.cursor.clone();
+
+        async move {
+            let (total, items) = queries?;
+            let total = total.get_result::<i64>(conn).await?;
+            let mut rows = items.load::<(U, String)>(conn).await?;
+
+            let has_next = rows.len() as i64 > per_page;
+            rows.truncate(per_page as usize);
+            let next_cursor = if has_next { rows.last().map(|(_, cursor)| cursor.clone()) } else { None };
+
+            let links = match page {
+                Some(page) => PageLinks {
+                    current: links.link(Some(("page", page.to_string()))),
+                    next: page.checked_add(1).filter(|_| has_next).map(|next| links.link(Some(("page", next.to_string())))),
+                    prev: (page > 1).then(|| links.link(Some(("page", (page - 1).to_string())))),
+                },
+                None => PageLinks {
+                    current: links.link(cursor.map(|cursor| ("cursor", cursor))),
+                    next: next_cursor.clone().map(|cursor| links.link(Some(("cursor", cursor)))),
+                    prev: None,
+                },
+            };
+
+            Ok(Page {
+                items: rows.into_iter().map(|(item, _)| item).collect(),
+                total,
+                next_cursor,
+                links,
+            })
+        }
+    }
+
+    /// Returns the offset of the requested page, or `None` for keyset pagination.
+    fn offset(&self) -> Result<Option<i64>, PaginationError> {
+        self.page
+            .map(|page| (page - 1).checked_mul(self.per_page()).filter(|_| page >= 1).ok_or(PaginationError::InvalidPage(page)))
+            .transpose()
+    }
+
+    /// Builds the queries of the total and the items of the page.
+    #[allow(clippy::type_complexity)]
+    fn queries<T, Q, F>(&self, query: F) -> Result<(<Q as SelectDsl<CountStar>>::Output, <Q as SelectDsl<(T::AllColumns, SqlLiteral<Text>)>>::Output), PaginationError>
+    where
+        T: Listable,
+        F: Fn() -> Q,
+        Q: FilterDsl<Condition<T>, Output = Q> + ThenOrderDsl<SqlLiteral<Text>, Output = Q>
+            + LimitDsl<Output = Q> + OffsetDsl<Output = Q>
+            + SelectDsl<CountStar> + SelectDsl<(T::AllColumns, SqlLiteral<Text>)>,
+    {
+        let order = self.sort.columns::<T>()?;
+        let per_page = self.per_page();
+
+        let mut total = query();
+        for condition in self.filter.conditions::<T>()? {
+            total = FilterDsl::filter(total, condition);
+        }
+
+        let mut items = query();
+        for condition in self.filter.conditions::<T>()? {
+            items = FilterDsl::filter(items, condition);
+        }
+        for (column, descending) in &order {
+            items = items.then_order_by(sql::<Text>(&format!("{} {}", T::column_sql(column), if *descending { "DESC" } else { "ASC" })));
+        }
+        items = items.then_order_by(sql::<Text>(&format!("{} ASC", T::column_sql(&T::PRIMARY_KEY))));
+
+        items = match (self.offset()?, &self.cursor) {
+            (Some(offset), _) => items.offset(offset),
+            (None, Some(cursor)) => FilterDsl::filter(items, keyset_condition::<T>(&order, cursor)?),
+            (None, None) => items,
+        };
+
+        let cursor_sql = format!(
+            "CAST(json_build_array({}) AS TEXT)",
+            order.iter().map(|(column, _)| column).chain([&T::PRIMARY_KEY])
+                .map(|column| format!("CAST({} AS TEXT)", T::column_sql(column)))
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+        Ok((
+            SelectDsl::select(total, diesel::dsl::count_star()),
+            SelectDsl::select(items.limit(per_page + 1), (T::all_columns(), sql::<Text>(&cursor_sql))),
+        ))
+    }
+}
+
+impl Sort {
+    /// Returns the requested columns and whether they are sorted descending.
+    pub fn columns<T: Listable>(&self) -> Result<Vec<(ListColumn, bool)>, PaginationError> {
+        self.0.split(',').filter(|name| !name.is_empty()).map(|name| {
+            let (name, descending) = match name.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (name, false),
+            };
+
+            T::SORTABLE.iter().find(|column| column.name == name)
+                .map(|column| (*column, descending))
+                .ok_or_else(|| PaginationError::UnknownSortColumn(name.to_string()))
+        }).collect()
+    }
+}
+
+impl Filter {
+    /// Returns the requested columns and their values.
+    fn columns<T: Listable>(&self) -> Result<Vec<(&'static ListColumn, &String)>, PaginationError> {
+        self.0.iter().map(|(name, value)| {
+            T::FILTERABLE.iter().find(|column| column.name == name)
+                .map(|column| (column, value))
+                .ok_or_else(|| PaginationError::UnknownFilterColumn(name.to_string()))
+        }).collect()
+    }
+
+    /// Returns the conditions of the requested filters.
+    pub fn conditions<T: Listable>(&self) -> Result<Vec<Condition<T>>, PaginationError> {
+        self.columns::<T>()?.into_iter().map(|(column, value)| {
+            let value = (column.parse)(value).ok_or_else(|| PaginationError::InvalidFilterValue(column.name.to_string()))?;
+            let condition = sql::<Bool>(&format!("{} = CAST(", T::column_sql(column)))
+                .bind::<Text, _>(value)
+                .sql(&format!(" AS {})", column.cast));
+            Ok(Box::new(condition) as Condition<T>)
+        }).collect()
+    }
+}
+
+/// Builds the condition selecting the rows after the last row of the previous page in `order`.
+///
+/// The cursor is a JSON array of the values of the sorted columns and the primary key of that row, as text.
+fn keyset_condition<T: Listable>(order: &[(ListColumn, bool)], cursor: &str) -> Result<Condition<T>, PaginationError> {
+    let columns = order.iter().map(|(column, _)| *column).chain([T::PRIMARY_KEY]).collect::<Vec<_>>();
+    let values = serde_json::from_str::<Vec<Option<String>>>(cursor)
+        .ok()
+        .filter(|values| values.len() == columns.len())
+        .ok_or(PaginationError::InvalidCursor)?
+        .into_iter()
+        .zip(&columns)
+        // Null values match no rows, like the rows with null values they were read from
+        .map(|(value, column)| value.map(|value| (column.parse)(&value).ok_or(PaginationError::InvalidCursor)).transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let compare = |index: usize, operator: &str| -> Condition<T> {
+        let column = &columns[index];
+        let condition = sql::<Bool>(&format!("{} {} CAST(", T::column_sql(column), operator))
+            .bind::<Nullable<Text>, _>(values[index].clone())
+            .sql(&format!(" AS {})", column.cast));
+        Box::new(condition)
+    };
+
+    // (a > a') OR (a = a' AND ((b > b') OR (b = b' AND id > id')))
+    let mut condition = compare(order.len(), ">");
+    for (index, (_, descending)) in order.iter().enumerate().rev() {
+        let after = compare(index, if *descending { "<" } else { ">" });
+        condition = Box::new(after.or(compare(index, "=").and(condition)));
+    }
+
+    Ok(condition)
+}
+
+/// Builds the links of a page from the uri of the request.
+struct PageLinkBuilder {
+    url: String,
+    query: Vec<String>,
+}
+
+impl PageLinkBuilder {
+    fn new(uri: &Origin<'_>) -> Self {
+        let query = uri.query()
+            .map(|query| query.raw_segments()
+                .filter(|segment| !matches!(segment.as_str().split('=').next(), Some("page" | "cursor")))
+                .map(|segment| segment.to_string())
+                .collect())
+            .unwrap_or_default();
+
+        PageLinkBuilder {
+            url: UrlGenerator::get_or_init().absolute_url(uri.clone()),
+            query,
+        }
+    }
+
+    /// Returns the absolute link to the uri with `page` or `cursor` replaced by `parameter`.
+    fn link(&self, parameter: Option<(&str, String)>) -> String {
+        let mut query = self.query.clone();
+        if let Some((name, value)) = parameter {
+            query.push(format!("{}={}", name, rocket::http::RawStr::new(&value).percent_encode()));
+        }
+
+        if query.is_empty() {
+            self.url.clone()
+        } else {
+            format!("{}?{}", self.url, query.join("&"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel_async::SimpleAsyncConnection;
+    use rocket::http::uri::Origin;
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::{async_test, get, routes};
+    use serde_json::Value;
+    use ferrox_env::EnvLoader;
+    use crate::db::{DatabaseFairing, DbConn, DbPool};
+    use crate::pagination::{Page, Pagination, PaginationError, SqlCast};
+
+    diesel::table! {
+        ferrox_pagination_test (id) {
+            id -> Integer,
+            name -> Text,
+        }
+    }
+
+    crate::listable!(ferrox_pagination_test (id) {
+        sort: [id, name],
+        filter: [id, name],
+    });
+
+    #[derive(diesel::Queryable, serde::Serialize)]
+    struct Item {
+        id: i32,
+        name: String,
+    }
+
+    #[get("/items?<pagination..>")]
+    async fn items(uri: &Origin<'_>, pagination: Pagination, mut conn: DbConn) -> Result<Page<Item>, PaginationError> {
+        use diesel::QueryDsl;
+        pagination.load(|| ferrox_pagination_test::table.into_boxed(), uri, &mut conn).await
+    }
+
+    async fn get(client: &Client, uri: &str) -> Value {
+        serde_json::from_str(&client.get(uri).dispatch().await.into_string().await.unwrap()).unwrap()
+    }
+
+    #[async_test]
+    async fn test_pagination() {
+        EnvLoader::load_test();
+        std::env::set_var("BASE_URL", "http://localhost");
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        conn.batch_execute("
+            CREATE TABLE IF NOT EXISTS ferrox_pagination_test (id INT PRIMARY KEY, name TEXT NOT NULL);
+            DELETE FROM ferrox_pagination_test;
+            INSERT INTO ferrox_pagination_test VALUES (1, 'b'), (2, 'a'), (3, 'b'), (4, 'c'), (5, 'a');
+        ").await.unwrap();
+
+        let client = Client::tracked(rocket::build().attach(DatabaseFairing::default()).mount("/", routes![items])).await.unwrap();
+
+        let page = get(&client, "/items?sort=-name&per_page=2&page=2").await;
+        assert_eq!(page["data"]["total"], 5);
+        assert_eq!(page["data"]["items"][0]["id"], 3);
+        assert_eq!(page["data"]["items"][1]["id"], 2);
+        assert_eq!(page["data"]["links"]["next"], "http://localhost/items?sort=-name&per_page=2&page=3");
+        assert_eq!(page["data"]["links"]["prev"], "http://localhost/items?sort=-name&per_page=2&page=1");
+
+        let page = get(&client, "/items?sort=-name&per_page=2").await;
+        assert_eq!(page["data"]["items"][1]["id"], 1);
+        let cursor = page["data"]["next_cursor"].as_str().unwrap();
+        assert_eq!(cursor, r#"["b", "1"]"#);
+
+        // Rows changed after the previous page do not affect the position of the cursor
+        conn.batch_execute("UPDATE ferrox_pagination_test SET name = 'z' WHERE id = 1").await.unwrap();
+        let page = get(&client, &format!("/items?sort=-name&per_page=2&cursor={}", rocket::http::RawStr::new(cursor).percent_encode())).await;
+        assert_eq!(page["data"]["items"][0]["id"], 3);
+        assert_eq!(page["data"]["items"][1]["id"], 2);
+        assert_eq!(page["data"]["next_cursor"], r#"["a", "2"]"#);
+
+        let page = get(&client, "/items?filter.name=a").await;
+        assert_eq!(page["data"]["total"], 2);
+        assert_eq!(page["data"]["next_cursor"], Value::Null);
+
+        let page = get(&client, "/items?sort=secret").await;
+        assert_eq!(page["success"], false);
+
+        let page = get(&client, "/items?per_page=1&page=9223372036854775807").await;
+        assert_eq!(page["data"]["items"], Value::Array(vec![]));
+        assert_eq!(page["data"]["links"]["next"], Value::Null);
+
+        for (uri, msg) in [
+            ("/items?page=9223372036854775807", "Invalid page 9223372036854775807"),
+            ("/items?filter.id=abc", "Invalid value for filter id"),
+            ("/items?page=0", "Invalid page 0"),
+            ("/items?cursor=abc", "Invalid cursor"),
+            ("/items?cursor=%5B%22abc%22%5D", "Invalid cursor"),
+            ("/items?sort=name&cursor=%5B%221%22%5D", "Invalid cursor"),
+        ] {
+            let response = client.get(uri).dispatch().await;
+            assert_eq!(response.status(), Status::BadRequest);
+            let body: Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+            assert_eq!(body["msg"], msg);
+        }
+    }
+
+    #[test]
+    fn test_sql_cast() {
+        use diesel::sql_types::{Bool, Integer, Numeric, Timestamp, Timestamptz};
+
+        assert_eq!(Integer::parse("42"), Some("42".to_string()));
+        assert_eq!(Integer::parse("4294967296"), None);
+        assert_eq!(Bool::parse("t"), Some("true".to_string()));
+        assert_eq!(Numeric::parse("-1.5e3"), Some("-1.5e3".to_string()));
+        assert_eq!(Numeric::parse("1.2.3"), None);
+        assert_eq!(Timestamp::parse("2026-10-19 12:00:00"), Some("2026-10-19T12:00:00".to_string()));
+        assert_eq!(Timestamptz::parse("2026-10-19 12:00:00.5+02"), Some("2026-10-19T12:00:00.500+02:00".to_string()));
+        assert_eq!(Timestamptz::parse("2026-10-19T12:00:00Z"), Some("2026-10-19T12:00:00+00:00".to_string()));
+        assert_eq!(Timestamptz::parse("yesterday"), None);
+    }
+}
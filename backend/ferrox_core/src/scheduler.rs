@@ -0,0 +1,331 @@
+//! Contains the [SchedulerFairing] running async tasks on cron schedules inside the Rocket process.
+//!
+//! Each tick of a task runs on only one instance of the application: the instance holding the
+//! Postgres advisory lock of the task claims the tick in the `ferrox_scheduled_task` table, which also
+//! records the last run and its result. The table is created by [SCHEDULER_MIGRATIONS].
+//!
+//! With the `sentry` feature, failures are reported to sentry and every run sends cron monitor check-ins.
+//!
+//! Enabled through the `scheduler` feature.
+
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use diesel::sql_types::{BigInt, Bool, Nullable, Text, Timestamptz};
+use diesel::QueryableByName;
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::sync::watch;
+use rocket::{async_trait, error, tokio, warn, Build, Orbit, Rocket};
+use serde::Serialize;
+use crate::db::{DbPool, PooledConnection};
+
+/// Migrations creating the `ferrox_scheduled_task` table used by the [SchedulerFairing].
+///
+/// Add them through `DatabaseFairing::with_migrations` next to the migrations of the application.
+pub const SCHEDULER_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/scheduler");
+
+/// Result of an invocation of a scheduled task.
+pub type TaskResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+type TaskFn = Box<dyn Fn() -> Pin<Box<dyn Future<Output = TaskResult> + Send>> + Send + Sync>;
+
+struct ScheduledTask {
+    name: String,
+    expression: String,
+    task: TaskFn,
+}
+
+/// Last recorded run of a scheduled task.
+#[derive(QueryableByName, Serialize, Clone, Debug)]
+pub struct TaskRun {
+    /// Name of the task.
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    /// Tick of the schedule the task last ran for.
+    #[diesel(sql_type = Timestamptz)]
+    pub last_tick: DateTime<Utc>,
+    /// When the last run finished, `None` while the first run is in progress.
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    pub last_run_at: Option<DateTime<Utc>>,
+    /// Duration of the last run in milliseconds.
+    #[diesel(sql_type = Nullable<BigInt>)]
+    pub last_duration_ms: Option<i64>,
+    /// Whether the last run succeeded.
+    #[diesel(sql_type = Nullable<Bool>)]
+    pub last_success: Option<bool>,
+    /// Error of the last run if it failed.
+    #[diesel(sql_type = Nullable<Text>)]
+    pub last_error: Option<String>,
+}
+
+impl TaskRun {
+    /// Retrieves the last run of the task `name`.
+    pub async fn get(name: &str, conn: &mut PooledConnection) -> diesel::QueryResult<Option<TaskRun>> {
+        diesel::sql_query("SELECT * FROM ferrox_scheduled_task WHERE name = $1")
+            .bind::<Text, _>(name)
+            .get_results::<TaskRun>(conn)
+            .await
+            .map(|runs| runs.into_iter().next())
+    }
+}
+
+/// Fairing running tasks registered through [Self::with_task] on their cron schedule.
+///
+/// Requires the [DbPool] to be configured with Postgres and the [SCHEDULER_MIGRATIONS] to be applied.
+#[derive(Default)]
+pub struct SchedulerFairing {
+    tasks: Mutex<Vec<ScheduledTask>>,
+    shutdown: Mutex<Option<watch::Sender<bool>>>,
+}
+
+impl SchedulerFairing {
+    /// Runs `task` on the cron schedule `expression`, e.g. `0 */5 * * * *` for every 5 minutes.
+    ///
+    /// Expressions start with the seconds and may end with the year, times are in UTC.
+    /// `name` identifies the task across instances and is used as sentry monitor slug.
+    ///
+    /// Invalid expressions abort the ignition of Rocket.
+    pub fn with_task<F, Fut>(self, name: &str, expression: &str, task: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = TaskResult> + Send + 'static,
+    {
+        self.tasks.lock().unwrap().push(ScheduledTask {
+            name: name.to_string(),
+            expression: expression.to_string(),
+            task: Box::new(move || Box::pin(task())),
+        });
+        self
+    }
+}
+
+#[async_trait]
+impl Fairing for SchedulerFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "ferrox-scheduler",
+            kind: Kind::Ignite | Kind::Liftoff | Kind::Shutdown,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        for task in self.tasks.lock().unwrap().iter() {
+            if let Err(e) = Schedule::from_str(&task.expression) {
+                error!("Invalid schedule {} of task {}: {}", task.expression, task.name, e);
+                return Err(rocket);
+            }
+        }
+
+        Ok(rocket)
+    }
+
+    async fn on_liftoff(&self, _rocket: &Rocket<Orbit>) {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        let (sender, receiver) = watch::channel(false);
+        for task in tasks {
+            tokio::spawn(schedule(Arc::new(task), receiver.clone()));
+        }
+        *self.shutdown.lock().unwrap() = Some(sender);
+    }
+
+    async fn on_shutdown(&self, _rocket: &Rocket<Orbit>) {
+        if let Some(sender) = self.shutdown.lock().unwrap().take() {
+            let _ = sender.send(true);
+        }
+    }
+}
+
+/// Waits for the ticks of `task` and runs it until shutdown is signaled.
+async fn schedule(task: Arc<ScheduledTask>, mut shutdown: watch::Receiver<bool>) {
+    // Validated on ignite
+    let schedule = Schedule::from_str(&task.expression).unwrap();
+    while !*shutdown.borrow() {
+        let Some(tick) = schedule.upcoming(Utc).next() else {
+            return;
+        };
+
+        let wait = (tick - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = shutdown.changed() => return,
+        }
+
+        // Runs in its own task, so slow runs do not delay the next tick
+        tokio::spawn(run_tick(task.clone(), tick));
+    }
+}
+
+/// Runs the `tick` of `task` if no other instance claimed it.
+async fn run_tick(task: Arc<ScheduledTask>, tick: DateTime<Utc>) {
+    // The connection is returned to the pool while the task runs
+    let claimed = match DbPool::get_conn().await {
+        Ok(mut conn) => claim_tick(&task.name, tick, &mut conn).await,
+        Err(e) => {
+            error!("Failed to get connection for task {}: {}", task.name, e);
+            return;
+        }
+    };
+
+    match claimed {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            error!("Failed to claim tick of task {}: {}", task.name, e);
+            return;
+        }
+    }
+
+    #[cfg(feature = "sentry")]
+    let check_in_id = check_in(&task, crate::sentry::sentry::protocol::MonitorCheckInStatus::InProgress, None, None);
+
+    let started = Instant::now();
+    let result = match tokio::spawn((task.task)()).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(e) if e.is_panic() => Err("Task panicked".to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    let duration = started.elapsed();
+
+    if let Err(e) = &result {
+        warn!("Task {} failed: {}", task.name, e);
+    }
+
+    #[cfg(feature = "sentry")]
+    {
+        use crate::sentry::sentry::protocol::MonitorCheckInStatus;
+
+        let status = if result.is_ok() { MonitorCheckInStatus::Ok } else { MonitorCheckInStatus::Error };
+        check_in(&task, status, Some(check_in_id), Some(duration.as_secs_f64()));
+        if let Err(e) = &result {
+            crate::sentry::sentry::capture_message(&format!("Task {} failed: {}", task.name, e), crate::sentry::sentry::Level::Error);
+        }
+    }
+
+    let recorded = match DbPool::get_conn().await {
+        Ok(mut conn) => record_run(&task.name, &result, duration, &mut conn).await.map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = recorded {
+        error!("Failed to record run of task {}: {}", task.name, e);
+    }
+}
+
+#[derive(QueryableByName)]
+struct Claimed {
+    #[diesel(sql_type = Bool)]
+    claimed: bool,
+}
+
+/// Claims `tick` of the task `name` for this instance.
+///
+/// The advisory lock serializes the claims of all instances, while the recorded tick prevents an instance
+/// acquiring the lock later from running the same tick again.
+async fn claim_tick(name: &str, tick: DateTime<Utc>, conn: &mut PooledConnection) -> diesel::QueryResult<bool> {
+    let name = name.to_string();
+    conn.transaction(|conn| Box::pin(async move {
+        // Transaction scoped, so the lock is released even if the connection is lost
+        let locked = diesel::sql_query("SELECT pg_try_advisory_xact_lock(hashtext('ferrox_scheduler:' || $1)) AS claimed")
+            .bind::<Text, _>(&name)
+            .get_result::<Claimed>(conn)
+            .await?;
+        if !locked.claimed {
+            return Ok(false);
+        }
+
+        diesel::sql_query("
+            INSERT INTO ferrox_scheduled_task (name, last_tick) VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE SET last_tick = excluded.last_tick
+            WHERE ferrox_scheduled_task.last_tick < excluded.last_tick
+        ")
+            .bind::<Text, _>(&name)
+            .bind::<Timestamptz, _>(tick)
+            .execute(conn)
+            .await
+            .map(|count| count > 0)
+    })).await
+}
+
+async fn record_run(name: &str, result: &Result<(), String>, duration: std::time::Duration, conn: &mut PooledConnection) -> diesel::QueryResult<()> {
+    diesel::sql_query("
+        UPDATE ferrox_scheduled_task SET last_run_at = now(), last_duration_ms = $2, last_success = $3, last_error = $4
+        WHERE name = $1
+    ")
+        .bind::<Text, _>(name)
+        .bind::<BigInt, _>(duration.as_millis() as i64)
+        .bind::<Bool, _>(result.is_ok())
+        .bind::<Nullable<Text>, _>(result.as_ref().err())
+        .execute(conn)
+        .await
+        .map(|_| ())
+}
+
+/// Sends a cron monitor check-in of `task` to sentry and returns its id.
+///
+/// Pass the id of the in progress check-in to finish it.
+#[cfg(feature = "sentry")]
+fn check_in(
+    task: &ScheduledTask,
+    status: crate::sentry::sentry::protocol::MonitorCheckInStatus,
+    id: Option<crate::sentry::sentry::types::Uuid>,
+    duration: Option<f64>,
+) -> crate::sentry::sentry::types::Uuid {
+    use crate::sentry::sentry::protocol::{MonitorCheckIn, MonitorConfig, MonitorSchedule};
+    use crate::sentry::sentry::Hub;
+
+    let check_in_id = id.unwrap_or_else(uuid::Uuid::new_v4);
+    if let Some(client) = Hub::current().client() {
+        // Sentry only knows crontabs without seconds and years
+        let fields = task.expression.split_whitespace().collect::<Vec<_>>();
+        let monitor_config = (fields.len() == 6 && fields[0] == "0").then(|| MonitorConfig {
+            schedule: MonitorSchedule::Crontab { value: fields[1..].join(" ") },
+            checkin_margin: None,
+            max_runtime: None,
+            timezone: Some("UTC".to_string()),
+            failure_issue_threshold: None,
+            recovery_threshold: None,
+        });
+
+        client.send_envelope(MonitorCheckIn {
+            check_in_id,
+            monitor_slug: task.name.clone(),
+            status,
+            environment: client.options().environment.as_ref().map(|environment| environment.to_string()),
+            duration,
+            monitor_config,
+        }.into());
+    }
+
+    check_in_id
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use diesel::sql_types::Text;
+    use diesel_async::RunQueryDsl;
+    use rocket::async_test;
+    use ferrox_env::EnvLoader;
+    use crate::db::DbPool;
+    use crate::scheduler::{claim_tick, SCHEDULER_MIGRATIONS};
+
+    #[async_test]
+    async fn test_claim_tick() {
+        EnvLoader::load_test();
+        DbPool::run_migrations(vec![SCHEDULER_MIGRATIONS]).await.unwrap();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        diesel::sql_query("DELETE FROM ferrox_scheduled_task WHERE name = $1").bind::<Text, _>("test").execute(&mut conn).await.unwrap();
+
+        let tick = Utc::now();
+        assert!(claim_tick("test", tick, &mut conn).await.unwrap());
+        assert!(!claim_tick("test", tick, &mut conn).await.unwrap());
+        assert!(claim_tick("test", tick + Duration::minutes(1), &mut conn).await.unwrap());
+    }
+}
@@ -0,0 +1,193 @@
+//! Contains a command line interface to manage [EmbeddedMigrations].
+//!
+//! Enabled through the `cli` feature. Wire it into a binary of your project with the same migrations
+//! given to [crate::DatabaseFairing::with_migrations]:
+//!
+//! ```ignore
+//! const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
+//!
+//! fn main() -> std::process::ExitCode {
+//!     ferrox_db::cli::run(MIGRATIONS)
+//! }
+//! ```
+//!
+//! Usage: `<bin> [--dry-run] <status|up|down [N]|redo>`
+
+use std::process::ExitCode;
+
+use diesel::connection::{Instrumentation, InstrumentationEvent};
+use diesel::migration::Migration;
+use diesel::pg::Pg;
+use diesel::{Connection, PgConnection};
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
+use ferrox_env::EnvLoader;
+use crate::migrations::{all_migrations, run_unlocked, with_migration_lock, MigrationError};
+
+/// Command of the CLI.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Lists all migrations and whether they are applied.
+    Status,
+    /// Runs all pending migrations.
+    Up,
+    /// Reverts the last N migrations.
+    Down(usize),
+    /// Reverts and reruns the last migration.
+    Redo,
+}
+
+impl Command {
+    /// Parses the command and the `--dry-run` flag from arguments, excluding the binary name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<(Command, bool), String> {
+        let mut dry_run = false;
+        let mut args = args.into_iter().filter(|arg| {
+            let flag = arg == "--dry-run";
+            dry_run |= flag;
+            !flag
+        }).collect::<Vec<_>>().into_iter();
+
+        let command = match args.next().as_deref() {
+            Some("status") => Command::Status,
+            Some("up") => Command::Up,
+            Some("down") => Command::Down(match args.next() {
+                Some(count) => count.parse().map_err(|_| format!("Invalid count {}", count))?,
+                None => 1,
+            }),
+            Some("redo") => Command::Redo,
+            Some(command) => return Err(format!("Unknown command {}", command)),
+            None => return Err("Missing command".to_string()),
+        };
+
+        if let Some(arg) = args.next() {
+            return Err(format!("Unexpected argument {}", arg));
+        }
+
+        Ok((command, dry_run))
+    }
+}
+
+/// Runs the CLI with the arguments of the process.
+///
+/// Reads `DATABASE_URL` through [EnvLoader::load].
+pub fn run(migrations: EmbeddedMigrations) -> ExitCode {
+    let (command, dry_run) = match Command::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: [--dry-run] <status|up|down [N]|redo>");
+            return ExitCode::from(2);
+        }
+    };
+
+    EnvLoader::load();
+    match execute(&migrations, command, dry_run) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Executes a [Command] against the database at `DATABASE_URL`.
+///
+/// With `dry_run` the SQL is printed and all changes are rolled back.
+pub fn execute(migrations: &EmbeddedMigrations, command: Command, dry_run: bool) -> Result<(), MigrationError> {
+    let url = std::env::var("DATABASE_URL").map_err(|_| "No DATABASE_URL found")?;
+    let mut conn = PgConnection::establish(&url)?;
+
+    if command == Command::Status {
+        let applied = conn.applied_migrations()?;
+        for migration in all_migrations(migrations)? {
+            let mark = if applied.contains(&migration.name().version()) { "X" } else { " " };
+            println!("[{}] {}", mark, migration.name());
+        }
+        return Ok(());
+    }
+
+    if dry_run {
+        conn.begin_test_transaction()?;
+        conn.set_instrumentation(PrintSql);
+        return apply(&mut conn, migrations, command);
+    }
+
+    with_migration_lock(&mut conn, |conn| apply(conn, migrations, command))
+}
+
+fn apply(conn: &mut PgConnection, migrations: &EmbeddedMigrations, command: Command) -> Result<(), MigrationError> {
+    match command {
+        Command::Status => Ok(()),
+        Command::Up => {
+            let applied = run_unlocked(conn, migrations)?;
+            if applied.is_empty() {
+                println!("No pending migrations");
+            }
+            for name in applied {
+                println!("Applied {}", name);
+            }
+            Ok(())
+        }
+        Command::Down(count) => {
+            for migration in last_applied(conn, migrations, count)? {
+                conn.revert_migration(&*migration)?;
+                println!("Reverted {}", migration.name());
+            }
+            Ok(())
+        }
+        Command::Redo => {
+            let Some(migration) = last_applied(conn, migrations, 1)?.pop() else {
+                return Err("No migration applied".into());
+            };
+            conn.revert_migration(&*migration)?;
+            println!("Reverted {}", migration.name());
+            conn.run_migration(&*migration)?;
+            println!("Applied {}", migration.name());
+            Ok(())
+        }
+    }
+}
+
+/// Returns the last `count` applied migrations, latest first.
+fn last_applied(conn: &mut PgConnection, migrations: &EmbeddedMigrations, count: usize) -> Result<Vec<Box<dyn Migration<Pg>>>, MigrationError> {
+    let applied = conn.applied_migrations()?;
+    let applied = all_migrations(migrations)?
+        .into_iter()
+        .filter(|migration| applied.contains(&migration.name().version()))
+        .rev()
+        .take(count)
+        .collect();
+
+    Ok(applied)
+}
+
+/// [Instrumentation] printing all executed SQL.
+struct PrintSql;
+
+impl Instrumentation for PrintSql {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        if let InstrumentationEvent::StartQuery { query, .. } = event {
+            let sql = query.to_string();
+            if !sql.contains("SAVEPOINT") {
+                println!("{}", sql.trim());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::Command;
+
+    #[test]
+    fn test_parse() {
+        let parse = |args: &[&str]| Command::parse(args.iter().map(|arg| arg.to_string()));
+
+        assert_eq!(parse(&["status"]), Ok((Command::Status, false)));
+        assert_eq!(parse(&["--dry-run", "up"]), Ok((Command::Up, true)));
+        assert_eq!(parse(&["down"]), Ok((Command::Down(1), false)));
+        assert_eq!(parse(&["down", "3", "--dry-run"]), Ok((Command::Down(3), true)));
+        assert!(parse(&["down", "x"]).is_err());
+        assert!(parse(&["sideways"]).is_err());
+        assert!(parse(&[]).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! Contains the settings of the [crate::DbPool].
+//!
+//! See [PoolConfig].
+
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+use diesel::QueryResult;
+use diesel_async::pooled_connection::{ManagerConfig, RecyclingMethod};
+use diesel_async::{AsyncConnection, SimpleAsyncConnection};
+use crate::instrumentation::QueryInstrumentation;
+use crate::DbConnection;
+
+/// Describes how connections are checked before they are reused.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PoolRecycling {
+    /// Only checks for open transactions.
+    Fast,
+    /// Additionally executes a test query. This is the default.
+    Verified,
+    /// Executes the given test query.
+    Query(String),
+}
+
+/// Settings of the [crate::DbPool].
+///
+/// Every setting can be provided through env and through the corresponding builder of [crate::DatabaseFairing].
+/// Values of the fairing take precedence over env.
+/// `statement_timeout`, `application_name`, `timezone` and `replica_stickiness` only apply to Postgres.
+///
+/// | Setting | Env |
+/// |---|---|
+/// | max_size | `DATABASE_POOL_MAX_SIZE` |
+/// | wait_timeout | `DATABASE_POOL_WAIT_TIMEOUT` (seconds) |
+/// | create_timeout | `DATABASE_POOL_CREATE_TIMEOUT` (seconds) |
+/// | recycle_timeout | `DATABASE_POOL_RECYCLE_TIMEOUT` (seconds) |
+/// | recycling | `DATABASE_POOL_RECYCLING` (`fast`, `verified` or a test query) |
+/// | statement_timeout | `DATABASE_STATEMENT_TIMEOUT` (milliseconds) |
+/// | application_name | `DATABASE_APPLICATION_NAME` |
+/// | timezone | `DATABASE_TIMEZONE` |
+/// | replica_stickiness | `DATABASE_REPLICA_STICKINESS` (seconds) |
+/// | query_metrics | `DATABASE_QUERY_METRICS` (`true` or `false`) |
+/// | slow_query_threshold | `DATABASE_SLOW_QUERY_THRESHOLD` (milliseconds) |
+/// | query_tracing | `DATABASE_QUERY_TRACING` (`true` or `false`) |
+#[derive(Clone, Debug, Default)]
+pub struct PoolConfig {
+    /// Maximum number of connections.
+    pub max_size: Option<usize>,
+    /// Maximum time to wait for a free connection.
+    pub wait_timeout: Option<Duration>,
+    /// Maximum time to establish a new connection.
+    pub create_timeout: Option<Duration>,
+    /// Maximum time to recycle a connection.
+    pub recycle_timeout: Option<Duration>,
+    /// How connections are checked before reuse.
+    pub recycling: Option<PoolRecycling>,
+    /// `statement_timeout` set on each new connection.
+    pub statement_timeout: Option<Duration>,
+    /// `application_name` set on each new connection.
+    pub application_name: Option<String>,
+    /// Time zone set on each new connection.
+    pub timezone: Option<String>,
+    /// How long reads of a client are routed to the primary after a write. Defaults to 5 seconds.
+    pub replica_stickiness: Option<Duration>,
+    /// Whether the execution time of each query is recorded. Requires the `metrics` feature.
+    pub query_metrics: Option<bool>,
+    /// Queries taking at least this long are logged with redacted parameters.
+    pub slow_query_threshold: Option<Duration>,
+    /// Whether each query is recorded as breadcrumb and span in sentry. Requires the `sentry` feature.
+    pub query_tracing: Option<bool>,
+}
+
+impl PoolConfig {
+    /// Reads the settings from env.
+    ///
+    /// # Panics
+    /// Panics if a value is set but invalid.
+    pub fn from_env() -> Self {
+        PoolConfig {
+            max_size: parse_env("DATABASE_POOL_MAX_SIZE"),
+            wait_timeout: parse_env("DATABASE_POOL_WAIT_TIMEOUT").map(Duration::from_secs),
+            create_timeout: parse_env("DATABASE_POOL_CREATE_TIMEOUT").map(Duration::from_secs),
+            recycle_timeout: parse_env("DATABASE_POOL_RECYCLE_TIMEOUT").map(Duration::from_secs),
+            recycling: env::var("DATABASE_POOL_RECYCLING").ok().map(|value| match value.to_lowercase().as_str() {
+                "fast" => PoolRecycling::Fast,
+                "verified" => PoolRecycling::Verified,
+                _ => PoolRecycling::Query(value),
+            }),
+            statement_timeout: parse_env("DATABASE_STATEMENT_TIMEOUT").map(Duration::from_millis),
+            application_name: env::var("DATABASE_APPLICATION_NAME").ok(),
+            timezone: env::var("DATABASE_TIMEZONE").ok(),
+            replica_stickiness: parse_env("DATABASE_REPLICA_STICKINESS").map(Duration::from_secs),
+            query_metrics: parse_env("DATABASE_QUERY_METRICS"),
+            slow_query_threshold: parse_env("DATABASE_SLOW_QUERY_THRESHOLD").map(Duration::from_millis),
+            query_tracing: parse_env("DATABASE_QUERY_TRACING"),
+        }
+    }
+
+    /// Overrides all settings of self which are set in `other`.
+    pub(crate) fn merge(self, other: &PoolConfig) -> Self {
+        let other = other.clone();
+        PoolConfig {
+            max_size: other.max_size.or(self.max_size),
+            wait_timeout: other.wait_timeout.or(self.wait_timeout),
+            create_timeout: other.create_timeout.or(self.create_timeout),
+            recycle_timeout: other.recycle_timeout.or(self.recycle_timeout),
+            recycling: other.recycling.or(self.recycling),
+            statement_timeout: other.statement_timeout.or(self.statement_timeout),
+            application_name: other.application_name.or(self.application_name),
+            timezone: other.timezone.or(self.timezone),
+            replica_stickiness: other.replica_stickiness.or(self.replica_stickiness),
+            query_metrics: other.query_metrics.or(self.query_metrics),
+            slow_query_threshold: other.slow_query_threshold.or(self.slow_query_threshold),
+            query_tracing: other.query_tracing.or(self.query_tracing),
+        }
+    }
+
+    pub(crate) fn manager_config(&self) -> ManagerConfig<DbConnection> {
+        let mut config = ManagerConfig::default();
+        config.recycling_method = match self.recycling.clone().unwrap_or(PoolRecycling::Verified) {
+            PoolRecycling::Fast => RecyclingMethod::Fast,
+            PoolRecycling::Verified => RecyclingMethod::Verified,
+            PoolRecycling::Query(query) => RecyclingMethod::CustomQuery(query.into()),
+        };
+        config
+    }
+
+    /// Returns whether new connections need to be set up.
+    pub(crate) fn has_setup(&self) -> bool {
+        self.statement_timeout.is_some() || self.application_name.is_some() || self.timezone.is_some()
+            || self.has_instrumentation()
+    }
+
+    /// Applies the session settings to a new connection.
+    pub(crate) async fn setup(&self, conn: &mut DbConnection) -> QueryResult<()> {
+        self.instrument(conn, None);
+
+        let statements = self.session_statements();
+        if statements.is_empty() {
+            return Ok(());
+        }
+
+        conn.batch_execute(&statements.join(";")).await
+    }
+
+    /// Returns the statements applying the session settings.
+    #[cfg(feature = "postgres")]
+    fn session_statements(&self) -> Vec<String> {
+        let mut statements = vec![];
+        if let Some(timeout) = self.statement_timeout {
+            statements.push(format!("SET statement_timeout = {}", timeout.as_millis()));
+        }
+        if let Some(name) = &self.application_name {
+            statements.push(format!("SET application_name = {}", quote_literal(name)));
+        }
+        if let Some(timezone) = &self.timezone {
+            statements.push(format!("SET TIME ZONE {}", quote_literal(timezone)));
+        }
+
+        statements
+    }
+
+    /// Session settings are not supported by SQLite.
+    #[cfg(not(feature = "postgres"))]
+    fn session_statements(&self) -> Vec<String> {
+        vec![]
+    }
+
+    pub(crate) fn query_metrics(&self) -> bool {
+        cfg!(feature = "metrics") && self.query_metrics.unwrap_or(false)
+    }
+
+    pub(crate) fn query_tracing(&self) -> bool {
+        cfg!(feature = "sentry") && self.query_tracing.unwrap_or(false)
+    }
+
+    /// Returns whether connections need a [QueryInstrumentation].
+    pub(crate) fn has_instrumentation(&self) -> bool {
+        self.query_metrics() || self.query_tracing() || self.slow_query_threshold.is_some()
+    }
+
+    /// Installs a [QueryInstrumentation] tagging queries with `route` if enabled.
+    pub(crate) fn instrument(&self, conn: &mut DbConnection, route: Option<String>) {
+        if self.has_instrumentation() {
+            conn.set_instrumentation(QueryInstrumentation::new(self, route));
+        }
+    }
+}
+
+fn parse_env<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().map(|value| value.parse().unwrap_or_else(|_| panic!("Invalid {}", name)))
+}
+
+/// Quotes a string literal for Postgres.
+#[cfg(feature = "postgres")]
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
@@ -0,0 +1,69 @@
+//! Contains the health report of the [crate::DbPool]. See [DbHealth].
+
+use std::time::{Duration, Instant};
+
+use diesel_async::SimpleAsyncConnection;
+use rocket::tokio;
+use crate::ConnectionPool;
+
+/// Maximum time of the round-trip check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Health of the [crate::DbPool], returned by [crate::DbPool::health].
+#[derive(Clone, Debug, Default)]
+pub struct DbHealth {
+    /// Maximum number of connections.
+    pub max_size: usize,
+    /// Current number of connections.
+    pub size: usize,
+    /// Number of idle connections.
+    pub available: usize,
+    /// Number of callers waiting for a connection.
+    pub waiting: usize,
+    /// Time of the round-trip check, if it succeeded.
+    pub latency: Option<Duration>,
+    /// Error of the round-trip check, if it failed.
+    pub error: Option<String>,
+}
+
+impl DbHealth {
+    /// Returns whether the database is reachable.
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Reports an uninitialized pool.
+    pub(crate) fn uninitialized() -> Self {
+        DbHealth {
+            error: Some("Database pool not initialized".to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Checks the status of `pool` and the round-trip time of a query.
+    pub(crate) async fn check(pool: &ConnectionPool) -> Self {
+        let status = pool.status();
+        let mut health = DbHealth {
+            max_size: status.max_size,
+            size: status.size,
+            available: status.available,
+            waiting: status.waiting,
+            latency: None,
+            error: None,
+        };
+
+        let start = Instant::now();
+        let result = tokio::time::timeout(CHECK_TIMEOUT, async {
+            let mut conn = pool.get().await.map_err(|e| e.to_string())?;
+            conn.batch_execute("SELECT 1").await.map_err(|e| e.to_string())
+        }).await;
+
+        match result {
+            Ok(Ok(())) => health.latency = Some(start.elapsed()),
+            Ok(Err(e)) => health.error = Some(e),
+            Err(_) => health.error = Some("Health check timed out".to_string()),
+        }
+
+        health
+    }
+}
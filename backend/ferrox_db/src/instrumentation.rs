@@ -0,0 +1,157 @@
+//! Contains the [QueryInstrumentation] of [crate::DbPool] connections.
+//!
+//! Depending on the [PoolConfig], it logs slow queries, records query metrics
+//! and traces queries in sentry. Each query is tagged with the route of the request using the connection.
+
+use std::cell::OnceCell;
+use std::time::{Duration, Instant};
+
+use diesel::connection::{DebugQuery, Instrumentation, InstrumentationEvent};
+use rocket::warn;
+use crate::PoolConfig;
+
+/// [Instrumentation] installed on every connection of the pool if enabled in the [PoolConfig].
+pub(crate) struct QueryInstrumentation {
+    slow_query_threshold: Option<Duration>,
+    #[cfg(feature = "metrics")]
+    metrics: bool,
+    #[cfg(feature = "sentry")]
+    tracing: bool,
+    route: Option<String>,
+    query: Option<RunningQuery>,
+}
+
+struct RunningQuery {
+    started: Instant,
+    #[cfg(feature = "sentry")]
+    span: Option<ferrox_sentry::sentry::Span>,
+}
+
+impl QueryInstrumentation {
+    pub(crate) fn new(config: &PoolConfig, route: Option<String>) -> Self {
+        QueryInstrumentation {
+            slow_query_threshold: config.slow_query_threshold,
+            #[cfg(feature = "metrics")]
+            metrics: config.query_metrics(),
+            #[cfg(feature = "sentry")]
+            tracing: config.query_tracing(),
+            route,
+            query: None,
+        }
+    }
+
+    #[cfg_attr(not(feature = "sentry"), allow(unused_variables))]
+    fn start(&mut self, query: &dyn DebugQuery) {
+        #[cfg(feature = "sentry")]
+        let span = if self.tracing {
+            ferrox_sentry::sentry::configure_scope(|scope| scope.get_span()).map(|parent| {
+                let span = parent.start_child("db.sql.query", &redact(&query.to_string()));
+                if let Some(route) = &self.route {
+                    span.set_data("route", route.as_str().into());
+                }
+                span
+            })
+        } else {
+            None
+        };
+
+        self.query = Some(RunningQuery {
+            started: Instant::now(),
+            #[cfg(feature = "sentry")]
+            span,
+        });
+    }
+
+    #[cfg_attr(not(feature = "sentry"), allow(unused_variables))]
+    fn finish(&mut self, query: &dyn DebugQuery, failed: bool) {
+        let Some(running) = self.query.take() else {
+            return;
+        };
+
+        let duration = running.started.elapsed();
+        let route = self.route.as_deref().unwrap_or("-");
+        let slow = self.slow_query_threshold.is_some_and(|threshold| duration >= threshold);
+
+        // Formatting and redacting are only worth it for the queries that end up in a log, metric or trace
+        let sql = OnceCell::new();
+        let sql = || sql.get_or_init(|| redact(&query.to_string())).as_str();
+        if slow {
+            warn!("Slow query on {} took {}ms: {}", route, duration.as_millis(), sql());
+        }
+
+        #[cfg(feature = "metrics")]
+        if self.metrics {
+            crate::metrics::record_query(sql(), duration);
+        }
+
+        #[cfg(feature = "sentry")]
+        if self.tracing {
+            use ferrox_sentry::sentry::protocol::{Breadcrumb, Level, Map, SpanStatus};
+
+            if let Some(span) = running.span {
+                span.set_status(if failed { SpanStatus::InternalError } else { SpanStatus::Ok });
+                span.finish();
+            }
+
+            let mut data = Map::new();
+            data.insert("route".to_string(), route.into());
+            data.insert("duration_ms".to_string(), (duration.as_secs_f64() * 1000.0).into());
+            ferrox_sentry::sentry::add_breadcrumb(Breadcrumb {
+                ty: "query".to_string(),
+                category: Some("db.sql.query".to_string()),
+                level: if failed || slow { Level::Warning } else { Level::Info },
+                message: Some(sql().to_string()),
+                data,
+                ..Default::default()
+            });
+        }
+    }
+}
+
+impl Instrumentation for QueryInstrumentation {
+    fn on_connection_event(&mut self, event: InstrumentationEvent<'_>) {
+        match event {
+            InstrumentationEvent::StartQuery { query, .. } => self.start(query),
+            InstrumentationEvent::FinishQuery { query, error, .. } => self.finish(query, error.is_some()),
+            _ => {}
+        }
+    }
+}
+
+/// Removes bound parameters and string literals from a query, so no user data ends up in logs.
+fn redact(query: &str) -> String {
+    let sql = query.split(" -- binds: ").next().unwrap_or_default();
+    let mut redacted = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut in_literal = false;
+
+    while let Some(c) = chars.next() {
+        match (c, in_literal) {
+            ('\'', false) => {
+                in_literal = true;
+                redacted.push_str("'?'");
+            }
+            // Escaped quote inside a literal
+            ('\'', true) if chars.peek() == Some(&'\'') => {
+                chars.next();
+            }
+            ('\'', true) => in_literal = false,
+            (_, true) => {}
+            (c, false) => redacted.push(c),
+        }
+    }
+
+    redacted
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::instrumentation::redact;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact(r#"SELECT * FROM "users" WHERE "id" = $1 -- binds: [42]"#), r#"SELECT * FROM "users" WHERE "id" = $1"#);
+        assert_eq!(redact("SELECT 'secret', 'it''s' FROM t"), "SELECT '?', '?' FROM t");
+        assert_eq!(redact("SELECT 1"), "SELECT 1");
+    }
+}
@@ -1,30 +1,212 @@
 //! Contains the implementation of the database pool. [deadpool] is used.
 //!
 //! Use [DatabaseFairing] as fairing for rocket.
+//!
+//! The backend is selected through the `postgres` (default) or `sqlite` feature, only one of them can be enabled.
+//! Tenant isolation, replicas, the migration CLI and the testing utilities require Postgres.
+//! Notifications through `DbPool::subscribe` require the `listen` feature.
+//!
+//! [VersionedDsl], [SoftDeleteDsl] and [Timestamps] help with common columns of models and require Postgres.
 
 use std::env;
 use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
-use deadpool::managed::Object;
-use diesel::pg::Pg;
+use deadpool::managed::{HookError, Object};
 use diesel_async::async_connection_wrapper::AsyncConnectionWrapper;
-use diesel_async::pooled_connection::deadpool::Pool;
+use diesel_async::pooled_connection::deadpool::{Hook, Pool};
 use diesel_async::pooled_connection::AsyncDieselConnectionManager;
-use diesel_async::AsyncPgConnection;
-use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
+use diesel_migrations::EmbeddedMigrations;
 use rocket::fairing::{Fairing, Info, Kind};
-use rocket::{async_trait, tokio, Build, Rocket};
+use rocket::http::{ContentType, Status};
+use rocket::{async_trait, error, info, tokio, warn, Build, Request, Response, Rocket};
+
+#[cfg(not(any(feature = "postgres", feature = "sqlite")))]
+compile_error!("Either the postgres or the sqlite feature of ferrox_db has to be enabled");
+#[cfg(all(feature = "postgres", feature = "sqlite"))]
+compile_error!("The postgres and sqlite features of ferrox_db cannot be enabled at the same time");
+
+mod config;
+mod health;
+mod instrumentation;
+#[cfg(feature = "listen")]
+mod listener;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod migrations;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "postgres")]
+mod replica;
+mod request;
+#[cfg(feature = "postgres")]
+mod soft_delete;
+#[cfg(feature = "postgres")]
+mod tenant;
+#[cfg(all(feature = "postgres", any(test, feature = "testing")))]
+pub mod testing;
+#[cfg(feature = "postgres")]
+mod timestamps;
+#[cfg(feature = "postgres")]
+mod versioned;
+
+pub use config::*;
+pub use health::*;
+#[cfg(feature = "listen")]
+pub use listener::Subscription;
+pub use migrations::MigrationError;
+#[cfg(feature = "postgres")]
+pub use replica::{ReadConn, PRIMARY_COOKIE_NAME};
+pub use request::*;
+#[cfg(feature = "postgres")]
+pub use soft_delete::*;
+#[cfg(feature = "postgres")]
+pub use tenant::*;
+#[cfg(feature = "postgres")]
+pub use timestamps::*;
+#[cfg(feature = "postgres")]
+pub use versioned::*;
 
 /// Fairing initializing the [DbPool].
 #[derive(Default)]
 pub struct DatabaseFairing {
-    migrations: Arc<Mutex<Option<EmbeddedMigrations>>>,
+    migrations: Arc<Mutex<Vec<EmbeddedMigrations>>>,
+    #[cfg(feature = "postgres")]
+    tenant_isolation: Option<TenantIsolation>,
+    #[cfg(feature = "postgres")]
+    timestamps: Vec<String>,
+    #[cfg(all(feature = "postgres", any(test, feature = "testing")))]
+    test_connections: Option<testing::TestConnections>,
+    pool_config: PoolConfig,
 }
 
 impl DatabaseFairing {
     /// Allows to specify an instance of [EmbeddedMigrations] to be executed at startup
-    pub fn with_migrations(mut self, migrations: EmbeddedMigrations) -> Self {
-        self.migrations = Arc::new(Mutex::new(Some(migrations)));
+    ///
+    /// Can be called multiple times, e.g. to add the migrations of other ferrox crates.
+    /// The instances are executed in the order they were added.
+    pub fn with_migrations(self, migrations: EmbeddedMigrations) -> Self {
+        self.migrations.lock().unwrap().push(migrations);
+        self
+    }
+
+    /// Allows to specify how tenants are isolated in connections from [DbPool::get_tenant_conn].
+    #[cfg(feature = "postgres")]
+    pub fn with_tenant_isolation(mut self, isolation: TenantIsolation) -> Self {
+        self.tenant_isolation = Some(isolation);
+        self
+    }
+
+    /// Installs the triggers maintaining the timestamps of `tables` at startup, after the migrations ran
+    /// and while holding the migration lock.
+    ///
+    /// See [Timestamps::manage].
+    #[cfg(feature = "postgres")]
+    pub fn with_timestamps(mut self, tables: &[&str]) -> Self {
+        self.timestamps.extend(tables.iter().map(|table| table.to_string()));
+        self
+    }
+
+    /// Routes the [DbConn], [Tx] and [ReadConn] guards of this rocket to `database` instead of the [DbPool].
+    ///
+    /// Migrations and timestamps given to this fairing are applied to `database` as well.
+    #[cfg(all(feature = "postgres", any(test, feature = "testing")))]
+    pub fn with_test_database(mut self, database: &testing::TestDatabase) -> Self {
+        self.test_connections = Some(database.connections());
+        self
+    }
+
+    /// Lets the [DbConn], [Tx] and [ReadConn] guards of this rocket share the connection of `transaction`.
+    ///
+    /// Requests have to be dispatched one after another, as only one guard can hold the connection at a time.
+    #[cfg(all(feature = "postgres", any(test, feature = "testing")))]
+    pub fn with_test_transaction(mut self, transaction: &testing::TestTransaction) -> Self {
+        self.test_connections = Some(transaction.connections());
+        self
+    }
+
+    /// Retrieves the connection used to run migrations and install timestamps at startup.
+    async fn setup_conn(&self) -> Result<PooledConnection, DbPoolError> {
+        #[cfg(all(feature = "postgres", any(test, feature = "testing")))]
+        if let Some(testing::TestConnections::Database(pool)) = &self.test_connections {
+            return pool.get().await;
+        }
+
+        DbPool::get_or_init_conn().await
+    }
+
+    /// Sets the maximum number of connections of the [DbPool].
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.pool_config.max_size = Some(max_size);
+        self
+    }
+
+    /// Sets the maximum time to wait for a free connection.
+    pub fn with_wait_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_config.wait_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum time to establish a new connection.
+    pub fn with_create_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_config.create_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the maximum time to recycle a connection.
+    pub fn with_recycle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_config.recycle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how connections are checked before they are reused.
+    pub fn with_recycling(mut self, recycling: PoolRecycling) -> Self {
+        self.pool_config.recycling = Some(recycling);
+        self
+    }
+
+    /// Sets the `statement_timeout` of each connection.
+    pub fn with_statement_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_config.statement_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the `application_name` of each connection.
+    pub fn with_application_name(mut self, name: &str) -> Self {
+        self.pool_config.application_name = Some(name.to_string());
+        self
+    }
+
+    /// Sets how long reads of a client are routed to the primary after a write, see [ReadConn].
+    #[cfg(feature = "postgres")]
+    pub fn with_replica_stickiness(mut self, stickiness: Duration) -> Self {
+        self.pool_config.replica_stickiness = Some(stickiness);
+        self
+    }
+
+    /// Sets the time zone of each connection.
+    pub fn with_timezone(mut self, timezone: &str) -> Self {
+        self.pool_config.timezone = Some(timezone.to_string());
+        self
+    }
+
+    /// Logs queries taking at least `threshold` with the route of the request.
+    pub fn with_slow_query_threshold(mut self, threshold: Duration) -> Self {
+        self.pool_config.slow_query_threshold = Some(threshold);
+        self
+    }
+
+    /// Records each query as breadcrumb and span in sentry, tagged with the route of the request.
+    #[cfg(feature = "sentry")]
+    pub fn with_query_tracing(mut self) -> Self {
+        self.pool_config.query_tracing = Some(true);
+        self
+    }
+
+    /// Records the execution time of each query in the `ferrox_db_query_seconds` metric.
+    #[cfg(feature = "metrics")]
+    pub fn with_query_metrics(mut self) -> Self {
+        self.pool_config.query_metrics = Some(true);
         self
     }
 }
@@ -34,39 +216,186 @@ impl Fairing for DatabaseFairing {
     fn info(&self) -> Info {
         Info {
             name: "database-init",
-            kind: Kind::Ignite,
+            kind: Kind::Ignite | Kind::Response,
         }
     }
 
     async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        #[cfg(all(feature = "postgres", any(test, feature = "testing")))]
+        let rocket = match &self.test_connections {
+            Some(connections) => rocket.manage(connections.clone()),
+            None => rocket,
+        };
+
+        #[cfg(feature = "postgres")]
+        if let Some(isolation) = &self.tenant_isolation {
+            if TENANT_ISOLATION.set(isolation.clone()).is_err() {
+                warn!("Tenant isolation was already configured");
+            }
+        }
+
+        if POOL_CONFIG.set(PoolConfig::from_env().merge(&self.pool_config)).is_err() {
+            warn!("Database pool was already configured");
+        }
+
         DB_POOL.get_or_init(init_db);
+        #[cfg(feature = "postgres")]
+        replica::Replicas::get();
+
+        let embedded_migrations = std::mem::take(&mut *self.migrations.lock().unwrap());
+        if !embedded_migrations.is_empty() {
+            let result = match self.setup_conn().await {
+                Ok(conn) => run_migrations_on(conn, embedded_migrations).await,
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(applied) => info!("Applied {} migrations", applied.len()),
+                Err(e) => {
+                    error!("Failed to run migrations: {}", e);
+                    return Err(rocket);
+                }
+            }
+        }
 
-        if self.migrations.lock().unwrap().is_some() {
-            let embedded_migrations = {
-                let mut migrations = self.migrations.lock().unwrap();
-                std::mem::take(&mut *migrations).unwrap()
+        #[cfg(feature = "postgres")]
+        if !self.timestamps.is_empty() {
+            let result = match self.setup_conn().await {
+                Ok(conn) => Timestamps::manage_locked(conn, self.timestamps.clone()).await,
+                Err(e) => Err(e.into()),
             };
-            let conn = DbPool::get_conn().await.unwrap();
-            tokio::task::spawn_blocking(move || {
-                <AsyncConnectionWrapper<PooledConnection> as MigrationHarness<Pg>>::run_pending_migrations::<EmbeddedMigrations>(&mut AsyncConnectionWrapper::from(conn), embedded_migrations).unwrap();
-            });
+            if let Err(e) = result {
+                error!("Failed to install timestamps triggers: {}", e);
+                return Err(rocket);
+            }
         }
 
         Ok(rocket)
     }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if request::finish_request(request, response.status()).await.is_err() {
+            // Same shape as the `StdResponse` of ferrox_core
+            let body = r#"{"success":false,"data":null,"msg":"Failed to commit transaction"}"#;
+            response.set_status(Status::InternalServerError);
+            response.set_header(ContentType::JSON);
+            response.set_sized_body(body.len(), std::io::Cursor::new(body));
+        }
+    }
 }
 
-static DB_POOL: OnceLock<PgPool> = OnceLock::new();
+static DB_POOL: OnceLock<ConnectionPool> = OnceLock::new();
+#[cfg(feature = "postgres")]
+static TENANT_ISOLATION: OnceLock<TenantIsolation> = OnceLock::new();
+static POOL_CONFIG: OnceLock<PoolConfig> = OnceLock::new();
+
+/// Connection type of the selected backend.
+#[cfg(feature = "postgres")]
+pub type DbConnection = diesel_async::AsyncPgConnection;
+/// Connection type of the selected backend.
+#[cfg(not(feature = "postgres"))]
+pub type DbConnection = diesel_async::sync_connection_wrapper::SyncConnectionWrapper<diesel::SqliteConnection>;
+/// Selected [diesel::backend::Backend].
+#[cfg(feature = "postgres")]
+pub type DbBackend = diesel::pg::Pg;
+/// Selected [diesel::backend::Backend].
+#[cfg(not(feature = "postgres"))]
+pub type DbBackend = diesel::sqlite::Sqlite;
 
-type PgPool = Pool<AsyncPgConnection>;
+pub(crate) type ConnectionPool = Pool<DbConnection>;
 /// Type describing a connection from the [DbPool].
-pub type PooledConnection = Object<AsyncDieselConnectionManager<AsyncPgConnection>>;
+pub type PooledConnection = Object<AsyncDieselConnectionManager<DbConnection>>;
+/// Error returned when retrieving a [PooledConnection] fails.
+pub type DbPoolError = deadpool::managed::PoolError<diesel_async::pooled_connection::PoolError>;
+
+/// Returns the url of the primary database from `DATABASE_URL`.
+pub(crate) fn database_url() -> Result<String, env::VarError> {
+    env::var("DATABASE_URL")
+}
+
+fn init_db() -> ConnectionPool {
+    let uri = database_url().expect("No DATABASE_URL found");
+    #[cfg(feature = "metrics")]
+    metrics::init();
+    build_pool(uri, pool_config())
+}
+
+/// Retrieves a connection from `pool`, recording the wait time if metrics are enabled.
+async fn checkout(pool: &ConnectionPool) -> Result<PooledConnection, DbPoolError> {
+    #[cfg(feature = "metrics")]
+    let started = std::time::Instant::now();
+    let result = pool.get().await;
+    #[cfg(feature = "metrics")]
+    metrics::record_checkout(started.elapsed(), &result);
+    result
+}
+
+/// Returns the [PoolConfig] from env, overridden by the [DatabaseFairing].
+///
+/// It is computed once when the fairing ignites, or from env alone if the pool is used without it.
+pub(crate) fn pool_config() -> &'static PoolConfig {
+    POOL_CONFIG.get_or_init(PoolConfig::from_env)
+}
+
+/// Builds a pool of connections to `uri`.
+pub(crate) fn build_pool(uri: String, config: &PoolConfig) -> ConnectionPool {
+    let config = config.clone();
+    let recycle_config = config.clone();
+    let manager = AsyncDieselConnectionManager::<DbConnection>::new_with_config(uri, config.manager_config());
+    let mut builder = Pool::builder(manager)
+        .runtime(deadpool::Runtime::Tokio1)
+        .wait_timeout(config.wait_timeout)
+        .create_timeout(config.create_timeout)
+        .recycle_timeout(config.recycle_timeout);
 
-fn init_db() -> PgPool {
-    let uri = env::var("DATABASE_URL").expect("No DATABASE_URL found");
+    if let Some(max_size) = config.max_size {
+        builder = builder.max_size(max_size);
+    }
+
+    if config.has_setup() {
+        builder = builder.post_create(Hook::async_fn(move |conn, _| {
+            let config = config.clone();
+            Box::pin(async move {
+                config.setup(conn).await
+                    .map_err(|e| HookError::Backend(diesel_async::pooled_connection::PoolError::QueryError(e)))
+            })
+        }));
+    }
+
+    #[cfg(feature = "postgres")]
+    let tenant_isolation = TENANT_ISOLATION.get().is_some();
+    #[cfg(not(feature = "postgres"))]
+    let tenant_isolation = false;
+
+    if tenant_isolation || recycle_config.has_instrumentation() {
+        builder = builder.post_recycle(Hook::async_fn(move |conn, _| {
+            // Removes the route of the previous request
+            recycle_config.instrument(conn, None);
+            Box::pin(async move {
+                #[cfg(feature = "postgres")]
+                if let Some(isolation) = TENANT_ISOLATION.get() {
+                    isolation.reset(conn).await
+                        .map_err(|e| HookError::Backend(diesel_async::pooled_connection::PoolError::QueryError(e)))?;
+                }
+
+                Ok(())
+            })
+        }));
+    }
 
-    let manager = AsyncDieselConnectionManager::<AsyncPgConnection>::new(uri);
-    Pool::builder(manager).build().expect("Failed to create deadpool")
+    builder.build().expect("Failed to create deadpool")
+}
+
+/// Runs the pending migrations of all `migrations` in order on `conn`, see [DbPool::run_migrations].
+async fn run_migrations_on(conn: PooledConnection, migrations: Vec<EmbeddedMigrations>) -> Result<Vec<String>, MigrationError> {
+    tokio::task::spawn_blocking(move || {
+        let mut conn = AsyncConnectionWrapper::<PooledConnection>::from(conn);
+        let mut applied = vec![];
+        for migrations in &migrations {
+            applied.extend(migrations::run_pending_migrations(&mut conn, migrations)?);
+        }
+
+        Ok(applied)
+    }).await?
 }
 
 /// Holds functions to retrieve connections from the pool.
@@ -79,13 +408,60 @@ impl DbPool {
     /// This requires [Self::get_or_init_conn] to be called first.
     ///
     /// This usually happens through the [DatabaseFairing].
-    pub async fn get_conn() -> Result<PooledConnection, deadpool::managed::PoolError<diesel_async::pooled_connection::PoolError>> {
-        DB_POOL.get().unwrap().get().await
+    pub async fn get_conn() -> Result<PooledConnection, DbPoolError> {
+        checkout(DB_POOL.get().unwrap()).await
     }
 
     /// Gets a connection and initialize the pool if not initialized.
-    pub async fn get_or_init_conn() -> Result<PooledConnection, deadpool::managed::PoolError<diesel_async::pooled_connection::PoolError>> {
-        DB_POOL.get_or_init(init_db).get().await
+    pub async fn get_or_init_conn() -> Result<PooledConnection, DbPoolError> {
+        checkout(DB_POOL.get_or_init(init_db)).await
+    }
+
+    /// Runs the pending migrations of all `migrations` in order, each while holding the migration lock.
+    ///
+    /// Returns the names of the applied migrations.
+    /// This usually happens through [DatabaseFairing::with_migrations].
+    pub async fn run_migrations(migrations: Vec<EmbeddedMigrations>) -> Result<Vec<String>, MigrationError> {
+        run_migrations_on(Self::get_or_init_conn().await?, migrations).await
+    }
+
+    /// Reports the status of the pool and the round-trip time of a query to the primary.
+    ///
+    /// Unlike [Self::get_conn], this never panics if the pool is not initialized.
+    pub async fn health() -> DbHealth {
+        match DB_POOL.get() {
+            Some(pool) => DbHealth::check(pool).await,
+            None => DbHealth::uninitialized(),
+        }
+    }
+
+    /// Retrieves a [PooledConnection] for reads from a replica.
+    ///
+    /// Replicas from `DATABASE_REPLICA_URLS` are selected round-robin, skipping replicas which recently failed.
+    /// Falls back to [Self::get_conn] if no replica is configured or available.
+    #[cfg(feature = "postgres")]
+    pub async fn get_read_conn() -> Result<PooledConnection, DbPoolError> {
+        match replica::Replicas::get().get_conn().await {
+            Some(conn) => Ok(conn),
+            None => Self::get_conn().await,
+        }
+    }
+
+    /// Retrieves a [PooledConnection] isolated to `tenant`.
+    ///
+    /// The tenant is applied as configured by [DatabaseFairing::with_tenant_isolation]
+    /// and removed again once the connection returns to the pool.
+    ///
+    /// # Panics
+    /// Panics if no [TenantIsolation] was configured.
+    #[cfg(feature = "postgres")]
+    pub async fn get_tenant_conn(tenant: &str) -> Result<PooledConnection, DbPoolError> {
+        let isolation = TENANT_ISOLATION.get().expect("No tenant isolation configured");
+        let mut conn = Self::get_conn().await?;
+        isolation.apply(&mut conn, tenant).await
+            .map_err(|e| deadpool::managed::PoolError::Backend(diesel_async::pooled_connection::PoolError::QueryError(e)))?;
+
+        Ok(conn)
     }
 }
 
@@ -105,5 +481,6 @@ mod tests {
         let result = diesel::select("healthy".into_sql::<Text>()).load::<String>(&mut conn).await.unwrap();
         assert_eq!(result.len(), 1);
         assert_eq!(result[0], "healthy");
+        assert!(DbPool::health().await.is_healthy());
     }
 }
\ No newline at end of file
@@ -0,0 +1,294 @@
+//! Contains the dedicated connection receiving Postgres notifications for [DbPool::subscribe].
+//!
+//! Notifications are fanned out to all [Subscription]s of their channel. The connection is re-established
+//! with exponential backoff if it is lost, subscribing to all channels again. Notifications sent while
+//! disconnected are lost. Channels are unsubscribed once their last [Subscription] is dropped.
+
+use std::collections::HashMap;
+use std::env::VarError;
+use std::future::poll_fn;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use diesel::sql_types::Text;
+use diesel_async::RunQueryDsl;
+use rocket::response::stream::{Event, EventStream};
+use rocket::tokio::sync::{broadcast, mpsc};
+use rocket::{error, info, tokio, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_postgres::{AsyncMessage, NoTls, Notification};
+use crate::{database_url, DbPool, PooledConnection};
+
+/// Amount of notifications buffered per channel for slow subscribers.
+const CHANNEL_CAPACITY: usize = 256;
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+type Channels = Arc<Mutex<HashMap<String, broadcast::Sender<String>>>>;
+
+static LISTENER: OnceLock<Listener> = OnceLock::new();
+
+/// Change of the channels the listener connection is subscribed to.
+enum Command {
+    Listen(String),
+    Unlisten(String),
+}
+
+struct Listener {
+    channels: Channels,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Listener {
+    fn get() -> Result<&'static Listener, VarError> {
+        if let Some(listener) = LISTENER.get() {
+            return Ok(listener);
+        }
+
+        let uri = database_url()?;
+        Ok(LISTENER.get_or_init(|| {
+            let channels = Channels::default();
+            let (commands, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(listen(uri, channels.clone(), receiver));
+            Listener { channels, commands }
+        }))
+    }
+}
+
+/// Typed subscription to a Postgres notification channel, see [DbPool::subscribe].
+pub struct Subscription<T> {
+    channel: String,
+    receiver: broadcast::Receiver<String>,
+    payload: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> Subscription<T> {
+    /// Waits for the next notification.
+    ///
+    /// Payloads which cannot be deserialized into `T` are skipped, as are notifications missed by a slow subscriber.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(payload) => match serde_json::from_str(&payload) {
+                    Ok(payload) => return Some(payload),
+                    Err(e) => warn!("Invalid payload on channel {}: {}", self.channel, e),
+                },
+                Err(broadcast::error::RecvError::Lagged(count)) => warn!("Missed {} notifications on channel {}", count, self.channel),
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        let Some(listener) = LISTENER.get() else {
+            return;
+        };
+
+        let mut channels = listener.channels.lock().unwrap();
+        // The receiver of this subscription is still alive
+        if channels.get(&self.channel).is_some_and(|sender| sender.receiver_count() <= 1) {
+            channels.remove(&self.channel);
+            // The listener task only stops if the runtime shuts down
+            let _ = listener.commands.send(Command::Unlisten(self.channel.clone()));
+        }
+    }
+}
+
+impl<T: DeserializeOwned + Serialize + Send + 'static> Subscription<T> {
+    /// Streams all notifications as Server-Sent Events named after the channel.
+    ///
+    /// Return this from a route to push notifications to clients:
+    /// ```rust,ignore
+    /// #[get("/orders/events")]
+    /// fn order_events() -> Result<EventStream![], Status> {
+    ///     let subscription = DbPool::subscribe::<OrderChanged>("orders").map_err(|_| Status::InternalServerError)?;
+    ///     Ok(subscription.into_event_stream())
+    /// }
+    /// ```
+    pub fn into_event_stream(mut self) -> EventStream![] {
+        EventStream! {
+            while let Some(payload) = self.recv().await {
+                match serde_json::to_string(&payload) {
+                    Ok(data) => yield Event::data(data).event(self.channel.clone()),
+                    Err(e) => error!("Failed to serialize payload on channel {}: {}", self.channel, e),
+                }
+            }
+        }
+    }
+}
+
+impl DbPool {
+    /// Subscribes to the Postgres notification channel `channel`, deserializing payloads as JSON into `T`.
+    ///
+    /// All subscriptions share one dedicated connection to `DATABASE_URL`, which is opened on the first subscription.
+    /// Fails if `DATABASE_URL` is not set. Has to be called inside the Tokio runtime of Rocket.
+    pub fn subscribe<T: DeserializeOwned>(channel: &str) -> Result<Subscription<T>, VarError> {
+        let listener = Listener::get()?;
+        let receiver = {
+            let mut channels = listener.channels.lock().unwrap();
+            match channels.get(channel) {
+                Some(sender) => sender.subscribe(),
+                None => {
+                    let (sender, receiver) = broadcast::channel(CHANNEL_CAPACITY);
+                    channels.insert(channel.to_string(), sender);
+                    // The listener task only stops if the runtime shuts down
+                    let _ = listener.commands.send(Command::Listen(channel.to_string()));
+                    receiver
+                }
+            }
+        };
+
+        Ok(Subscription {
+            channel: channel.to_string(),
+            receiver,
+            payload: PhantomData,
+        })
+    }
+
+    /// Sends `payload` as JSON to all subscribers of `channel`.
+    ///
+    /// Inside a transaction, the notification is only sent once the transaction commits.
+    pub async fn notify<T: Serialize>(channel: &str, payload: &T, conn: &mut PooledConnection) -> diesel::QueryResult<()> {
+        let payload = serde_json::to_string(payload).map_err(|e| diesel::result::Error::SerializationError(Box::new(e)))?;
+        diesel::sql_query("SELECT pg_notify($1, $2)")
+            .bind::<Text, _>(channel)
+            .bind::<Text, _>(payload)
+            .execute(conn)
+            .await
+            .map(|_| ())
+    }
+}
+
+/// Keeps a connection listening to all subscribed channels, reconnecting if it is lost.
+async fn listen(uri: String, channels: Channels, mut commands: mpsc::UnboundedReceiver<Command>) {
+    let mut delay = Duration::from_secs(1);
+    loop {
+        match connect(&uri).await {
+            Ok((client, mut notifications)) => {
+                let subscribed = channels.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+                let mut result = Ok(());
+                for channel in subscribed {
+                    result = result.and(client.batch_execute(&listen_query(&channel)).await);
+                }
+
+                if result.is_ok() {
+                    info!("Listening for notifications");
+                    delay = Duration::from_secs(1);
+                }
+
+                while result.is_ok() {
+                    tokio::select! {
+                        command = commands.recv() => match command {
+                            Some(Command::Listen(channel)) => result = client.batch_execute(&listen_query(&channel)).await,
+                            Some(Command::Unlisten(channel)) => result = client.batch_execute(&unlisten_query(&channel)).await,
+                            None => return,
+                        },
+                        notification = notifications.recv() => match notification {
+                            Some(notification) => dispatch(&channels, notification),
+                            None => break,
+                        },
+                    }
+                }
+
+                if let Err(e) = result {
+                    error!("Failed to listen for notifications: {}", e);
+                }
+                warn!("Lost notification connection, reconnecting in {}s", delay.as_secs());
+            }
+            Err(e) => error!("Failed to open notification connection, retrying in {}s: {}", delay.as_secs(), e),
+        }
+
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Opens a connection and returns its client and the notifications it receives.
+async fn connect(uri: &str) -> Result<(tokio_postgres::Client, mpsc::UnboundedReceiver<Notification>), tokio_postgres::Error> {
+    let (client, mut connection) = tokio_postgres::connect(uri, NoTls).await?;
+    let (sender, notifications) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(message) = poll_fn(|cx| connection.poll_message(cx)).await {
+            match message {
+                Ok(AsyncMessage::Notification(notification)) => {
+                    if sender.send(notification).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!("Notification connection failed: {}", e);
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok((client, notifications))
+}
+
+fn listen_query(channel: &str) -> String {
+    format!("LISTEN {}", quote_channel(channel))
+}
+
+fn unlisten_query(channel: &str) -> String {
+    format!("UNLISTEN {}", quote_channel(channel))
+}
+
+fn quote_channel(channel: &str) -> String {
+    format!("\"{}\"", channel.replace('"', "\"\""))
+}
+
+fn dispatch(channels: &Channels, notification: Notification) {
+    if let Some(sender) = channels.lock().unwrap().get(notification.channel()) {
+        // Fails only if there are no subscribers left
+        let _ = sender.send(notification.payload().to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rocket::{async_test, tokio};
+    use serde::{Deserialize, Serialize};
+    use ferrox_env::EnvLoader;
+    use crate::listener::LISTENER;
+    use crate::DbPool;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Changed {
+        id: i32,
+    }
+
+    #[async_test]
+    async fn test_subscribe() {
+        EnvLoader::load_test();
+        let mut subscription = DbPool::subscribe::<Changed>("ferrox_test \"channel\"").unwrap();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+
+        // Notifications sent before the listener subscribed are lost, so notify until one is received
+        let mut received = false;
+        for _ in 0..50 {
+            DbPool::notify("ferrox_test \"channel\"", &Changed { id: 1 }, &mut conn).await.unwrap();
+            if let Ok(changed) = tokio::time::timeout(Duration::from_millis(100), subscription.recv()).await {
+                assert_eq!(changed, Some(Changed { id: 1 }));
+                received = true;
+                break;
+            }
+        }
+        assert!(received, "No notification received");
+
+        // The channel is unsubscribed with its last subscription
+
+        let channels = || LISTENER.get().unwrap().channels.lock().unwrap().contains_key("ferrox_test \"channel\"");
+        let second = DbPool::subscribe::<Changed>("ferrox_test \"channel\"").unwrap();
+        drop(subscription);
+        assert!(channels());
+        drop(second);
+        assert!(!channels());
+    }
+}
@@ -0,0 +1,113 @@
+//! Contains the Prometheus metrics of the [crate::DbPool].
+//!
+//! All metrics are registered in the [prometheus::default_registry].
+
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{register, Histogram, HistogramOpts, HistogramVec, IntCounter, IntGaugeVec, Opts};
+
+use crate::{DbPoolError, PooledConnection, DB_POOL};
+
+struct DbMetrics {
+    wait: Histogram,
+    timeouts: IntCounter,
+    queries: HistogramVec,
+}
+
+static DB_METRICS: OnceLock<DbMetrics> = OnceLock::new();
+
+fn metrics() -> &'static DbMetrics {
+    DB_METRICS.get_or_init(|| {
+        let metrics = DbMetrics {
+            wait: Histogram::with_opts(HistogramOpts::new(
+                "ferrox_db_pool_wait_seconds",
+                "Time waited for a connection from the pool",
+            )).unwrap(),
+            timeouts: IntCounter::new(
+                "ferrox_db_pool_timeouts_total",
+                "Number of timed out attempts to get a connection from the pool",
+            ).unwrap(),
+            queries: HistogramVec::new(HistogramOpts::new(
+                "ferrox_db_query_seconds",
+                "Execution time of queries",
+            ), &["statement"]).unwrap(),
+        };
+
+        register(Box::new(metrics.wait.clone())).unwrap();
+        register(Box::new(metrics.timeouts.clone())).unwrap();
+        register(Box::new(metrics.queries.clone())).unwrap();
+        register(Box::new(PoolCollector::new())).unwrap();
+
+        metrics
+    })
+}
+
+/// Registers all metrics, so they are exported before the first connection is used.
+pub(crate) fn init() {
+    metrics();
+}
+
+/// Records the wait time of a connection from the pool.
+pub(crate) fn record_checkout(waited: Duration, result: &Result<PooledConnection, DbPoolError>) {
+    let metrics = metrics();
+    metrics.wait.observe(waited.as_secs_f64());
+    if let Err(DbPoolError::Timeout(_)) = result {
+        metrics.timeouts.inc();
+    }
+}
+
+/// Reports the current connections of the pool whenever metrics are collected.
+struct PoolCollector {
+    connections: IntGaugeVec,
+}
+
+impl PoolCollector {
+    fn new() -> Self {
+        PoolCollector {
+            connections: IntGaugeVec::new(Opts::new(
+                "ferrox_db_pool_connections",
+                "Number of connections of the pool",
+            ), &["state"]).unwrap(),
+        }
+    }
+}
+
+impl Collector for PoolCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.connections.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        if let Some(pool) = DB_POOL.get() {
+            let status = pool.status();
+            self.connections.with_label_values(&["active"]).set((status.size - status.available) as i64);
+            self.connections.with_label_values(&["idle"]).set(status.available as i64);
+            self.connections.with_label_values(&["waiting"]).set(status.waiting as i64);
+            self.connections.with_label_values(&["max"]).set(status.max_size as i64);
+        }
+
+        self.connections.collect()
+    }
+}
+
+/// Records the execution time of a query by its statement, e.g. `select`.
+pub(crate) fn record_query(sql: &str, duration: Duration) {
+    metrics().queries.with_label_values(&[statement(sql)]).observe(duration.as_secs_f64());
+}
+
+/// Returns the statement of a query with a bounded set of values to keep the label cardinality low.
+fn statement(sql: &str) -> &'static str {
+    let keyword = sql.split_whitespace().next().unwrap_or_default().to_lowercase();
+    match keyword.as_str() {
+        "select" => "select",
+        "insert" => "insert",
+        "update" => "update",
+        "delete" => "delete",
+        "with" => "with",
+        "begin" | "commit" | "rollback" | "savepoint" | "release" => "transaction",
+        _ => "other",
+    }
+}
@@ -0,0 +1,157 @@
+//! Contains the execution of [EmbeddedMigrations].
+
+use std::error::Error;
+
+use diesel::migration::{Migration, MigrationSource};
+use diesel::Connection;
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
+use rocket::info;
+use crate::DbBackend;
+
+/// Key of the advisory lock held while migrations are running.
+#[cfg(feature = "postgres")]
+const MIGRATION_LOCK: i64 = 0x6665_7272_6f78;
+
+/// Error of a migration.
+pub type MigrationError = Box<dyn Error + Send + Sync>;
+
+/// Runs `f` inside a transaction holding the migration advisory lock.
+///
+/// Concurrent callers, e.g. several replicas starting together, wait for the lock instead of racing.
+/// The lock is released with the transaction, so it never outlives an error or a panic of `f`,
+/// which is resumed after the transaction was rolled back.
+#[cfg(feature = "postgres")]
+pub(crate) fn with_migration_lock<C, T>(conn: &mut C, f: impl FnOnce(&mut C) -> Result<T, MigrationError>) -> Result<T, MigrationError>
+where
+    C: Connection<Backend = DbBackend>,
+{
+    use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+    use diesel::RunQueryDsl;
+
+    let mut panic = None;
+    let result = conn.transaction(|conn| {
+        diesel::sql_query(format!("SELECT pg_advisory_xact_lock({})", MIGRATION_LOCK)).execute(conn)?;
+        catch_unwind(AssertUnwindSafe(|| f(conn))).unwrap_or_else(|payload| {
+            panic = Some(payload);
+            Err("Migration panicked".into())
+        })
+    });
+
+    if let Some(payload) = panic {
+        resume_unwind(payload);
+    }
+    result
+}
+
+/// Runs `f` while holding the migration advisory lock on the session of `conn`.
+///
+/// Only used with dedicated connections which are closed afterwards, as statements like `CREATE DATABASE`
+/// can not run inside the transaction of [with_migration_lock].
+#[cfg(all(feature = "postgres", any(test, feature = "testing")))]
+pub(crate) fn with_session_migration_lock<C, T>(conn: &mut C, f: impl FnOnce(&mut C) -> Result<T, MigrationError>) -> Result<T, MigrationError>
+where
+    C: Connection<Backend = DbBackend>,
+{
+    use diesel::RunQueryDsl;
+
+    diesel::sql_query(format!("SELECT pg_advisory_lock({})", MIGRATION_LOCK)).execute(conn)?;
+    let result = f(conn);
+    diesel::sql_query(format!("SELECT pg_advisory_unlock({})", MIGRATION_LOCK)).execute(conn)?;
+    result
+}
+
+/// Runs `f` without a lock, as SQLite only allows a single writer anyway.
+#[cfg(not(feature = "postgres"))]
+pub(crate) fn with_migration_lock<C, T>(conn: &mut C, f: impl FnOnce(&mut C) -> Result<T, MigrationError>) -> Result<T, MigrationError>
+where
+    C: Connection<Backend = DbBackend>,
+{
+    f(conn)
+}
+
+/// Returns all migrations of `migrations` sorted by version.
+pub(crate) fn all_migrations(migrations: &EmbeddedMigrations) -> Result<Vec<Box<dyn Migration<DbBackend>>>, MigrationError> {
+    let mut migrations = MigrationSource::<DbBackend>::migrations(migrations)?;
+    migrations.sort_by(|a, b| a.name().version().cmp(&b.name().version()));
+    Ok(migrations)
+}
+
+/// Returns all migrations of `migrations` which are not applied yet, sorted by version.
+pub(crate) fn pending_migrations<C>(conn: &mut C, migrations: &EmbeddedMigrations) -> Result<Vec<Box<dyn Migration<DbBackend>>>, MigrationError>
+where
+    C: MigrationHarness<DbBackend>,
+{
+    let applied = conn.applied_migrations()?;
+    Ok(all_migrations(migrations)?
+        .into_iter()
+        .filter(|migration| !applied.contains(&migration.name().version()))
+        .collect())
+}
+
+/// Runs all pending migrations without locking and logs each applied one.
+pub(crate) fn run_unlocked<C>(conn: &mut C, migrations: &EmbeddedMigrations) -> Result<Vec<String>, MigrationError>
+where
+    C: MigrationHarness<DbBackend>,
+{
+    let mut names = vec![];
+    for migration in pending_migrations(conn, migrations)? {
+        conn.run_migration(&*migration)?;
+        info!("Applied migration {}", migration.name());
+        names.push(migration.name().to_string());
+    }
+
+    Ok(names)
+}
+
+/// Runs all pending migrations and logs each applied one.
+///
+/// Returns the names of the applied migrations.
+pub(crate) fn run_pending_migrations<C>(conn: &mut C, migrations: &EmbeddedMigrations) -> Result<Vec<String>, MigrationError>
+where
+    C: Connection<Backend = DbBackend> + MigrationHarness<DbBackend>,
+{
+    with_migration_lock(conn, |conn| run_unlocked(conn, migrations))
+}
+
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    use diesel::sql_types::Bool;
+    use diesel::{Connection, PgConnection, QueryableByName, RunQueryDsl};
+    use ferrox_env::EnvLoader;
+    use super::{with_migration_lock, MigrationError, MIGRATION_LOCK};
+
+    #[derive(QueryableByName)]
+    struct Locked {
+        #[diesel(sql_type = Bool)]
+        locked: bool,
+    }
+
+    fn lock_available(conn: &mut PgConnection) -> bool {
+        let locked = diesel::sql_query(format!("SELECT pg_try_advisory_xact_lock({}) AS locked", MIGRATION_LOCK))
+            .get_result::<Locked>(conn)
+            .unwrap();
+        locked.locked
+    }
+
+    #[test]
+    fn test_migration_lock_release() {
+        EnvLoader::load_test();
+        let url = std::env::var("DATABASE_URL").unwrap();
+        let mut conn = PgConnection::establish(&url).unwrap();
+        let mut other = PgConnection::establish(&url).unwrap();
+
+        let result = with_migration_lock(&mut conn, |_| {
+            assert!(!other.transaction(|other| Ok::<_, diesel::result::Error>(lock_available(other))).unwrap());
+            Err::<(), MigrationError>("failed".into())
+        });
+        assert!(result.is_err());
+        assert!(other.transaction(|other| Ok::<_, diesel::result::Error>(lock_available(other))).unwrap());
+
+        let result = catch_unwind(AssertUnwindSafe(|| with_migration_lock(&mut conn, |_| -> Result<(), MigrationError> { panic!("failed") })));
+        assert!(result.is_err());
+        assert!(other.transaction(|other| Ok::<_, diesel::result::Error>(lock_available(other))).unwrap());
+        assert!(lock_available(&mut conn));
+    }
+}
@@ -0,0 +1,233 @@
+//! Contains the routing of reads to replicas of the [crate::DbPool].
+//!
+//! Replicas are configured through `DATABASE_REPLICA_URLS`, a comma separated list of urls.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rocket::http::{Cookie, Method, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::{async_trait, error, warn, Request};
+use crate::{build_pool, pool_config, DbPool, ConnectionPool, PooledConnection};
+
+/// Name of the cookie routing reads of a client to the primary after a write.
+pub const PRIMARY_COOKIE_NAME: &str = "DbPrimaryUntil";
+
+/// Time a replica is skipped after a connection to it failed.
+const UNHEALTHY_BACKOFF: Duration = Duration::from_secs(30);
+
+static REPLICAS: OnceLock<Replicas> = OnceLock::new();
+
+struct Replica {
+    url_index: usize,
+    pool: ConnectionPool,
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl Replica {
+    fn is_healthy(&self) -> bool {
+        self.unhealthy_until.lock().unwrap().is_none_or(|until| until <= Instant::now())
+    }
+
+    fn mark_unhealthy(&self) {
+        *self.unhealthy_until.lock().unwrap() = Some(Instant::now() + UNHEALTHY_BACKOFF);
+    }
+}
+
+/// Replicas of the primary database, selected round-robin.
+pub(crate) struct Replicas {
+    replicas: Vec<Replica>,
+    next: AtomicUsize,
+}
+
+impl Replicas {
+    pub(crate) fn get() -> &'static Self {
+        REPLICAS.get_or_init(Self::init)
+    }
+
+    fn init() -> Self {
+        let config = pool_config();
+        let replicas = std::env::var("DATABASE_REPLICA_URLS").unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .enumerate()
+            .map(|(url_index, url)| Replica {
+                url_index,
+                pool: build_pool(url.to_string(), config),
+                unhealthy_until: Mutex::new(None),
+            })
+            .collect();
+
+        Replicas {
+            replicas,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    /// Retrieves a connection from the next healthy replica.
+    ///
+    /// Replicas failing to provide a connection are skipped for [UNHEALTHY_BACKOFF].
+    pub(crate) async fn get_conn(&self) -> Option<PooledConnection> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        for offset in 0..self.replicas.len() {
+            let replica = &self.replicas[(start + offset) % self.replicas.len()];
+            if !replica.is_healthy() {
+                continue;
+            }
+
+            match replica.pool.get().await {
+                Ok(conn) => return Some(conn),
+                Err(e) => {
+                    warn!("Replica {} is unhealthy: {}", replica.url_index, e);
+                    replica.mark_unhealthy();
+                }
+            }
+        }
+
+        None
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Routes further reads of the client to the primary if `request` may write.
+///
+/// Called by [crate::DbConn] and [crate::Tx] for requests with methods other than `GET`, `HEAD` and `OPTIONS`.
+pub(crate) fn mark_write(request: &Request<'_>) {
+    if matches!(request.method(), Method::Get | Method::Head | Method::Options) || Replicas::get().is_empty() {
+        return;
+    }
+
+    let stickiness = pool_config().replica_stickiness.unwrap_or(Duration::from_secs(5));
+    let until = now_secs() + stickiness.as_secs();
+    request.cookies().add(Cookie::build((PRIMARY_COOKIE_NAME, until.to_string()))
+        .path("/")
+        .http_only(true)
+        .max_age(rocket::time::Duration::seconds(stickiness.as_secs() as i64)));
+}
+
+/// Request guard providing a [PooledConnection] for read-only handlers.
+///
+/// Connections are retrieved through [DbPool::get_read_conn], unless the client wrote recently.
+/// After a write through [crate::DbConn] or [crate::Tx], reads of this client are routed to the primary
+/// for the configured stickiness (see [crate::PoolConfig::replica_stickiness]), so it reads its own writes.
+///
+/// This will respond with [Status::InternalServerError] if no connection is available.
+pub struct ReadConn(ReadConnection);
+
+enum ReadConnection {
+    Pooled(PooledConnection),
+    /// Connection of a `TestTransaction` given to [crate::DatabaseFairing::with_test_transaction].
+    #[cfg(any(test, feature = "testing"))]
+    Shared(crate::DbConn),
+}
+
+impl Deref for ReadConn {
+    type Target = PooledConnection;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.0 {
+            ReadConnection::Pooled(conn) => conn,
+            #[cfg(any(test, feature = "testing"))]
+            ReadConnection::Shared(conn) => conn,
+        }
+    }
+}
+
+impl DerefMut for ReadConn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut self.0 {
+            ReadConnection::Pooled(conn) => conn,
+            #[cfg(any(test, feature = "testing"))]
+            ReadConnection::Shared(conn) => conn,
+        }
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for ReadConn {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        #[cfg(any(test, feature = "testing"))]
+        match request.rocket().state() {
+            Some(crate::testing::TestConnections::Transaction(conn)) => {
+                return match conn.clone().try_lock_owned() {
+                    Ok(guard) => Outcome::Success(ReadConn(ReadConnection::Shared(crate::DbConn(guard)))),
+                    Err(_) => Outcome::Error((Status::InternalServerError, "Connection already in use")),
+                };
+            }
+            Some(crate::testing::TestConnections::Database(pool)) => {
+                return match pool.get().await {
+                    Ok(conn) => Outcome::Success(ReadConn(ReadConnection::Pooled(conn))),
+                    Err(e) => {
+                        error!("Failed to get connection: {}", e);
+                        Outcome::Error((Status::InternalServerError, "Failed to get connection"))
+                    }
+                };
+            }
+            None => {}
+        }
+
+        let sticky = request.cookies().get(PRIMARY_COOKIE_NAME)
+            .and_then(|cookie| cookie.value().parse::<u64>().ok())
+            .is_some_and(|until| until > now_secs());
+
+        let conn = if sticky {
+            DbPool::get_conn().await
+        } else {
+            DbPool::get_read_conn().await
+        };
+
+        match conn {
+            Ok(mut conn) => {
+                crate::pool_config().instrument(&mut conn, crate::request::route_name(request));
+                Outcome::Success(ReadConn(ReadConnection::Pooled(conn)))
+            }
+            Err(e) => {
+                error!("Failed to get connection: {}", e);
+                Outcome::Error((Status::InternalServerError, "Failed to get connection"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+
+    use rocket::async_test;
+    use ferrox_env::EnvLoader;
+    use crate::replica::{Replica, Replicas};
+    use crate::{build_pool, PoolConfig};
+
+    #[async_test]
+    async fn test_unhealthy_replica() {
+        EnvLoader::load_test();
+        let urls = ["postgres://user@127.0.0.1:1/db_name".to_string(), std::env::var("DATABASE_URL").unwrap()];
+        let replicas = Replicas {
+            replicas: urls.into_iter().enumerate().map(|(url_index, url)| Replica {
+                url_index,
+                pool: build_pool(url, &PoolConfig::default()),
+                unhealthy_until: Mutex::new(None),
+            }).collect(),
+            next: AtomicUsize::new(0),
+        };
+
+        for _ in 0..3 {
+            assert!(replicas.get_conn().await.is_some());
+        }
+        assert!(!replicas.replicas[0].is_healthy());
+        assert!(replicas.replicas[1].is_healthy());
+    }
+}
@@ -0,0 +1,285 @@
+//! Contains the per-request connection guards [DbConn] and [Tx].
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use diesel::result::Error;
+use diesel_async::{AsyncConnection, TransactionManager};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::tokio::sync::{Mutex, OwnedMutexGuard};
+use rocket::{async_trait, error, Request};
+use crate::{DbConnection, DbPool, PooledConnection};
+
+type AnsiTransactionManager = <DbConnection as AsyncConnection>::TransactionManager;
+
+/// Connection shared by all [DbConn] and [Tx] guards of a request.
+#[derive(Default)]
+struct RequestConnection {
+    conn: Arc<Mutex<Option<PooledConnection>>>,
+    transaction: AtomicBool,
+    /// Whether `conn` is the connection of a `TestTransaction` shared by all requests.
+    shared: bool,
+}
+
+impl RequestConnection {
+    fn get<'r>(request: &'r Request<'_>) -> &'r Self {
+        request.local_cache(|| {
+            #[cfg(all(feature = "postgres", any(test, feature = "testing")))]
+            if let Some(crate::testing::TestConnections::Transaction(conn)) = request.rocket().state() {
+                return RequestConnection {
+                    conn: conn.clone(),
+                    transaction: AtomicBool::new(false),
+                    shared: true,
+                };
+            }
+
+            RequestConnection::default()
+        })
+    }
+}
+
+/// Retrieves a connection for `request` from the [DbPool], or from the pool of a `TestDatabase`
+/// given to [crate::DatabaseFairing::with_test_database].
+#[cfg_attr(not(all(feature = "postgres", any(test, feature = "testing"))), allow(unused_variables))]
+pub(crate) async fn checkout_request(request: &Request<'_>) -> Result<PooledConnection, crate::DbPoolError> {
+    #[cfg(all(feature = "postgres", any(test, feature = "testing")))]
+    if let Some(crate::testing::TestConnections::Database(pool)) = request.rocket().state() {
+        return pool.get().await;
+    }
+
+    DbPool::get_conn().await
+}
+
+/// Request guard providing the [PooledConnection] of the current request.
+///
+/// The connection is checked out from the [DbPool] once per request and shared with all other
+/// [DbConn] and [Tx] guards, including the one used by `ferrox_auth` to load the login.
+/// It is returned to the pool once the response is sent.
+///
+/// With Postgres, further reads of the client are routed to the primary for requests which may write, see `ReadConn`.
+///
+/// Only one guard can hold the connection at a time, so a handler should not take more than one of them.
+/// This will respond with [Status::InternalServerError] if no connection is available.
+pub struct DbConn(pub(crate) OwnedMutexGuard<Option<PooledConnection>>);
+
+impl Deref for DbConn {
+    type Target = PooledConnection;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for DbConn {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_mut().unwrap()
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for DbConn {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let state = RequestConnection::get(request);
+        let Ok(mut guard) = state.conn.clone().try_lock_owned() else {
+            return Outcome::Error((Status::InternalServerError, "Connection already in use"));
+        };
+
+        #[cfg(feature = "postgres")]
+        crate::replica::mark_write(request);
+
+        if guard.is_none() {
+            match checkout_request(request).await {
+                Ok(mut conn) => {
+                    crate::pool_config().instrument(&mut conn, route_name(request));
+                    *guard = Some(conn);
+                }
+                Err(e) => {
+                    error!("Failed to get connection: {}", e);
+                    return Outcome::Error((Status::InternalServerError, "Failed to get connection"));
+                }
+            }
+        }
+
+        Outcome::Success(DbConn(guard))
+    }
+}
+
+/// Returns the method and route of `request` to tag queries with, e.g. `GET /users/<id>`.
+pub(crate) fn route_name(request: &Request<'_>) -> Option<String> {
+    request.route().map(|route| format!("{} {}", route.method, route.uri))
+}
+
+/// Request guard providing the [PooledConnection] of the current request inside a transaction.
+///
+/// The transaction is opened on first use and spans the whole request, including [DbConn] guards used afterwards.
+/// It is committed if the response has a status below 400, e.g. a success or a redirect after a form submission,
+/// and rolled back otherwise, which includes panics of the handler.
+/// The commit happens before the response is sent; if it fails, the response is replaced by [Status::InternalServerError].
+///
+/// Requires the [crate::DatabaseFairing] to be attached, otherwise the connection is discarded without commit.
+pub struct Tx(DbConn);
+
+impl Deref for Tx {
+    type Target = PooledConnection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Tx {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for Tx {
+    type Error = &'static str;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let mut conn = match DbConn::from_request(request).await {
+            Outcome::Success(conn) => conn,
+            Outcome::Error(e) => return Outcome::Error(e),
+            Outcome::Forward(status) => return Outcome::Forward(status),
+        };
+
+        let state = RequestConnection::get(request);
+        if !state.transaction.load(Ordering::SeqCst) {
+            if let Err(e) = AnsiTransactionManager::begin_transaction(&mut **conn).await {
+                error!("Failed to begin transaction: {}", e);
+                return Outcome::Error((Status::InternalServerError, "Failed to begin transaction"));
+            }
+            state.transaction.store(true, Ordering::SeqCst);
+        }
+
+        Outcome::Success(Tx(conn))
+    }
+}
+
+/// Finishes the transaction of the request and returns its connection to the pool.
+///
+/// The connection of a `TestTransaction` is kept, its transaction of the request is a savepoint.
+/// Returns the error if the transaction should have been committed but the commit failed.
+pub(crate) async fn finish_request(request: &Request<'_>, status: Status) -> Result<(), Error> {
+    let state = RequestConnection::get(request);
+    let mut guard = state.conn.lock().await;
+    let Some(conn) = guard.as_mut() else {
+        return Ok(());
+    };
+
+    let mut result = Ok(());
+    if state.transaction.swap(false, Ordering::SeqCst) {
+        if status.code < 400 {
+            result = AnsiTransactionManager::commit_transaction(&mut **conn).await;
+            if let Err(e) = &result {
+                error!("Failed to commit transaction: {}", e);
+            }
+        } else if let Err(e) = AnsiTransactionManager::rollback_transaction(&mut **conn).await {
+            error!("Failed to roll back transaction: {}", e);
+        }
+    }
+
+    // Removes the route before the connection returns to the pool
+    crate::pool_config().instrument(conn, None);
+    if !state.shared {
+        guard.take();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::sql_types::{BigInt, Integer};
+    use diesel::QueryableByName;
+    use diesel_async::{RunQueryDsl, SimpleAsyncConnection};
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+    use rocket::response::Redirect;
+    use rocket::{async_test, get, routes};
+    use ferrox_env::EnvLoader;
+    use crate::{DatabaseFairing, DbConn, DbPool, Tx};
+
+    async fn insert(conn: &mut crate::PooledConnection, id: i32) {
+        diesel::sql_query("INSERT INTO ferrox_tx_test VALUES ($1)").bind::<Integer, _>(id).execute(conn).await.unwrap();
+    }
+
+    #[get("/commit/<id>")]
+    async fn commit(mut tx: Tx, id: i32) {
+        insert(&mut tx, id).await;
+    }
+
+    #[get("/redirect/<id>")]
+    async fn redirect(mut tx: Tx, id: i32) -> Redirect {
+        insert(&mut tx, id).await;
+        Redirect::to("/")
+    }
+
+    #[get("/rollback/<id>")]
+    async fn rollback(mut tx: Tx, id: i32) -> Status {
+        insert(&mut tx, id).await;
+        Status::BadRequest
+    }
+
+    #[get("/panic/<id>")]
+    async fn panic(mut tx: Tx, id: i32) {
+        insert(&mut tx, id).await;
+        panic!("handler failed");
+    }
+
+    #[get("/deferred/<id>")]
+    async fn deferred(mut tx: Tx, id: i32) {
+        for _ in 0..2 {
+            diesel::sql_query("INSERT INTO ferrox_tx_deferred_test VALUES ($1)").bind::<Integer, _>(id).execute(&mut *tx).await.unwrap();
+        }
+    }
+
+    #[get("/conn/<id>")]
+    async fn conn(mut conn: DbConn, id: i32) -> Status {
+        insert(&mut conn, id).await;
+        Status::BadRequest
+    }
+
+    #[derive(QueryableByName)]
+    struct Count {
+        #[diesel(sql_type = BigInt)]
+        count: i64,
+    }
+
+    #[async_test]
+    async fn test_transaction() {
+        EnvLoader::load_test();
+        let rocket = rocket::build().attach(DatabaseFairing::default()).mount("/", routes![commit, redirect, rollback, panic, deferred, conn]);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let mut conn = DbPool::get_conn().await.unwrap();
+        conn.batch_execute("CREATE TABLE IF NOT EXISTS ferrox_tx_test (id INT); DELETE FROM ferrox_tx_test").await.unwrap();
+        conn.batch_execute(
+            "CREATE TABLE IF NOT EXISTS ferrox_tx_deferred_test (id INT UNIQUE DEFERRABLE INITIALLY DEFERRED); DELETE FROM ferrox_tx_deferred_test",
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(client.get("/commit/1").dispatch().await.status(), Status::Ok);
+        assert_eq!(client.get("/redirect/6").dispatch().await.status(), Status::SeeOther);
+        assert_eq!(client.get("/rollback/2").dispatch().await.status(), Status::BadRequest);
+        assert_eq!(client.get("/panic/3").dispatch().await.status(), Status::InternalServerError);
+        assert_eq!(client.get("/conn/4").dispatch().await.status(), Status::BadRequest);
+
+        let count = diesel::sql_query("SELECT count(*) AS count FROM ferrox_tx_test WHERE id IN (1, 4, 6)").get_result::<Count>(&mut conn).await.unwrap();
+        let total = diesel::sql_query("SELECT count(*) AS count FROM ferrox_tx_test").get_result::<Count>(&mut conn).await.unwrap();
+        assert_eq!(count.count, 3);
+        assert_eq!(total.count, 3);
+
+        // The unique violation only surfaces on commit, which has to fail the response
+        let response = client.get("/deferred/5").dispatch().await;
+        assert_eq!(response.status(), Status::InternalServerError);
+        assert_eq!(response.into_string().await.unwrap(), r#"{"success":false,"data":null,"msg":"Failed to commit transaction"}"#);
+        let deferred = diesel::sql_query("SELECT count(*) AS count FROM ferrox_tx_deferred_test").get_result::<Count>(&mut conn).await.unwrap();
+        assert_eq!(deferred.count, 0);
+    }
+}
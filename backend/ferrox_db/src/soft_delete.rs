@@ -0,0 +1,124 @@
+//! Contains [SoftDelete] tables, whose rows are marked as deleted instead of being removed, see [SoftDeleteDsl].
+
+use diesel::dsl::{now, sql, Filter, IsNotNull, IsNull};
+use diesel::expression::SqlLiteral;
+use diesel::query_builder::{AsChangeset, AsQuery, IntoUpdateTarget, UpdateStatement};
+use diesel::query_dsl::methods::FilterDsl;
+use diesel::sql_types::{Nullable, Timestamptz};
+use diesel::associations::HasTable;
+use diesel::{Column, ExpressionMethods, Table};
+
+/// Table with a nullable `TIMESTAMPTZ` column marking deleted rows.
+///
+/// ```rust,ignore
+/// impl SoftDelete for users::table {
+///     type DeletedAt = users::deleted_at;
+/// }
+///
+/// let active = users::table.active().load::<User>(&mut conn).await?;
+/// users::table.find(id).soft_delete().execute(&mut conn).await?;
+/// ```
+///
+/// The `crud_store!` macro of `ferrox_core` applies the filter to all routes of a resource with `soft_delete`.
+pub trait SoftDelete: Table {
+    /// Column containing the time the row was deleted at.
+    type DeletedAt: Column<Table = Self, SqlType = Nullable<Timestamptz>> + ExpressionMethods + Default;
+}
+
+type Deleted<T> = IsNotNull<<T as SoftDelete>::DeletedAt>;
+type NotDeleted<T> = IsNull<<T as SoftDelete>::DeletedAt>;
+type MarkDeleted<T> = diesel::dsl::Eq<<T as SoftDelete>::DeletedAt, now>;
+type MarkRestored<T> = diesel::dsl::Eq<<T as SoftDelete>::DeletedAt, SqlLiteral<Nullable<Timestamptz>>>;
+
+/// Extends queries of [SoftDelete] tables, e.g. `users::table` or `users::table.find(id)`.
+pub trait SoftDeleteDsl: HasTable + Sized {
+    /// Filters out deleted rows. Use this instead of the table for all regular queries.
+    fn active(self) -> Filter<Self, NotDeleted<Self::Table>>
+    where
+        Self::Table: SoftDelete,
+        Self: FilterDsl<NotDeleted<Self::Table>>,
+    {
+        FilterDsl::filter(self, <Self::Table as SoftDelete>::DeletedAt::default().is_null())
+    }
+
+    /// Filters out rows which are not deleted, e.g. to list the rows which can be restored.
+    fn deleted(self) -> Filter<Self, Deleted<Self::Table>>
+    where
+        Self::Table: SoftDelete,
+        Self: FilterDsl<Deleted<Self::Table>>,
+    {
+        FilterDsl::filter(self, <Self::Table as SoftDelete>::DeletedAt::default().is_not_null())
+    }
+
+    /// Builds the update marking the rows of this target as deleted, skipping rows which already are.
+    #[allow(clippy::type_complexity)]
+    fn soft_delete<F, V>(self) -> UpdateStatement<Self::Table, F::WhereClause, V>
+    where
+        Self::Table: SoftDelete,
+        Self: FilterDsl<NotDeleted<Self::Table>, Output = F>,
+        F: IntoUpdateTarget<Table = Self::Table>,
+        MarkDeleted<Self::Table>: AsChangeset<Target = Self::Table, Changeset = V>,
+        UpdateStatement<Self::Table, F::WhereClause, V>: AsQuery,
+    {
+        let column = <Self::Table as SoftDelete>::DeletedAt::default();
+        diesel::update(self.active()).set(column.eq(now))
+    }
+
+    /// Builds the update restoring the deleted rows of this target.
+    #[allow(clippy::type_complexity)]
+    fn restore<F, V>(self) -> UpdateStatement<Self::Table, F::WhereClause, V>
+    where
+        Self::Table: SoftDelete,
+        Self: FilterDsl<Deleted<Self::Table>, Output = F>,
+        F: IntoUpdateTarget<Table = Self::Table>,
+        MarkRestored<Self::Table>: AsChangeset<Target = Self::Table, Changeset = V>,
+        UpdateStatement<Self::Table, F::WhereClause, V>: AsQuery,
+    {
+        let column = <Self::Table as SoftDelete>::DeletedAt::default();
+        diesel::update(self.deleted()).set(column.eq(sql::<Nullable<Timestamptz>>("NULL")))
+    }
+}
+
+impl<T: HasTable> SoftDeleteDsl for T {}
+
+#[cfg(test)]
+mod tests {
+    use diesel::QueryDsl;
+    use diesel_async::{RunQueryDsl, SimpleAsyncConnection};
+    use rocket::async_test;
+    use ferrox_env::EnvLoader;
+    use crate::{DbPool, SoftDelete, SoftDeleteDsl};
+
+    diesel::table! {
+        ferrox_soft_delete_test (id) {
+            id -> Integer,
+            deleted_at -> Nullable<Timestamptz>,
+        }
+    }
+
+    impl SoftDelete for ferrox_soft_delete_test::table {
+        type DeletedAt = ferrox_soft_delete_test::deleted_at;
+    }
+
+    #[async_test]
+    async fn test_soft_delete() {
+        EnvLoader::load_test();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        conn.batch_execute("
+            CREATE TABLE IF NOT EXISTS ferrox_soft_delete_test (id INT PRIMARY KEY, deleted_at TIMESTAMPTZ);
+            DELETE FROM ferrox_soft_delete_test;
+            INSERT INTO ferrox_soft_delete_test VALUES (1, NULL), (2, NULL);
+        ").await.unwrap();
+
+        assert_eq!(ferrox_soft_delete_test::table.find(1).soft_delete().execute(&mut conn).await.unwrap(), 1);
+        assert_eq!(ferrox_soft_delete_test::table.find(1).soft_delete().execute(&mut conn).await.unwrap(), 0);
+
+        let active = ferrox_soft_delete_test::table.active().select(ferrox_soft_delete_test::id).load::<i32>(&mut conn).await.unwrap();
+        assert_eq!(active, vec![2]);
+        let deleted = ferrox_soft_delete_test::table.deleted().select(ferrox_soft_delete_test::id).load::<i32>(&mut conn).await.unwrap();
+        assert_eq!(deleted, vec![1]);
+
+        assert_eq!(ferrox_soft_delete_test::table.find(1).restore().execute(&mut conn).await.unwrap(), 1);
+        assert_eq!(ferrox_soft_delete_test::table.active().count().get_result::<i64>(&mut conn).await.unwrap(), 2);
+    }
+}
@@ -0,0 +1,61 @@
+//! Contains the tenant isolation for connections of the [crate::DbPool].
+//!
+//! See [TenantIsolation].
+
+use diesel::sql_types::Text;
+use diesel::QueryResult;
+use diesel_async::{AsyncPgConnection, RunQueryDsl, SimpleAsyncConnection};
+
+/// Describes how a tenant is applied to a connection.
+///
+/// Configure through [crate::DatabaseFairing::with_tenant_isolation] and retrieve
+/// connections with [crate::DbPool::get_tenant_conn].
+#[derive(Clone, Debug)]
+pub enum TenantIsolation {
+    /// Sets a Postgres session variable (e.g. `app.tenant_id`) to the tenant.
+    ///
+    /// Row-level security policies can read it through `current_setting('app.tenant_id')`.
+    SessionVariable(&'static str),
+    /// Sets the `search_path` to the schema named after the tenant, followed by `public`.
+    SearchPath,
+}
+
+impl TenantIsolation {
+    /// Applies the tenant to the connection.
+    pub(crate) async fn apply(&self, conn: &mut AsyncPgConnection, tenant: &str) -> QueryResult<()> {
+        match self {
+            TenantIsolation::SessionVariable(name) => {
+                diesel::sql_query("SELECT set_config($1, $2, false)")
+                    .bind::<Text, _>(*name)
+                    .bind::<Text, _>(tenant)
+                    .execute(conn)
+                    .await
+                    .map(|_| ())
+            }
+            TenantIsolation::SearchPath => {
+                conn.batch_execute(&format!("SET search_path TO {}, public", quote_ident(tenant))).await
+            }
+        }
+    }
+
+    /// Removes any tenant from the connection.
+    ///
+    /// This runs whenever a connection is recycled, so tenants never leak between checkouts.
+    pub(crate) async fn reset(&self, conn: &mut AsyncPgConnection) -> QueryResult<()> {
+        match self {
+            TenantIsolation::SessionVariable(name) => {
+                diesel::sql_query("SELECT set_config($1, '', false)")
+                    .bind::<Text, _>(*name)
+                    .execute(conn)
+                    .await
+                    .map(|_| ())
+            }
+            TenantIsolation::SearchPath => conn.batch_execute("RESET search_path").await,
+        }
+    }
+}
+
+/// Quotes an identifier for Postgres.
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
@@ -0,0 +1,298 @@
+//! Contains utilities for isolating database tests from each other.
+//!
+//! Enabled through the `testing` feature. Both helpers read `DATABASE_URL` through [EnvLoader::load_test].
+//!
+//! - [TestTransaction] wraps a connection of the [DbPool] in a transaction which is always rolled back.
+//! - [TestDatabase] creates a fresh database cloned from a template migrated with the [EmbeddedMigrations]
+//!   also given to [crate::DatabaseFairing::with_migrations].
+//!
+//! Give them to [crate::DatabaseFairing::with_test_transaction] or [crate::DatabaseFairing::with_test_database],
+//! so the [DbConn], [crate::Tx] and [crate::ReadConn] guards of the rocket under test use them instead of the [DbPool].
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+
+use deadpool::managed::Object;
+use diesel::sql_types::Text;
+use diesel::{Connection, PgConnection, QueryableByName, RunQueryDsl};
+use diesel_async::pooled_connection::deadpool::Pool;
+use diesel_async::pooled_connection::AsyncDieselConnectionManager;
+use diesel_async::{AsyncConnection, AsyncPgConnection};
+use diesel_migrations::EmbeddedMigrations;
+use rocket::tokio::sync::Mutex;
+use ferrox_env::EnvLoader;
+use crate::migrations::{all_migrations, run_pending_migrations, with_session_migration_lock, MigrationError};
+use crate::{ConnectionPool, DbConn, DbPool, DbPoolError, PooledConnection};
+
+static TEMPLATES: StdMutex<Option<HashSet<String>>> = StdMutex::new(None);
+static DATABASE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Connections of a [TestDatabase] or [TestTransaction], managed by the [crate::DatabaseFairing].
+#[derive(Clone)]
+pub(crate) enum TestConnections {
+    Database(ConnectionPool),
+    Transaction(Arc<Mutex<Option<PooledConnection>>>),
+}
+
+/// [PooledConnection] inside a transaction which is rolled back when this is dropped.
+///
+/// The connection is removed from the [DbPool] on drop, so the transaction never leaks into other tests.
+/// Requests of a rocket given this through [crate::DatabaseFairing::with_test_transaction] share the connection,
+/// their [crate::Tx] transactions become savepoints.
+pub struct TestTransaction(Arc<Mutex<Option<PooledConnection>>>);
+
+impl TestTransaction {
+    /// Retrieves a connection from the [DbPool] and begins the transaction.
+    ///
+    /// # Panics
+    /// Panics if no connection could be retrieved or the transaction could not be started.
+    pub async fn begin() -> Self {
+        EnvLoader::load_test();
+        let mut conn = DbPool::get_or_init_conn().await.expect("Failed to get connection");
+        conn.begin_test_transaction().await.expect("Failed to begin test transaction");
+        TestTransaction(Arc::new(Mutex::new(Some(conn))))
+    }
+
+    /// Returns the connection of the transaction.
+    ///
+    /// Waits until no request of the rocket under test uses it.
+    pub async fn conn(&self) -> DbConn {
+        DbConn(self.0.clone().lock_owned().await)
+    }
+
+    pub(crate) fn connections(&self) -> TestConnections {
+        TestConnections::Transaction(self.0.clone())
+    }
+}
+
+impl Drop for TestTransaction {
+    fn drop(&mut self) {
+        if let Some(conn) = self.0.try_lock().ok().and_then(|mut conn| conn.take()) {
+            // Closing the connection rolls back the transaction.
+            drop(Object::take(conn));
+        }
+    }
+}
+
+/// Freshly created database, dropped again when this is dropped.
+///
+/// The database is cloned from a template, which is created once per set of migrations and reused by
+/// later test runs. Templates are identified by the names of the migrations, so changing an applied
+/// migration requires dropping the `*_ferrox_template_*` databases.
+pub struct TestDatabase {
+    name: String,
+    url: String,
+    admin_url: String,
+    pool: Pool<AsyncPgConnection>,
+}
+
+impl TestDatabase {
+    /// Creates a new database with all `migrations` applied.
+    ///
+    /// # Panics
+    /// Panics if the database could not be created.
+    pub fn create(migrations: &EmbeddedMigrations) -> Self {
+        EnvLoader::load_test();
+        let admin_url = std::env::var("DATABASE_URL").expect("No DATABASE_URL found");
+        let base = database_name(&admin_url);
+        let template = template_name(&base, migrations).expect("Failed to read migrations");
+
+        let name = format!("{}_test_{}_{}", base, std::process::id(), DATABASE_COUNTER.fetch_add(1, Ordering::SeqCst));
+        let mut admin = PgConnection::establish(&admin_url).expect("Failed to connect to database");
+        with_session_migration_lock(&mut admin, |admin| {
+            let mut templates = TEMPLATES.lock().unwrap();
+            let templates = templates.get_or_insert_with(HashSet::new);
+            if !templates.contains(&template) {
+                create_template(admin, &admin_url, &template, migrations)?;
+                templates.insert(template.clone());
+            }
+
+            drop_database(admin, &name)?;
+            diesel::sql_query(format!("CREATE DATABASE \"{}\" TEMPLATE \"{}\"", name, template)).execute(admin)?;
+            Ok(())
+        }).expect("Failed to create test database");
+
+        let url = with_database(&admin_url, &name);
+        let manager = AsyncDieselConnectionManager::<AsyncPgConnection>::new(url.clone());
+        let pool = Pool::builder(manager).build().expect("Failed to create deadpool");
+
+        TestDatabase {
+            name,
+            url,
+            admin_url,
+            pool,
+        }
+    }
+
+    /// Returns the url of this database.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Retrieves a [PooledConnection] to this database.
+    pub async fn get_conn(&self) -> Result<PooledConnection, DbPoolError> {
+        self.pool.get().await
+    }
+
+    pub(crate) fn connections(&self) -> TestConnections {
+        TestConnections::Database(self.pool.clone())
+    }
+}
+
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        self.pool.close();
+        let name = self.name.clone();
+        let admin_url = self.admin_url.clone();
+
+        // Dropping happens on a separate thread, as this might run inside of the async runtime.
+        let result = std::thread::spawn(move || {
+            let mut admin = PgConnection::establish(&admin_url)?;
+            drop_database(&mut admin, &name)
+        }).join();
+
+        if !matches!(result, Ok(Ok(()))) {
+            eprintln!("Failed to drop test database {}", self.name);
+        }
+    }
+}
+
+fn drop_database(conn: &mut PgConnection, name: &str) -> Result<(), MigrationError> {
+    diesel::sql_query(format!("DROP DATABASE IF EXISTS \"{}\" WITH (FORCE)", name)).execute(conn)?;
+    Ok(())
+}
+
+#[derive(QueryableByName)]
+struct Exists {
+    #[diesel(sql_type = diesel::sql_types::Bool)]
+    exists: bool,
+}
+
+/// Returns the name of the template of `migrations`, derived from the names of all migrations.
+fn template_name(base: &str, migrations: &EmbeddedMigrations) -> Result<String, MigrationError> {
+    let mut hasher = DefaultHasher::new();
+    for migration in all_migrations(migrations)? {
+        migration.name().to_string().hash(&mut hasher);
+    }
+
+    Ok(format!("{}_ferrox_template_{:016x}", base, hasher.finish()))
+}
+
+/// Creates the template database `name` with all `migrations` applied, unless it already exists.
+///
+/// The template is migrated under another name and renamed afterwards, so a failed migration never leaves
+/// a template behind which later runs would reuse.
+fn create_template(admin: &mut PgConnection, admin_url: &str, name: &str, migrations: &EmbeddedMigrations) -> Result<(), MigrationError> {
+    let exists = diesel::sql_query("SELECT EXISTS (SELECT 1 FROM pg_database WHERE datname = $1) AS exists")
+        .bind::<Text, _>(name)
+        .get_result::<Exists>(admin)?;
+    if exists.exists {
+        return Ok(());
+    }
+
+    let building = format!("{}_building", name);
+    drop_database(admin, &building)?;
+    diesel::sql_query(format!("CREATE DATABASE \"{}\"", building)).execute(admin)?;
+
+    let mut conn = PgConnection::establish(&with_database(admin_url, &building))?;
+    run_pending_migrations(&mut conn, migrations)?;
+    drop(conn);
+
+    diesel::sql_query(format!("ALTER DATABASE \"{}\" RENAME TO \"{}\"", building, name)).execute(admin)?;
+    Ok(())
+}
+
+/// Returns the name of the database of `url`.
+fn database_name(url: &str) -> String {
+    let path = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = path.split_once('/').map_or("", |(_, path)| path);
+    path.split('?').next().unwrap_or_default().to_string()
+}
+
+/// Replaces the database of `url` with `name`.
+fn with_database(url: &str, name: &str) -> String {
+    let current = database_name(url);
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let (host, query) = match rest.split_once('/') {
+        Some((host, path)) => (host, path.strip_prefix(current.as_str()).unwrap_or(path)),
+        None => (rest, ""),
+    };
+
+    format!("{}://{}/{}{}", scheme, host, name, query)
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::dsl::sql;
+    use diesel::sql_types::{Bool, Integer, Text};
+    use diesel_async::{RunQueryDsl, SimpleAsyncConnection};
+    use diesel_migrations::EmbeddedMigrations;
+    use rocket::local::asynchronous::Client;
+    use rocket::{get, routes};
+    use crate::testing::{database_name, with_database, TestDatabase, TestTransaction};
+    use crate::{DatabaseFairing, DbConn, Tx};
+
+    #[test]
+    fn test_with_database() {
+        assert_eq!(database_name("postgres://user:pw@host:5432/db?sslmode=require"), "db");
+        assert_eq!(with_database("postgres://user:pw@host:5432/db?sslmode=require", "other"), "postgres://user:pw@host:5432/other?sslmode=require");
+        assert_eq!(with_database("postgres://host/db", "other"), "postgres://host/other");
+    }
+
+    #[rocket::async_test]
+    async fn test_isolation() {
+        let tx = TestTransaction::begin().await;
+        tx.conn().await.batch_execute("CREATE TABLE ferrox_isolation_test (id INT)").await.unwrap();
+        drop(tx);
+
+        let database = TestDatabase::create(&EmbeddedMigrations::new(&[]));
+        let mut conn = database.get_conn().await.unwrap();
+        conn.batch_execute("CREATE TABLE ferrox_isolation_test (id INT)").await.unwrap();
+        let result = diesel::select(sql::<Text>("current_database()")).load::<String>(&mut conn).await.unwrap();
+        assert_eq!(result[0], database.url().rsplit('/').next().unwrap());
+        drop(conn);
+        drop(database);
+
+        let tx = TestTransaction::begin().await;
+        let exists = diesel::select(sql::<Bool>("EXISTS (SELECT 1 FROM pg_tables WHERE tablename = 'ferrox_isolation_test')"))
+            .load::<bool>(&mut *tx.conn().await).await.unwrap();
+        assert!(!exists[0]);
+    }
+
+    #[get("/database")]
+    async fn database(mut conn: DbConn) -> String {
+        diesel::select(sql::<Text>("current_database()")).get_result::<String>(&mut *conn).await.unwrap()
+    }
+
+    #[get("/insert/<id>")]
+    async fn insert(mut tx: Tx, id: i32) {
+        diesel::sql_query("INSERT INTO ferrox_fairing_test VALUES ($1)").bind::<Integer, _>(id).execute(&mut *tx).await.unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn test_fairing() {
+        let database = TestDatabase::create(&EmbeddedMigrations::new(&[]));
+        let rocket = rocket::build().attach(DatabaseFairing::default().with_test_database(&database)).mount("/", routes![database]);
+        let client = Client::untracked(rocket).await.unwrap();
+        let response = client.get("/database").dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), database.url().rsplit('/').next().unwrap());
+
+        let tx = TestTransaction::begin().await;
+        tx.conn().await.batch_execute("CREATE TABLE ferrox_fairing_test (id INT)").await.unwrap();
+        let rocket = rocket::build().attach(DatabaseFairing::default().with_test_transaction(&tx)).mount("/", routes![insert]);
+        let client = Client::untracked(rocket).await.unwrap();
+        client.get("/insert/1").dispatch().await;
+        let count = diesel::select(sql::<Bool>("EXISTS (SELECT 1 FROM ferrox_fairing_test WHERE id = 1)"))
+            .get_result::<bool>(&mut *tx.conn().await).await.unwrap();
+        assert!(count);
+        drop(tx);
+
+        let tx = TestTransaction::begin().await;
+        let exists = diesel::select(sql::<Bool>("EXISTS (SELECT 1 FROM pg_tables WHERE tablename = 'ferrox_fairing_test')"))
+            .get_result::<bool>(&mut *tx.conn().await).await.unwrap();
+        assert!(!exists);
+    }
+}
@@ -0,0 +1,115 @@
+//! Contains [Timestamps], which maintains the `created_at` and `updated_at` columns of tables through a trigger.
+
+use diesel::connection::SimpleConnection;
+use diesel_async::async_connection_wrapper::AsyncConnectionWrapper;
+use diesel_async::SimpleAsyncConnection;
+use rocket::tokio;
+use crate::migrations::with_migration_lock;
+use crate::{MigrationError, PooledConnection};
+
+const CREATE_FUNCTION: &str = "
+    CREATE OR REPLACE FUNCTION ferrox_set_timestamps() RETURNS trigger AS $$
+    BEGIN
+        IF TG_OP = 'INSERT' THEN
+            NEW.created_at := now();
+            NEW.updated_at := NEW.created_at;
+        ELSE
+            NEW.created_at := OLD.created_at;
+            NEW.updated_at := OLD.updated_at;
+            IF NEW IS DISTINCT FROM OLD THEN
+                NEW.updated_at := now();
+            END IF;
+        END IF;
+        RETURN NEW;
+    END;
+    $$ LANGUAGE plpgsql;
+";
+
+/// Maintains the `created_at` and `updated_at` columns of tables.
+///
+/// Both columns are set on insert, `updated_at` is set on every update changing the row.
+/// Values written by the application are ignored, so both can be left out of inserts and changesets.
+pub struct Timestamps;
+
+impl Timestamps {
+    /// Installs the trigger maintaining the timestamps of `table`, replacing an existing one.
+    ///
+    /// The table needs `created_at` and `updated_at` columns of type `TIMESTAMPTZ`.
+    /// Tables can also be given to [crate::DatabaseFairing::with_timestamps] to install the trigger at startup
+    /// while holding the migration lock.
+    pub async fn manage(table: &str, conn: &mut PooledConnection) -> diesel::QueryResult<()> {
+        conn.batch_execute(&manage_sql(table)).await
+    }
+
+    /// Installs the triggers of `tables` while holding the migration lock, so starting instances do not race.
+    pub(crate) async fn manage_locked(conn: PooledConnection, tables: Vec<String>) -> Result<(), MigrationError> {
+        tokio::task::spawn_blocking(move || {
+            let mut conn = AsyncConnectionWrapper::<PooledConnection>::from(conn);
+            with_migration_lock(&mut conn, |conn| {
+                for table in &tables {
+                    conn.batch_execute(&manage_sql(table))?;
+                }
+
+                Ok(())
+            })
+        }).await?
+    }
+}
+
+/// Returns the statements installing the trigger of `table`.
+fn manage_sql(table: &str) -> String {
+    let table = format!("\"{}\"", table.replace('"', "\"\""));
+    format!("
+        {CREATE_FUNCTION}
+        DROP TRIGGER IF EXISTS ferrox_timestamps ON {table};
+        CREATE TRIGGER ferrox_timestamps BEFORE INSERT OR UPDATE ON {table} FOR EACH ROW EXECUTE FUNCTION ferrox_set_timestamps();
+    ")
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::sql_types::{Bool, Integer};
+    use diesel::QueryableByName;
+    use diesel_async::{RunQueryDsl, SimpleAsyncConnection};
+    use rocket::async_test;
+    use ferrox_env::EnvLoader;
+    use crate::{DbPool, Timestamps};
+
+    #[derive(QueryableByName)]
+    struct Row {
+        #[diesel(sql_type = Integer)]
+        id: i32,
+        #[diesel(sql_type = Bool)]
+        updated: bool,
+    }
+
+    #[async_test]
+    async fn test_timestamps() {
+        EnvLoader::load_test();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        conn.batch_execute("
+            CREATE TABLE IF NOT EXISTS ferrox_timestamps_test (id INT PRIMARY KEY, name TEXT NOT NULL, created_at TIMESTAMPTZ NOT NULL, updated_at TIMESTAMPTZ NOT NULL);
+            DELETE FROM ferrox_timestamps_test;
+        ").await.unwrap();
+        Timestamps::manage("ferrox_timestamps_test", &mut conn).await.unwrap();
+        Timestamps::manage_locked(DbPool::get_conn().await.unwrap(), vec!["ferrox_timestamps_test".to_string()]).await.unwrap();
+
+        // Separate transactions, as now() returns the start of the transaction
+        conn.batch_execute("
+            INSERT INTO ferrox_timestamps_test (id, name, created_at, updated_at) VALUES (1, 'a', '2000-01-01', '2000-01-01'), (2, 'b', '2000-01-01', '2000-01-01');
+        ").await.unwrap();
+        conn.batch_execute("
+            UPDATE ferrox_timestamps_test SET name = 'c', created_at = '2000-01-01' WHERE id = 1;
+            UPDATE ferrox_timestamps_test SET name = 'b', updated_at = '2000-01-01' WHERE id = 2;
+        ").await.unwrap();
+
+        let rows = diesel::sql_query("
+            SELECT id, updated_at > created_at AS updated FROM ferrox_timestamps_test
+            WHERE created_at > '2000-01-01' ORDER BY id
+        ")
+            .load::<Row>(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(rows.iter().map(|row| (row.id, row.updated)).collect::<Vec<_>>(), vec![(1, true), (2, false)]);
+    }
+}
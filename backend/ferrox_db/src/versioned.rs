@@ -0,0 +1,181 @@
+//! Contains [Versioned] tables, which are updated with optimistic locking through [VersionedDsl].
+
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+
+use diesel::dsl::{sql, Filter};
+use diesel::expression::SqlLiteral;
+use diesel::query_builder::{AsChangeset, AsQuery, IntoUpdateTarget, UpdateStatement};
+use diesel::query_dsl::methods::FilterDsl;
+use diesel::sql_types::Integer;
+use diesel::associations::HasTable;
+use diesel::{Column, ExpressionMethods, OptionalExtension, Table};
+
+/// Table with an integer version column, which is incremented by every update through [VersionedDsl].
+///
+/// ```rust,ignore
+/// impl Versioned for users::table {
+///     type Version = users::version;
+/// }
+/// ```
+pub trait Versioned: Table {
+    /// Version column of the table.
+    type Version: Column<Table = Self, SqlType = Integer> + ExpressionMethods + Default;
+}
+
+type VersionCheck<T> = diesel::dsl::Eq<<T as Versioned>::Version, i32>;
+type VersionBump<T> = diesel::dsl::Eq<<T as Versioned>::Version, SqlLiteral<Integer>>;
+
+/// Errors of updates built by [VersionedDsl::update_versioned], see [CheckVersion].
+///
+/// Converts into the `DbError` of `ferrox_core`, which responds to conflicts with `409 Conflict`.
+#[derive(Debug)]
+pub enum VersionError {
+    /// The row was changed since its version was read, or it does not exist anymore.
+    Conflict,
+    /// The update failed.
+    Query(diesel::result::Error),
+}
+
+impl Display for VersionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VersionError::Conflict => write!(f, "The row was changed by another request"),
+            VersionError::Query(e) => write!(f, "Update failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for VersionError {}
+
+impl From<diesel::result::Error> for VersionError {
+    fn from(e: diesel::result::Error) -> Self {
+        VersionError::Query(e)
+    }
+}
+
+/// Extends update targets of [Versioned] tables, e.g. `users::table.find(id)`, with optimistic locking.
+///
+/// ```rust,ignore
+/// let user = users::table.find(id)
+///     .update_versioned(input.version, &input.changes)
+///     .get_result::<User>(&mut conn)
+///     .await
+///     .check_version()?;
+/// ```
+pub trait VersionedDsl: HasTable + Sized {
+    /// Filters the rows which still have `version`.
+    fn with_version(self, version: i32) -> Filter<Self, VersionCheck<Self::Table>>
+    where
+        Self::Table: Versioned,
+        Self: FilterDsl<VersionCheck<Self::Table>>,
+    {
+        FilterDsl::filter(self, <Self::Table as Versioned>::Version::default().eq(version))
+    }
+
+    /// Builds the update applying `changes` to the target if it still has `version`, incrementing it.
+    ///
+    /// The update affects no row if the version changed, see [CheckVersion].
+    #[allow(clippy::type_complexity)]
+    fn update_versioned<C, F, V>(self, version: i32, changes: C) -> UpdateStatement<Self::Table, F::WhereClause, V>
+    where
+        Self::Table: Versioned,
+        Self: FilterDsl<VersionCheck<Self::Table>, Output = F>,
+        F: IntoUpdateTarget<Table = Self::Table>,
+        (C, VersionBump<Self::Table>): AsChangeset<Target = Self::Table, Changeset = V>,
+        UpdateStatement<Self::Table, F::WhereClause, V>: AsQuery,
+    {
+        let column = <Self::Table as Versioned>::Version::default();
+        let bump = sql::<Integer>(&format!("\"{}\" + 1", <<Self::Table as Versioned>::Version as Column>::NAME));
+        diesel::update(self.with_version(version)).set((changes, column.eq(bump)))
+    }
+}
+
+impl<T: HasTable> VersionedDsl for T {}
+
+/// Maps the result of an update built by [VersionedDsl::update_versioned] to [VersionError].
+///
+/// Works with `get_result`, which fails with `NotFound`, and `execute`, which returns 0 affected rows on a conflict.
+pub trait CheckVersion<T> {
+    /// Returns [VersionError::Conflict] if the update affected no row.
+    fn check_version(self) -> Result<T, VersionError>;
+}
+
+impl<T: 'static> CheckVersion<T> for diesel::QueryResult<T> {
+    fn check_version(self) -> Result<T, VersionError> {
+        let result = self.optional()?.ok_or(VersionError::Conflict)?;
+
+        // A blanket impl overlaps with one for `usize`, so the row count of `execute` is detected here
+        match (&result as &dyn Any).downcast_ref::<usize>() {
+            Some(0) => Err(VersionError::Conflict),
+            _ => Ok(result),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::{ExpressionMethods, QueryDsl};
+    use diesel_async::{RunQueryDsl, SimpleAsyncConnection};
+    use rocket::async_test;
+    use ferrox_env::EnvLoader;
+    use crate::{CheckVersion, DbPool, Versioned, VersionedDsl, VersionError};
+
+    diesel::table! {
+        ferrox_versioned_test (id) {
+            id -> Integer,
+            name -> Text,
+            version -> Integer,
+        }
+    }
+
+    impl Versioned for ferrox_versioned_test::table {
+        type Version = ferrox_versioned_test::version;
+    }
+
+    #[async_test]
+    async fn test_update_versioned() {
+        EnvLoader::load_test();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        conn.batch_execute("
+            CREATE TABLE IF NOT EXISTS ferrox_versioned_test (id INT PRIMARY KEY, name TEXT NOT NULL, version INT NOT NULL);
+            DELETE FROM ferrox_versioned_test;
+            INSERT INTO ferrox_versioned_test VALUES (1, 'a', 1);
+        ").await.unwrap();
+
+        let updated = ferrox_versioned_test::table.find(1)
+            .update_versioned(1, ferrox_versioned_test::name.eq("b"))
+            .get_result::<(i32, String, i32)>(&mut conn)
+            .await
+            .check_version().unwrap();
+        assert_eq!(updated, (1, "b".to_string(), 2));
+
+        let stale = ferrox_versioned_test::table.find(1)
+            .update_versioned(1, ferrox_versioned_test::name.eq("c"))
+            .get_result::<(i32, String, i32)>(&mut conn)
+            .await
+            .check_version();
+        assert!(matches!(stale, Err(VersionError::Conflict)));
+
+        let missing = ferrox_versioned_test::table.find(2)
+            .update_versioned(1, ferrox_versioned_test::name.eq("c"))
+            .get_result::<(i32, String, i32)>(&mut conn)
+            .await
+            .check_version();
+        assert!(matches!(missing, Err(VersionError::Conflict)));
+
+        let executed = ferrox_versioned_test::table.find(1)
+            .update_versioned(2, ferrox_versioned_test::name.eq("c"))
+            .execute(&mut conn)
+            .await
+            .check_version();
+        assert!(matches!(executed, Ok(1)));
+
+        let stale = ferrox_versioned_test::table.find(1)
+            .update_versioned(2, ferrox_versioned_test::name.eq("d"))
+            .execute(&mut conn)
+            .await
+            .check_version();
+        assert!(matches!(stale, Err(VersionError::Conflict)));
+    }
+}
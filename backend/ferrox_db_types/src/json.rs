@@ -17,8 +17,11 @@ use serde_json::Value;
 /// Stores the given type as JSON in the database.
 ///
 /// This type will always parse the json from the database when loaded.
+///
+/// SQLite has no json type, so with the `sqlite` feature it can also be stored as [diesel::sql_types::Text].
 #[derive(Debug, FromSqlRow, AsExpression)]
 #[diesel(sql_type = diesel::sql_types::Json)]
+#[cfg_attr(feature = "sqlite", diesel(sql_type = diesel::sql_types::Text))]
 pub struct TypedJson<T: Serialize + for<'a> Deserialize<'a> + Debug>(pub T);
 
 impl<T: Serialize + for<'a> Deserialize<'a> + Debug> Deref for TypedJson<T> {
@@ -49,4 +52,20 @@ impl<B: Backend, T: Serialize + for<'a> Deserialize<'a> + Debug> deserialize::Fr
     fn from_sql(bytes: B::RawValue<'_>) -> deserialize::Result<Self> {
         <Value as deserialize::FromSql<Json, B>>::from_sql(bytes).map(|v| TypedJson(serde_json::from_value::<T>(v).unwrap()))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(feature = "sqlite")]
+impl<T: Serialize + for<'a> Deserialize<'a> + Debug> serialize::ToSql<diesel::sql_types::Text, diesel::sqlite::Sqlite> for TypedJson<T> {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, diesel::sqlite::Sqlite>) -> serialize::Result {
+        out.set_value(serde_json::to_string(&self.0)?);
+        Ok(IsNull::No)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl<T: Serialize + for<'a> Deserialize<'a> + Debug> deserialize::FromSql<diesel::sql_types::Text, diesel::sqlite::Sqlite> for TypedJson<T> {
+    fn from_sql(bytes: diesel::sqlite::SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+        let value = <String as deserialize::FromSql<diesel::sql_types::Text, diesel::sqlite::Sqlite>>::from_sql(bytes)?;
+        Ok(TypedJson(serde_json::from_str(&value)?))
+    }
+}
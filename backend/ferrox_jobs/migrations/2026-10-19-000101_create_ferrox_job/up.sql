@@ -0,0 +1,15 @@
+CREATE TABLE ferrox_job (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    payload JSON NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    max_attempts INT NOT NULL,
+    run_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    unique_key TEXT,
+    last_error TEXT,
+    locked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX ferrox_job_run_at ON ferrox_job (run_at) WHERE status = 'pending';
+CREATE UNIQUE INDEX ferrox_job_unique_key ON ferrox_job (name, unique_key) WHERE status IN ('pending', 'running');
@@ -0,0 +1,12 @@
+CREATE TABLE ferrox_outbox (
+    id UUID PRIMARY KEY,
+    topic TEXT NOT NULL,
+    payload JSON NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    delivered_at TIMESTAMPTZ,
+    failed_at TIMESTAMPTZ
+);
+CREATE INDEX ferrox_outbox_pending ON ferrox_outbox (next_attempt_at) WHERE delivered_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1,163 @@
+//! Contains the [OutboxHandler]s provided by this crate.
+
+use std::error::Error;
+
+use rocket::async_trait;
+use rocket::tokio::sync::broadcast;
+use crate::{OutboxEvent, OutboxHandler};
+
+/// Handler publishing events to in-process subscribers.
+///
+/// Keep a clone to [Self::subscribe] to the events. Events published while there are no subscribers are dropped.
+#[derive(Clone)]
+pub struct BusHandler {
+    sender: broadcast::Sender<OutboxEvent>,
+}
+
+impl BusHandler {
+    /// Creates a bus buffering up to `capacity` events for slow subscribers.
+    pub fn new(capacity: usize) -> Self {
+        BusHandler {
+            sender: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Subscribes to all events delivered to this handler.
+    pub fn subscribe(&self) -> broadcast::Receiver<OutboxEvent> {
+        self.sender.subscribe()
+    }
+}
+
+#[async_trait]
+impl OutboxHandler for BusHandler {
+    async fn handle(&self, event: &OutboxEvent) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Fails only if there are no subscribers
+        let _ = self.sender.send(event.clone());
+        Ok(())
+    }
+}
+
+/// Payload of events handled by [MailHandler].
+#[cfg(feature = "mailer")]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct OutboxMail {
+    /// Sender of the mail.
+    pub from: String,
+    /// Recipient of the mail.
+    pub to: String,
+    /// Subject of the mail.
+    pub subject: String,
+    /// Plain text body of the mail.
+    pub body: String,
+}
+
+/// Handler sending events with an [OutboxMail] payload through the [ferrox_mailer::Mailer].
+#[cfg(feature = "mailer")]
+pub struct MailHandler {
+    timeout: std::time::Duration,
+}
+
+#[cfg(feature = "mailer")]
+impl MailHandler {
+    /// Creates a handler sending through the configured [ferrox_mailer::Mailer].
+    pub fn new() -> Self {
+        MailHandler {
+            timeout: std::time::Duration::from_secs(30),
+        }
+    }
+
+    /// Sets after how long sending a mail is given up and the delivery counted as failed.
+    ///
+    /// The mail may still be sent afterwards, as the blocking send cannot be aborted. Defaults to 30 seconds.
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[cfg(feature = "mailer")]
+impl Default for MailHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "mailer")]
+#[async_trait]
+impl OutboxHandler for MailHandler {
+    async fn handle(&self, event: &OutboxEvent) -> Result<(), Box<dyn Error + Send + Sync>> {
+        use ferrox_mailer::lettre::message::header::ContentType;
+        use ferrox_mailer::lettre::{Message, Transport};
+
+        let mail = event.payload::<OutboxMail>()?;
+        let message = Message::builder()
+            .from(mail.from.parse()?)
+            .to(mail.to.parse()?)
+            .subject(mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body)?;
+
+        let sending = rocket::tokio::task::spawn_blocking(move || ferrox_mailer::Mailer::get_or_init().send(&message));
+        rocket::tokio::time::timeout(self.timeout, sending).await
+            .map_err(|_| format!("Sending the mail timed out after {:?}", self.timeout))???;
+        Ok(())
+    }
+}
+
+/// Handler posting the payload of events as JSON to a webhook.
+///
+/// The id of the event is sent as `Idempotency-Key` header and its topic as `X-Outbox-Topic` header.
+#[cfg(feature = "webhook")]
+pub struct WebhookHandler {
+    client: reqwest::Client,
+    url: String,
+    headers: Vec<(String, String)>,
+    timeout: std::time::Duration,
+}
+
+#[cfg(feature = "webhook")]
+impl WebhookHandler {
+    /// Creates a handler posting to `url`.
+    pub fn new(url: &str) -> Self {
+        WebhookHandler {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+            headers: vec![],
+            timeout: std::time::Duration::from_secs(10),
+        }
+    }
+
+    /// Sets after how long a request is aborted and the delivery counted as failed.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds a header sent with every request, e.g. for authorization.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+#[cfg(feature = "webhook")]
+#[async_trait]
+impl OutboxHandler for WebhookHandler {
+    async fn handle(&self, event: &OutboxEvent) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut request = self.client.post(&self.url)
+            .header("Content-Type", "application/json")
+            .header("Idempotency-Key", event.id.to_string())
+            .header("X-Outbox-Topic", &event.topic)
+            .timeout(self.timeout)
+            .body(event.payload.to_string());
+
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::fmt::Debug;
+
+use diesel::sql_types::{BigInt, Integer, Json, Nullable, Text, Timestamptz};
+use diesel::QueryableByName;
+use diesel_async::RunQueryDsl;
+use rocket::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use time::OffsetDateTime;
+use ferrox_db::PooledConnection;
+use ferrox_db_types::TypedJson;
+
+/// Background job executed by the workers of the [crate::JobFairing].
+///
+/// Jobs are stored as JSON in the `ferrox_job` table created by [crate::JOBS_MIGRATIONS] through [JobQueue::enqueue],
+/// so they survive restarts and are processed by exactly one worker of all instances.
+///
+/// Failed jobs are retried with exponential backoff until [Self::MAX_ATTEMPTS] is reached,
+/// afterward they are kept as dead jobs until requeued through [JobQueue::retry].
+#[async_trait]
+pub trait Job: Serialize + DeserializeOwned + Debug + Send + Sync + 'static {
+    /// Unique name of the job type, used to find the job implementation for stored jobs.
+    const JOB_NAME: &'static str;
+
+    /// Maximum attempts to run the job before it is dead.
+    const MAX_ATTEMPTS: i32 = 5;
+
+    /// Key deduplicating jobs of this type.
+    ///
+    /// A job is not enqueued while another pending or running job of the same type has the same key.
+    fn unique_key(&self) -> Option<String> {
+        None
+    }
+
+    /// Runs the job.
+    ///
+    /// The job is retried if this returns an error or panics.
+    async fn run(&self, conn: &mut PooledConnection) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// Job loaded from the `ferrox_job` table.
+#[derive(QueryableByName, Debug)]
+pub(crate) struct StoredJob {
+    #[diesel(sql_type = BigInt)]
+    pub(crate) id: i64,
+    #[diesel(sql_type = Text)]
+    pub(crate) name: String,
+    #[diesel(sql_type = Json)]
+    pub(crate) payload: serde_json::Value,
+    #[diesel(sql_type = Integer)]
+    pub(crate) attempts: i32,
+    #[diesel(sql_type = Integer)]
+    pub(crate) max_attempts: i32,
+}
+
+#[derive(QueryableByName)]
+struct JobId {
+    #[diesel(sql_type = BigInt)]
+    id: i64,
+}
+
+/// Provides access to the jobs stored in the `ferrox_job` table.
+pub struct JobQueue;
+
+impl JobQueue {
+    /// Enqueues `job` to run as soon as a worker is available.
+    ///
+    /// Returns the id of the job, or `None` if a job with the same [Job::unique_key] is already queued.
+    ///
+    /// Use a connection inside a transaction to enqueue the job only if the transaction commits.
+    pub async fn enqueue<T: Job>(job: T, conn: &mut PooledConnection) -> diesel::QueryResult<Option<i64>> {
+        Self::enqueue_at(job, OffsetDateTime::now_utc(), conn).await
+    }
+
+    /// Enqueues `job` to run at `run_at` or later.
+    ///
+    /// See [Self::enqueue].
+    pub async fn enqueue_at<T: Job>(job: T, run_at: OffsetDateTime, conn: &mut PooledConnection) -> diesel::QueryResult<Option<i64>> {
+        let unique_key = job.unique_key();
+        diesel::sql_query("
+            INSERT INTO ferrox_job (name, payload, max_attempts, run_at, unique_key) VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (name, unique_key) WHERE status IN ('pending', 'running') DO NOTHING
+            RETURNING id
+        ")
+            .bind::<Text, _>(T::JOB_NAME)
+            .bind::<Json, _>(TypedJson(job))
+            .bind::<Integer, _>(T::MAX_ATTEMPTS)
+            .bind::<Timestamptz, _>(run_at)
+            .bind::<Nullable<Text>, _>(unique_key)
+            .get_results::<JobId>(conn)
+            .await
+            .map(|ids| ids.into_iter().next().map(|job| job.id))
+    }
+
+    /// Requeues the dead job `id` to run again with all of its attempts.
+    ///
+    /// Returns `false` if there is no dead job with this id.
+    /// Fails if a job with the same [Job::unique_key] was enqueued in the meantime.
+    pub async fn retry(id: i64, conn: &mut PooledConnection) -> diesel::QueryResult<bool> {
+        diesel::sql_query("
+            UPDATE ferrox_job SET status = 'pending', attempts = 0, run_at = now(), locked_at = NULL
+            WHERE id = $1 AND status = 'dead'
+        ")
+            .bind::<BigInt, _>(id)
+            .execute(conn)
+            .await
+            .map(|count| count > 0)
+    }
+
+    /// Claims the next due job of one of `names` and marks it as running.
+    ///
+    /// Running jobs whose lock is older than `lock_timeout` are claimed again, as their worker is assumed to be gone.
+    /// Workers refresh the lock through [Self::heartbeat] while the job runs.
+    pub(crate) async fn claim(names: &[&'static str], lock_timeout: std::time::Duration, conn: &mut PooledConnection) -> diesel::QueryResult<Option<StoredJob>> {
+        diesel::sql_query("
+            UPDATE ferrox_job SET status = 'running', attempts = attempts + 1, locked_at = now()
+            WHERE id = (
+                SELECT id FROM ferrox_job
+                WHERE name = ANY($1) AND (
+                    (status = 'pending' AND run_at <= now())
+                    OR (status = 'running' AND locked_at < now() - make_interval(secs => $2))
+                )
+                ORDER BY run_at, id
+                FOR UPDATE SKIP LOCKED
+                LIMIT 1
+            )
+            RETURNING id, name, payload, attempts, max_attempts
+        ")
+            .bind::<diesel::sql_types::Array<Text>, _>(names)
+            .bind::<diesel::sql_types::Double, _>(lock_timeout.as_secs_f64())
+            .get_results::<StoredJob>(conn)
+            .await
+            .map(|jobs| jobs.into_iter().next())
+    }
+
+    /// Refreshes the lock of the running `job`, so it is not claimed again while its worker is alive.
+    pub(crate) async fn heartbeat(job: &StoredJob, conn: &mut PooledConnection) -> diesel::QueryResult<()> {
+        diesel::sql_query("UPDATE ferrox_job SET locked_at = now() WHERE id = $1 AND status = 'running' AND attempts = $2")
+            .bind::<BigInt, _>(job.id)
+            .bind::<Integer, _>(job.attempts)
+            .execute(conn)
+            .await
+            .map(|_| ())
+    }
+
+    /// Removes the successfully finished `job`, unless it was claimed again by another worker in the meantime.
+    pub(crate) async fn complete(job: &StoredJob, conn: &mut PooledConnection) -> diesel::QueryResult<()> {
+        diesel::sql_query("DELETE FROM ferrox_job WHERE id = $1 AND status = 'running' AND attempts = $2")
+            .bind::<BigInt, _>(job.id)
+            .bind::<Integer, _>(job.attempts)
+            .execute(conn)
+            .await
+            .map(|_| ())
+    }
+
+    /// Records the failure of `job` and schedules it again after `retry_in`, or marks it as dead if it has no attempts left.
+    ///
+    /// Does nothing if the job was claimed again by another worker in the meantime.
+    pub(crate) async fn fail(job: &StoredJob, error: &str, retry_in: std::time::Duration, conn: &mut PooledConnection) -> diesel::QueryResult<()> {
+        let status = if job.attempts >= job.max_attempts { "dead" } else { "pending" };
+        diesel::sql_query("
+            UPDATE ferrox_job SET status = $2, last_error = $3, locked_at = NULL, run_at = now() + make_interval(secs => $4)
+            WHERE id = $1 AND status = 'running' AND attempts = $5
+        ")
+            .bind::<BigInt, _>(job.id)
+            .bind::<Text, _>(status)
+            .bind::<Text, _>(error)
+            .bind::<diesel::sql_types::Double, _>(retry_in.as_secs_f64())
+            .bind::<Integer, _>(job.attempts)
+            .execute(conn)
+            .await
+            .map(|_| ())
+    }
+}
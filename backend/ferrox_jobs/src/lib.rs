@@ -0,0 +1,32 @@
+//! This crate contains a job queue and a transactional outbox stored in Postgres through [ferrox_db].
+//!
+//! Jobs implement [Job] and are enqueued through [JobQueue::enqueue].
+//! They are processed by the workers of the [JobFairing], which claim due jobs with `FOR UPDATE SKIP LOCKED`,
+//! so multiple instances of the application can share the queue.
+//!
+//! Events are written through [Outbox::publish] in the transaction of the business data
+//! and delivered to [OutboxHandler]s by the relay of the [OutboxFairing].
+//! Use the `mailer` and `webhook` features for the [MailHandler] and `WebhookHandler`.
+//!
+//! Tables used by this crate are created by [JOBS_MIGRATIONS].
+//!
+//! Requires the `postgres` feature (default).
+
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+
+#[cfg(not(feature = "postgres"))]
+compile_error!("ferrox_jobs requires the postgres feature");
+
+mod handlers;
+pub use handlers::*;
+mod job;
+pub use job::*;
+mod outbox;
+pub use outbox::*;
+mod worker;
+pub use worker::*;
+
+/// Migrations creating the tables of the [JobQueue] and the [Outbox].
+///
+/// Add them through `DatabaseFairing::with_migrations` next to the migrations of the application.
+pub const JOBS_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use diesel::sql_types::{Double, Integer, Json, Text, Timestamptz, Uuid as SqlUuid};
+use diesel::QueryableByName;
+use diesel_async::RunQueryDsl;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::sync::watch;
+use rocket::tokio::task::JoinHandle;
+use rocket::{async_trait, error, info, tokio, warn, Orbit, Rocket};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use ferrox_db::{DbPool, PooledConnection};
+
+/// Amount of events claimed at once by the relay.
+const BATCH_SIZE: i64 = 100;
+
+/// Event stored in the `ferrox_outbox` table created by [crate::JOBS_MIGRATIONS].
+#[derive(QueryableByName, Clone, Debug)]
+pub struct OutboxEvent {
+    /// Unique id of the event, handlers use it to detect repeated deliveries.
+    #[diesel(sql_type = SqlUuid)]
+    pub id: Uuid,
+    /// Topic the event was published to.
+    #[diesel(sql_type = Text)]
+    pub topic: String,
+    /// JSON payload of the event.
+    #[diesel(sql_type = Json)]
+    pub payload: serde_json::Value,
+    /// Amount of previous delivery attempts.
+    #[diesel(sql_type = Integer)]
+    pub attempts: i32,
+    /// When the event was published.
+    #[diesel(sql_type = Timestamptz)]
+    pub created_at: OffsetDateTime,
+}
+
+impl OutboxEvent {
+    /// Deserializes the payload into `T`.
+    pub fn payload<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        T::deserialize(&self.payload)
+    }
+}
+
+/// Handler receiving events of a topic from the relay of the [OutboxFairing].
+///
+/// Events are delivered at least once, so handlers have to tolerate repeated events, e.g. by their [OutboxEvent::id].
+#[async_trait]
+pub trait OutboxHandler: Send + Sync + 'static {
+    /// Handles `event`.
+    ///
+    /// The event is delivered again later if this returns an error or panics.
+    async fn handle(&self, event: &OutboxEvent) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+/// Provides access to the `ferrox_outbox` table.
+pub struct Outbox;
+
+impl Outbox {
+    /// Publishes `payload` to `topic` and returns the id of the event.
+    ///
+    /// Use the connection of the transaction writing the business data, e.g. [ferrox_db::Tx],
+    /// so the event is only delivered if the transaction commits.
+    pub async fn publish<T: Serialize>(topic: &str, payload: &T, conn: &mut PooledConnection) -> diesel::QueryResult<Uuid> {
+        let id = Uuid::new_v4();
+        Self::publish_with_id(id, topic, payload, conn).await?;
+        Ok(id)
+    }
+
+    /// Publishes `payload` to `topic` with the id `id`.
+    ///
+    /// Returns `false` if an event with this id was already published, so retried requests can derive
+    /// the id from their input to publish an event only once.
+    pub async fn publish_with_id<T: Serialize>(id: Uuid, topic: &str, payload: &T, conn: &mut PooledConnection) -> diesel::QueryResult<bool> {
+        let payload = serde_json::to_value(payload).map_err(|e| diesel::result::Error::SerializationError(Box::new(e)))?;
+        diesel::sql_query("INSERT INTO ferrox_outbox (id, topic, payload) VALUES ($1, $2, $3) ON CONFLICT (id) DO NOTHING")
+            .bind::<SqlUuid, _>(id)
+            .bind::<Text, _>(topic)
+            .bind::<Json, _>(payload)
+            .execute(conn)
+            .await
+            .map(|count| count > 0)
+    }
+}
+
+/// Configuration of the relay of the [OutboxFairing].
+pub(crate) struct RelayConfig {
+    handlers: HashMap<String, Vec<Arc<dyn OutboxHandler>>>,
+    poll_interval: Duration,
+    backoff: Duration,
+    max_attempts: i32,
+    retention: Duration,
+    lease: Duration,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        RelayConfig {
+            handlers: HashMap::new(),
+            poll_interval: Duration::from_secs(1),
+            backoff: Duration::from_secs(10),
+            max_attempts: 10,
+            retention: Duration::from_secs(7 * 24 * 60 * 60),
+            lease: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// Fairing running the relay delivering events of the [Outbox] to their handlers.
+///
+/// Only events of topics with handlers registered through [Self::with_handler] are delivered by this instance.
+/// Delivered events are kept for the retention to deduplicate [Outbox::publish_with_id].
+/// On shutdown, the relay finishes its current batch before Rocket stops, waiting at most for the grace period of Rocket.
+///
+/// Requires the [DbPool] to be configured and the [crate::JOBS_MIGRATIONS] to be applied.
+#[derive(Default)]
+pub struct OutboxFairing {
+    config: Mutex<Option<RelayConfig>>,
+    shutdown: Mutex<Option<watch::Sender<bool>>>,
+    relay: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl OutboxFairing {
+    fn config(&mut self) -> &mut RelayConfig {
+        self.config.get_mut().unwrap().get_or_insert_with(RelayConfig::default)
+    }
+
+    /// Delivers events of `topic` to `handler`.
+    ///
+    /// If multiple handlers are registered for a topic, the event is delivered to all of them again if one fails.
+    pub fn with_handler(mut self, topic: &str, handler: impl OutboxHandler) -> Self {
+        self.config().handlers.entry(topic.to_string()).or_default().push(Arc::new(handler));
+        self
+    }
+
+    /// Sets how often the relay checks for new events.
+    ///
+    /// Defaults to 1 second.
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.config().poll_interval = interval;
+        self
+    }
+
+    /// Sets the delay before the first retry of a failed delivery, doubled with every further attempt.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.config().backoff = backoff;
+        self
+    }
+
+    /// Sets the attempts after which the delivery of an event is given up.
+    ///
+    /// Defaults to 10.
+    pub fn with_max_attempts(mut self, max_attempts: i32) -> Self {
+        self.config().max_attempts = max_attempts;
+        self
+    }
+
+    /// Sets how long delivered and failed events are kept.
+    ///
+    /// Defaults to 7 days.
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.config().retention = retention;
+        self
+    }
+
+    /// Sets how long claimed events are reserved for this relay before other relays deliver them again.
+    ///
+    /// Events of a batch which were not delivered within the lease are left to the next batch. Defaults to 5 minutes.
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.config().lease = lease;
+        self
+    }
+}
+
+#[async_trait]
+impl Fairing for OutboxFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "ferrox-outbox",
+            kind: Kind::Liftoff | Kind::Shutdown,
+        }
+    }
+
+    async fn on_liftoff(&self, _rocket: &Rocket<Orbit>) {
+        let Some(config) = self.config.lock().unwrap().take() else {
+            return;
+        };
+        if config.handlers.is_empty() {
+            warn!("No outbox handlers registered, relay is not started");
+            return;
+        }
+
+        let (sender, receiver) = watch::channel(false);
+        *self.relay.lock().unwrap() = Some(tokio::spawn(relay(config, receiver)));
+        *self.shutdown.lock().unwrap() = Some(sender);
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        if let Some(sender) = self.shutdown.lock().unwrap().take() {
+            let _ = sender.send(true);
+        }
+
+        let Some(relay) = self.relay.lock().unwrap().take() else {
+            return;
+        };
+        let abort = relay.abort_handle();
+        let grace = Duration::from_secs(rocket.config().shutdown.grace.into());
+        match tokio::time::timeout(grace, relay).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("Outbox relay failed: {}", e),
+            Err(_) => {
+                warn!("Outbox relay did not finish within {:?}, its events are delivered again after the lease", grace);
+                abort.abort();
+            }
+        }
+    }
+}
+
+/// Delivers events until shutdown is signaled through `shutdown`, pruning old events once every hour.
+async fn relay(config: RelayConfig, mut shutdown: watch::Receiver<bool>) {
+    let mut prune = tokio::time::interval(Duration::from_secs(60 * 60));
+    while !*shutdown.borrow() {
+        if deliver_batch(&config).await == BATCH_SIZE as usize {
+            continue;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(config.poll_interval) => {}
+            _ = prune.tick() => match prune_events(config.retention).await {
+                Ok(count) => info!("Pruned {} outbox events", count),
+                Err(e) => error!("Failed to prune outbox events: {}", e),
+            },
+            _ = shutdown.changed() => {}
+        }
+    }
+}
+
+/// Claims the next batch of due events, delivers them and returns the amount of events claimed.
+///
+/// The events are claimed by moving their next attempt behind the lease, so no row stays locked during delivery.
+pub(crate) async fn deliver_batch(config: &RelayConfig) -> usize {
+    let deadline = Instant::now() + config.lease;
+    let mut events = match claim_events(config).await {
+        Ok(events) => events,
+        Err(e) => {
+            error!("Failed to claim outbox events: {}", e);
+            return 0;
+        }
+    };
+    events.sort_by_key(|claimed| claimed.event.created_at);
+
+    for ClaimedEvent { event, leased_until } in &events {
+        if Instant::now() >= deadline {
+            warn!("Lease of outbox events expired, leaving the rest of the batch to the next one");
+            break;
+        }
+
+        let result = match deliver(config, event).await {
+            Ok(()) => mark_delivered(event, *leased_until).await,
+            Err(e) => {
+                warn!("Delivery of outbox event {} ({}) failed on attempt {}: {}", event.id, event.topic, event.attempts + 1, e);
+                mark_failed(config, event, *leased_until, &e).await
+            }
+        };
+        match result {
+            Ok(true) => {}
+            Ok(false) => warn!("Lease of outbox event {} expired before its delivery was recorded", event.id),
+            Err(e) => error!("Failed to record delivery of outbox event {}: {}", event.id, e),
+        }
+    }
+
+    events.len()
+}
+
+/// Event claimed by the relay until `leased_until`.
+#[derive(QueryableByName)]
+struct ClaimedEvent {
+    #[diesel(embed)]
+    event: OutboxEvent,
+    /// `next_attempt_at` set by the claim, which changes once another relay claims the event again.
+    #[diesel(sql_type = Timestamptz)]
+    leased_until: OffsetDateTime,
+}
+
+/// Claims up to [BATCH_SIZE] due events for the lease of the relay.
+async fn claim_events(config: &RelayConfig) -> Result<Vec<ClaimedEvent>, String> {
+    let mut conn = DbPool::get_conn().await.map_err(|e| e.to_string())?;
+    let topics = config.handlers.keys().cloned().collect::<Vec<_>>();
+    diesel::sql_query("
+        UPDATE ferrox_outbox SET next_attempt_at = now() + make_interval(secs => $3)
+        WHERE id IN (
+            SELECT id FROM ferrox_outbox
+            WHERE topic = ANY($1) AND delivered_at IS NULL AND failed_at IS NULL AND next_attempt_at <= now()
+            ORDER BY created_at
+            FOR UPDATE SKIP LOCKED
+            LIMIT $2
+        )
+        RETURNING id, topic, payload, attempts, created_at, next_attempt_at AS leased_until
+    ")
+        .bind::<diesel::sql_types::Array<Text>, _>(&topics)
+        .bind::<diesel::sql_types::BigInt, _>(BATCH_SIZE)
+        .bind::<Double, _>(config.lease.as_secs_f64())
+        .load::<ClaimedEvent>(&mut conn)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Marks `event` as delivered.
+///
+/// Returns `false` if the lease of this relay ended and the event was claimed again.
+async fn mark_delivered(event: &OutboxEvent, leased_until: OffsetDateTime) -> Result<bool, String> {
+    let mut conn = DbPool::get_conn().await.map_err(|e| e.to_string())?;
+    diesel::sql_query("
+        UPDATE ferrox_outbox SET delivered_at = now(), attempts = attempts + 1
+        WHERE id = $1 AND next_attempt_at = $2 AND delivered_at IS NULL AND failed_at IS NULL
+    ")
+        .bind::<SqlUuid, _>(event.id)
+        .bind::<Timestamptz, _>(leased_until)
+        .execute(&mut conn)
+        .await
+        .map(|count| count > 0)
+        .map_err(|e| e.to_string())
+}
+
+/// Records the failed delivery of `event` and schedules it again, or marks it as failed if it has no attempts left.
+///
+/// Returns `false` if the lease of this relay ended and the event was claimed again.
+async fn mark_failed(config: &RelayConfig, event: &OutboxEvent, leased_until: OffsetDateTime, error: &str) -> Result<bool, String> {
+    let mut conn = DbPool::get_conn().await.map_err(|e| e.to_string())?;
+    let retry_in = config.backoff.saturating_mul(2u32.saturating_pow(event.attempts.max(0) as u32));
+    diesel::sql_query("
+        UPDATE ferrox_outbox SET attempts = $2, last_error = $3, next_attempt_at = now() + make_interval(secs => $4),
+            failed_at = CASE WHEN $2 >= $5 THEN now() END
+        WHERE id = $1 AND next_attempt_at = $6 AND delivered_at IS NULL AND failed_at IS NULL
+    ")
+        .bind::<SqlUuid, _>(event.id)
+        .bind::<Integer, _>(event.attempts + 1)
+        .bind::<Text, _>(error)
+        .bind::<Double, _>(retry_in.as_secs_f64())
+        .bind::<Integer, _>(config.max_attempts)
+        .bind::<Timestamptz, _>(leased_until)
+        .execute(&mut conn)
+        .await
+        .map(|count| count > 0)
+        .map_err(|e| e.to_string())
+}
+
+/// Delivers `event` to all handlers of its topic, each in its own task, so panics are reported as failures.
+async fn deliver(config: &RelayConfig, event: &OutboxEvent) -> Result<(), String> {
+    for handler in config.handlers.get(&event.topic).into_iter().flatten() {
+        let handler = handler.clone();
+        let event = event.clone();
+        match tokio::spawn(async move { handler.handle(&event).await.map_err(|e| e.to_string()) }).await {
+            Ok(result) => result?,
+            Err(e) if e.is_panic() => return Err("Outbox handler panicked".to_string()),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes delivered and failed events older than `retention`.
+async fn prune_events(retention: Duration) -> Result<usize, String> {
+    let mut conn = DbPool::get_conn().await.map_err(|e| e.to_string())?;
+    diesel::sql_query("
+        DELETE FROM ferrox_outbox
+        WHERE (delivered_at IS NOT NULL OR failed_at IS NOT NULL) AND created_at < now() - make_interval(secs => $1)
+    ")
+        .bind::<Double, _>(retention.as_secs_f64())
+        .execute(&mut conn)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use diesel::sql_types::{Text, Uuid as SqlUuid};
+    use diesel_async::RunQueryDsl;
+    use rocket::{async_test, async_trait};
+    use uuid::Uuid;
+    use ferrox_db::DbPool;
+    use ferrox_env::EnvLoader;
+    use crate::outbox::{claim_events, deliver_batch, mark_delivered};
+    use crate::{Outbox, OutboxEvent, OutboxFairing, OutboxHandler, JOBS_MIGRATIONS};
+
+    /// Fails on the first delivery and counts all deliveries.
+    struct FlakyHandler(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl OutboxHandler for FlakyHandler {
+        async fn handle(&self, event: &OutboxEvent) -> Result<(), Box<dyn Error + Send + Sync>> {
+            assert_eq!(event.payload::<String>()?, "created");
+            if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                return Err("unavailable".into());
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Takes a while to handle events.
+    struct SlowHandler;
+
+    #[async_trait]
+    impl OutboxHandler for SlowHandler {
+        async fn handle(&self, _event: &OutboxEvent) -> Result<(), Box<dyn Error + Send + Sync>> {
+            rocket::tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok(())
+        }
+    }
+
+    #[async_test]
+    async fn test_outbox() {
+        EnvLoader::load_test();
+        DbPool::run_migrations(vec![JOBS_MIGRATIONS]).await.unwrap();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        diesel::sql_query("DELETE FROM ferrox_outbox WHERE topic = $1").bind::<Text, _>("ferrox_test").execute(&mut conn).await.unwrap();
+
+        let id = Uuid::new_v4();
+        assert!(Outbox::publish_with_id(id, "ferrox_test", &"created", &mut conn).await.unwrap());
+        assert!(!Outbox::publish_with_id(id, "ferrox_test", &"created", &mut conn).await.unwrap());
+
+        let deliveries = Arc::new(AtomicUsize::new(0));
+        let fairing = OutboxFairing::default()
+            .with_handler("ferrox_test", FlakyHandler(deliveries.clone()))
+            .with_backoff(Duration::ZERO);
+        let config = fairing.config.lock().unwrap().take().unwrap();
+
+        assert_eq!(deliver_batch(&config).await, 1);
+        assert_eq!(deliver_batch(&config).await, 1);
+        assert_eq!(deliver_batch(&config).await, 0);
+        assert_eq!(AtomicUsize::load(&deliveries, Ordering::SeqCst), 2);
+    }
+
+    #[async_test]
+    async fn test_outbox_lease() {
+        EnvLoader::load_test();
+        DbPool::run_migrations(vec![JOBS_MIGRATIONS]).await.unwrap();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        diesel::sql_query("DELETE FROM ferrox_outbox WHERE topic = $1").bind::<Text, _>("ferrox_test_lease").execute(&mut conn).await.unwrap();
+        let id = Outbox::publish("ferrox_test_lease", &"created", &mut conn).await.unwrap();
+
+        let fairing = OutboxFairing::default().with_handler("ferrox_test_lease", SlowHandler);
+        let config = Arc::new(fairing.config.lock().unwrap().take().unwrap());
+        let relay = rocket::tokio::spawn({
+            let config = config.clone();
+            async move { deliver_batch(&config).await }
+        });
+
+        // While the event is delivered, its row is not locked and other relays skip it
+        rocket::tokio::time::sleep(Duration::from_millis(100)).await;
+        diesel::sql_query("SELECT id FROM ferrox_outbox WHERE id = $1 FOR UPDATE NOWAIT").bind::<SqlUuid, _>(id).execute(&mut conn).await.unwrap();
+        assert_eq!(deliver_batch(&config).await, 0);
+
+        assert_eq!(relay.await.unwrap(), 1);
+        let delivered = diesel::sql_query("SELECT id FROM ferrox_outbox WHERE id = $1 AND delivered_at IS NOT NULL").bind::<SqlUuid, _>(id).execute(&mut conn).await.unwrap();
+        assert_eq!(delivered, 1);
+    }
+
+    #[async_test]
+    async fn test_outbox_expired_lease() {
+        EnvLoader::load_test();
+        DbPool::run_migrations(vec![JOBS_MIGRATIONS]).await.unwrap();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        diesel::sql_query("DELETE FROM ferrox_outbox WHERE topic = $1").bind::<Text, _>("ferrox_test_expired").execute(&mut conn).await.unwrap();
+        let id = Outbox::publish("ferrox_test_expired", &"created", &mut conn).await.unwrap();
+
+        let fairing = OutboxFairing::default().with_handler("ferrox_test_expired", SlowHandler).with_lease(Duration::ZERO);
+        let config = fairing.config.lock().unwrap().take().unwrap();
+        let lost = claim_events(&config).await.unwrap().pop().unwrap();
+        let current = claim_events(&config).await.unwrap().pop().unwrap();
+        assert_eq!(lost.event.id, id);
+
+        // Only the relay holding the current lease records the delivery
+        assert!(!mark_delivered(&lost.event, lost.leased_until).await.unwrap());
+        assert!(mark_delivered(&current.event, current.leased_until).await.unwrap());
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::tokio::sync::watch;
+use rocket::tokio::task::JoinHandle;
+use rocket::{async_trait, error, tokio, warn, Orbit, Rocket};
+use serde_json::Value;
+use ferrox_db::{DbPool, PooledConnection};
+use crate::{Job, JobQueue, StoredJob};
+
+/// Runs stored jobs of one [Job] type.
+#[async_trait]
+trait JobRunner: Send + Sync {
+    async fn run(&self, payload: Value, conn: &mut PooledConnection) -> Result<(), String>;
+}
+
+struct TypedJobRunner<T: Job>(PhantomData<fn() -> T>);
+
+#[async_trait]
+impl<T: Job> JobRunner for TypedJobRunner<T> {
+    async fn run(&self, payload: Value, conn: &mut PooledConnection) -> Result<(), String> {
+        let job = serde_json::from_value::<T>(payload).map_err(|e| format!("Failed to deserialize job: {}", e))?;
+        job.run(conn).await.map_err(|e| e.to_string())
+    }
+}
+
+/// Configuration of the workers of the [JobFairing].
+pub(crate) struct WorkerConfig {
+    runners: HashMap<&'static str, Arc<dyn JobRunner>>,
+    concurrency: usize,
+    poll_interval: Duration,
+    backoff: Duration,
+    lock_timeout: Duration,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            runners: HashMap::new(),
+            concurrency: 1,
+            poll_interval: Duration::from_secs(1),
+            backoff: Duration::from_secs(10),
+            lock_timeout: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+impl WorkerConfig {
+    fn names(&self) -> Vec<&'static str> {
+        self.runners.keys().copied().collect()
+    }
+
+    /// Returns the delay before the next attempt after `attempts` failed attempts.
+    fn retry_in(&self, attempts: i32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1).max(0) as u32))
+    }
+}
+
+/// Fairing running the workers of the job queue inside the Rocket process.
+///
+/// Only jobs of types registered through [Self::with_job] are processed by this instance.
+/// On shutdown, workers finish their current job before Rocket stops, waiting at most for the grace period of Rocket.
+/// Jobs still running afterwards are claimed again once their lock times out.
+///
+/// Requires the [DbPool] to be configured and the [crate::JOBS_MIGRATIONS] to be applied.
+#[derive(Default)]
+pub struct JobFairing {
+    config: Mutex<Option<WorkerConfig>>,
+    shutdown: Mutex<Option<watch::Sender<bool>>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl JobFairing {
+    fn config(&mut self) -> &mut WorkerConfig {
+        self.config.get_mut().unwrap().get_or_insert_with(WorkerConfig::default)
+    }
+
+    /// Processes jobs of type `T`.
+    pub fn with_job<T: Job>(mut self) -> Self {
+        self.config().runners.insert(T::JOB_NAME, Arc::new(TypedJobRunner::<T>(PhantomData)));
+        self
+    }
+
+    /// Sets the amount of jobs run at the same time.
+    ///
+    /// Each running job holds two connections of the [DbPool], one passed to the job and one refreshing its lock,
+    /// so the pool has to allow more than twice this many connections besides those used by requests.
+    /// Defaults to 1.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.config().concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets how often idle workers check for due jobs.
+    ///
+    /// Defaults to 1 second.
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.config().poll_interval = interval;
+        self
+    }
+
+    /// Sets the delay before the first retry of a failed job, doubled with every further attempt.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn with_backoff(mut self, backoff: Duration) -> Self {
+        self.config().backoff = backoff;
+        self
+    }
+
+    /// Sets after how long a running job is claimed again, as its worker is assumed to be gone.
+    ///
+    /// Workers refresh the lock of their job every third of this timeout. Defaults to 30 minutes.
+    pub fn with_lock_timeout(mut self, timeout: Duration) -> Self {
+        self.config().lock_timeout = timeout;
+        self
+    }
+}
+
+#[async_trait]
+impl Fairing for JobFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "ferrox-jobs",
+            kind: Kind::Liftoff | Kind::Shutdown,
+        }
+    }
+
+    async fn on_liftoff(&self, _rocket: &Rocket<Orbit>) {
+        let Some(config) = self.config.lock().unwrap().take() else {
+            return;
+        };
+        if config.runners.is_empty() {
+            warn!("No jobs registered, job workers are not started");
+            return;
+        }
+
+        let config = Arc::new(config);
+        let (sender, receiver) = watch::channel(false);
+        let mut workers = self.workers.lock().unwrap();
+        for _ in 0..config.concurrency {
+            workers.push(tokio::spawn(work(config.clone(), receiver.clone())));
+        }
+        *self.shutdown.lock().unwrap() = Some(sender);
+    }
+
+    async fn on_shutdown(&self, rocket: &Rocket<Orbit>) {
+        if let Some(sender) = self.shutdown.lock().unwrap().take() {
+            let _ = sender.send(true);
+        }
+
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        let aborts = workers.iter().map(JoinHandle::abort_handle).collect::<Vec<_>>();
+        let grace = Duration::from_secs(rocket.config().shutdown.grace.into());
+        let finished = tokio::time::timeout(grace, async {
+            for worker in workers {
+                if let Err(e) = worker.await {
+                    error!("Job worker failed: {}", e);
+                }
+            }
+        });
+
+        if finished.await.is_err() {
+            warn!("Job workers did not finish within {:?}, their jobs are claimed again after the lock timeout", grace);
+            aborts.iter().for_each(|abort| abort.abort());
+        }
+    }
+}
+
+/// Runs due jobs until shutdown is signaled through `shutdown`.
+async fn work(config: Arc<WorkerConfig>, mut shutdown: watch::Receiver<bool>) {
+    let names = config.names();
+    while !*shutdown.borrow() {
+        if run_next(&config, &names).await {
+            continue;
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(config.poll_interval) => {}
+            _ = shutdown.changed() => {}
+        }
+    }
+}
+
+/// Claims and runs the next due job.
+///
+/// Returns whether a job was run.
+pub(crate) async fn run_next(config: &WorkerConfig, names: &[&'static str]) -> bool {
+    let mut conn = match DbPool::get_conn().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            error!("Failed to get connection for jobs: {}", e);
+            return false;
+        }
+    };
+
+    let job = match JobQueue::claim(names, config.lock_timeout, &mut conn).await {
+        Ok(Some(job)) => job,
+        Ok(None) => return false,
+        Err(e) => {
+            error!("Failed to claim job: {}", e);
+            return false;
+        }
+    };
+
+    let result = if job.attempts > job.max_attempts {
+        // Only happens if the workers running the job were lost on every attempt
+        Err("Job lock expired on the last attempt".to_string())
+    } else {
+        match DbPool::get_conn().await {
+            Ok(job_conn) => run_locked(config, &job, job_conn, &mut conn).await,
+            Err(e) => Err(format!("Failed to get connection for the job: {}", e)),
+        }
+    };
+
+    let finished = match result {
+        Ok(()) => JobQueue::complete(&job, &mut conn).await,
+        Err(e) => {
+            warn!("Job {} ({}) failed on attempt {}: {}", job.id, job.name, job.attempts, e);
+            JobQueue::fail(&job, &e, config.retry_in(job.attempts), &mut conn).await
+        }
+    };
+    if let Err(e) = finished {
+        error!("Failed to finish job {}: {}", job.id, e);
+    }
+
+    true
+}
+
+/// Runs `job` on `job_conn` while refreshing its lock through `conn` every third of the lock timeout.
+///
+/// Heartbeats are awaited outside of the `select!`, so `conn` is never left with a cancelled query.
+async fn run_locked(config: &WorkerConfig, job: &StoredJob, job_conn: PooledConnection, conn: &mut PooledConnection) -> Result<(), String> {
+    let mut running = std::pin::pin!(run_job(config, job, job_conn));
+    loop {
+        tokio::select! {
+            result = &mut running => return result,
+            _ = tokio::time::sleep(config.lock_timeout / 3) => {}
+        }
+
+        if let Err(e) = JobQueue::heartbeat(job, conn).await {
+            warn!("Failed to refresh the lock of job {}: {}", job.id, e);
+        }
+    }
+}
+
+/// Runs `job` in its own task, so panics are reported as failures.
+async fn run_job(config: &WorkerConfig, job: &StoredJob, mut conn: PooledConnection) -> Result<(), String> {
+    let Some(runner) = config.runners.get(job.name.as_str()).cloned() else {
+        return Err(format!("No job registered as {}", job.name));
+    };
+
+    let payload = job.payload.clone();
+    match tokio::spawn(async move { runner.run(payload, &mut conn).await }).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => Err("Job panicked".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use diesel::sql_types::{Integer, Text};
+    use diesel::QueryableByName;
+    use diesel_async::RunQueryDsl;
+    use rocket::async_test;
+    use serde::{Deserialize, Serialize};
+    use ferrox_db::{DbPool, PooledConnection};
+    use ferrox_env::EnvLoader;
+    use crate::worker::{run_next, WorkerConfig};
+    use crate::{Job, JobFairing, JobQueue, JOBS_MIGRATIONS};
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct FailingJob {
+        key: String,
+    }
+
+    #[rocket::async_trait]
+    impl Job for FailingJob {
+        const JOB_NAME: &'static str = "ferrox_test_failing";
+        const MAX_ATTEMPTS: i32 = 2;
+
+        fn unique_key(&self) -> Option<String> {
+            Some(self.key.clone())
+        }
+
+        async fn run(&self, _conn: &mut PooledConnection) -> Result<(), Box<dyn Error + Send + Sync>> {
+            Err("always fails".into())
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct SlowJob;
+
+    #[rocket::async_trait]
+    impl Job for SlowJob {
+        const JOB_NAME: &'static str = "ferrox_test_slow";
+
+        async fn run(&self, _conn: &mut PooledConnection) -> Result<(), Box<dyn Error + Send + Sync>> {
+            rocket::tokio::time::sleep(Duration::from_millis(600)).await;
+            Ok(())
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct ReclaimedJob;
+
+    #[rocket::async_trait]
+    impl Job for ReclaimedJob {
+        const JOB_NAME: &'static str = "ferrox_test_reclaimed";
+
+        async fn run(&self, _conn: &mut PooledConnection) -> Result<(), Box<dyn Error + Send + Sync>> {
+            Ok(())
+        }
+    }
+
+    #[derive(QueryableByName)]
+    struct JobState {
+        #[diesel(sql_type = Text)]
+        status: String,
+        #[diesel(sql_type = Integer)]
+        attempts: i32,
+    }
+
+    #[async_test]
+    async fn test_job_retries() {
+        EnvLoader::load_test();
+        DbPool::run_migrations(vec![JOBS_MIGRATIONS]).await.unwrap();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        diesel::sql_query("DELETE FROM ferrox_job WHERE name = $1").bind::<Text, _>(FailingJob::JOB_NAME).execute(&mut conn).await.unwrap();
+
+        let id = JobQueue::enqueue(FailingJob { key: "a".to_string() }, &mut conn).await.unwrap().unwrap();
+        assert!(JobQueue::enqueue(FailingJob { key: "a".to_string() }, &mut conn).await.unwrap().is_none());
+
+        let fairing = JobFairing::default().with_job::<FailingJob>().with_backoff(Duration::ZERO);
+        let config = fairing.config.lock().unwrap().take().unwrap();
+        let names = [FailingJob::JOB_NAME];
+        assert!(run_next(&config, &names).await);
+        assert!(run_next(&config, &names).await);
+        assert!(!run_next(&config, &names).await);
+
+        let state = diesel::sql_query("SELECT status, attempts FROM ferrox_job WHERE id = $1").bind::<diesel::sql_types::BigInt, _>(id).get_result::<JobState>(&mut conn).await.unwrap();
+        assert_eq!(state.status, "dead");
+        assert_eq!(state.attempts, 2);
+
+        assert!(JobQueue::retry(id, &mut conn).await.unwrap());
+        assert!(JobQueue::enqueue(FailingJob { key: "a".to_string() }, &mut conn).await.unwrap().is_none());
+        assert_eq!(WorkerConfig::default().retry_in(3), Duration::from_secs(40));
+    }
+
+    #[async_test]
+    async fn test_job_heartbeat() {
+        EnvLoader::load_test();
+        DbPool::run_migrations(vec![JOBS_MIGRATIONS]).await.unwrap();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        diesel::sql_query("DELETE FROM ferrox_job WHERE name = $1").bind::<Text, _>(SlowJob::JOB_NAME).execute(&mut conn).await.unwrap();
+        let id = JobQueue::enqueue(SlowJob, &mut conn).await.unwrap().unwrap();
+
+        let fairing = JobFairing::default().with_job::<SlowJob>().with_lock_timeout(Duration::from_millis(300));
+        let config = Arc::new(fairing.config.lock().unwrap().take().unwrap());
+        let names = [SlowJob::JOB_NAME];
+        let worker = rocket::tokio::spawn({
+            let config = config.clone();
+            async move { run_next(&config, &names).await }
+        });
+
+        // The job runs longer than the lock timeout, but the heartbeat keeps it locked
+        rocket::tokio::time::sleep(Duration::from_millis(450)).await;
+        assert!(JobQueue::claim(&names, config.lock_timeout, &mut conn).await.unwrap().is_none());
+
+        assert!(worker.await.unwrap());
+        let remaining = diesel::sql_query("SELECT status, attempts FROM ferrox_job WHERE id = $1").bind::<diesel::sql_types::BigInt, _>(id).get_results::<JobState>(&mut conn).await.unwrap();
+        assert!(remaining.is_empty());
+    }
+
+    #[async_test]
+    async fn test_job_reclaimed() {
+        EnvLoader::load_test();
+        DbPool::run_migrations(vec![JOBS_MIGRATIONS]).await.unwrap();
+        let mut conn = DbPool::get_or_init_conn().await.unwrap();
+        diesel::sql_query("DELETE FROM ferrox_job WHERE name = $1").bind::<Text, _>(ReclaimedJob::JOB_NAME).execute(&mut conn).await.unwrap();
+        let id = JobQueue::enqueue(ReclaimedJob, &mut conn).await.unwrap().unwrap();
+
+        // The lock of the first worker expires and another worker claims the job again
+        let names = [ReclaimedJob::JOB_NAME];
+        let lost = JobQueue::claim(&names, Duration::ZERO, &mut conn).await.unwrap().unwrap();
+        let current = JobQueue::claim(&names, Duration::ZERO, &mut conn).await.unwrap().unwrap();
+        assert_eq!(current.attempts, 2);
+
+        JobQueue::complete(&lost, &mut conn).await.unwrap();
+        JobQueue::fail(&lost, "lost", Duration::ZERO, &mut conn).await.unwrap();
+        let state = diesel::sql_query("SELECT status, attempts FROM ferrox_job WHERE id = $1").bind::<diesel::sql_types::BigInt, _>(id).get_result::<JobState>(&mut conn).await.unwrap();
+        assert_eq!(state.status, "running");
+        assert_eq!(state.attempts, 2);
+
+        JobQueue::complete(&current, &mut conn).await.unwrap();
+        let remaining = diesel::sql_query("SELECT status, attempts FROM ferrox_job WHERE id = $1").bind::<diesel::sql_types::BigInt, _>(id).get_results::<JobState>(&mut conn).await.unwrap();
+        assert!(remaining.is_empty());
+    }
+}
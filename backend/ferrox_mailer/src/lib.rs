@@ -34,7 +34,7 @@ static MAILER: OnceLock<Mailer> = OnceLock::new();
 
 fn init_mailer() -> Mailer {
     let transport = SmtpTransport::from_url(
-        &*std::env::var("MAILER_DSN").expect("No MAILER_DSN provided"),
+        &std::env::var("MAILER_DSN").expect("No MAILER_DSN provided"),
     ).unwrap().build();
 
     match transport.test_connection() {
@@ -4,6 +4,8 @@
 
 use std::sync::Mutex;
 
+pub use sentry;
+
 use rocket::fairing::{Fairing, Info, Kind};
 use rocket::{async_trait, info, Build, Rocket};
 use sentry::{release_name, ClientInitGuard, ClientOptions};
@@ -16,6 +18,7 @@ pub struct FerroxSentryFairing {
 impl FerroxSentryFairing {
     /// Initializes this [Fairing].
     #[must_use]
+    #[allow(clippy::new_ret_no_self)]
     pub fn new() -> impl Fairing {
         FerroxSentryFairing {
             guard: Mutex::new(None),
@@ -49,7 +52,7 @@ impl Fairing for FerroxSentryFairing {
     async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
         match std::env::var("SENTRY_DSN") {
             Ok(dsn) => {
-                self.init(&*dsn);
+                self.init(&dsn);
             }
             Err(_) => {
                 info!("No sentry dsn provided, disabled sentry");